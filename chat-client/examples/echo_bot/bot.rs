@@ -0,0 +1,59 @@
+//! How the bot answers chat messages, apart from `main` so that the tests
+//! can run it against a server of their own.
+
+use std::{sync::Arc, time::Duration};
+
+use chat_client::{ChatClient, protocol::ChatMessage};
+use tokio::time;
+
+/// Answers `message` if it is a command for the bot. Reminders are sent
+/// later, from a task of their own.
+pub fn handle(client: &Arc<ChatClient>, message: &ChatMessage) -> chat_client::Result<()> {
+    // Answering other bots, or ourselves, could go on forever.
+    if message.bot.is_some() {
+        return Ok(());
+    }
+
+    if let Some(text) = message.text.strip_prefix("!echo ") {
+        client.reply(message, text)?;
+    } else if let Some(request) = message.text.strip_prefix("!remind ") {
+        let Some((delay, text)) = parse_reminder(request) else {
+            return client.reply(
+                message,
+                "Usage: !remind <delay> <text>, e.g. !remind 10m stand-up",
+            );
+        };
+        let Some(author) = client.username(&message.from) else {
+            return Ok(());
+        };
+
+        client.react(message, "👍")?;
+        let client = client.clone();
+        let text = format!("@{} reminder: {}", author, text);
+        tokio::spawn(async move {
+            time::sleep(delay).await;
+            let _ = client.send_message(&text);
+        });
+    }
+
+    Ok(())
+}
+
+/// Splits `10m stand-up` into the delay and the text.
+pub fn parse_reminder(request: &str) -> Option<(Duration, &str)> {
+    let (delay, text) = request.trim().split_once(' ')?;
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+
+    let unit = match delay.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        _ => return None,
+    };
+    let amount: u64 = delay[..delay.len() - 1].parse().ok()?;
+
+    Some((Duration::from_secs(amount.checked_mul(unit)?), text))
+}
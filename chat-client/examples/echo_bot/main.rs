@@ -0,0 +1,47 @@
+//! A bot that repeats and reminds.
+//!
+//! ```text
+//! cargo run -p chat-client --example echo_bot -- 127.0.0.1:8080
+//! ```
+//!
+//! - `!echo <text>` replies with the text.
+//! - `!remind <delay> <text>` mentions the author with the text after the
+//!   delay, given as `30s`, `10m` or `2h`.
+
+use std::sync::Arc;
+
+use chat_client::{ChatClient, Event, protocol::ServerMessage};
+
+mod bot;
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:8080";
+
+const NAME: &str = "echo-bot";
+
+#[tokio::main]
+async fn main() -> chat_client::Result<()> {
+    let address = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+    let (client, mut events) = ChatClient::connect_bot(address, NAME).await?;
+    let client = Arc::new(client);
+
+    while let Some(event) = events.next().await {
+        match event {
+            Event::Server(ServerMessage::Chat(message)) => bot::handle(&client, &message)?,
+            Event::Server(_) => {}
+            Event::Reconnecting { attempt } => {
+                eprintln!("connection lost, reconnecting (attempt {})", attempt);
+            }
+            Event::Disconnected { reason } => {
+                eprintln!(
+                    "disconnected: {}",
+                    reason.as_deref().unwrap_or("connection lost")
+                );
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,432 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use common::{
+    protocol::{
+        CHUNK_SIZE, ChatMessage, ClientMessage, Encoding, ServerMessage, read_msg, write_msg,
+    },
+    uuid::Uid,
+};
+use sha2::{Digest, Sha256};
+use tokio::{
+    io,
+    net::{self, TcpStream, ToSocketAddrs, tcp::OwnedReadHalf},
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    time,
+};
+
+use crate::{
+    error::{Error, Result},
+    event::{Event, Events},
+};
+
+/// How to join a room.
+#[derive(Debug, Clone)]
+pub struct JoinOptions {
+    pub username: Arc<str>,
+    /// Join as a bot account. The server marks the bot's messages in
+    /// [`ChatMessage::bot`].
+    pub bot: bool,
+    /// The password of the username. The first join with a password
+    /// registers the name, and only joins with the same password are let in
+    /// under it afterwards.
+    pub password: Option<Arc<str>>,
+    /// Attempts to rejoin after the connection is lost; `0` gives up at once.
+    pub reconnect_attempts: u32,
+    /// Delay before the first attempt, doubled after every failed one.
+    pub reconnect_delay: Duration,
+}
+
+impl JoinOptions {
+    pub fn new(username: impl Into<Arc<str>>) -> Self {
+        Self {
+            username: username.into(),
+            bot: false,
+            password: None,
+            reconnect_attempts: 5,
+            reconnect_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// A participant connected to a room. Requests are queued and sent in order
+/// by a background task, which also rejoins the room when the connection is
+/// lost. Dropping the client leaves the room.
+pub struct ChatClient {
+    outgoing: UnboundedSender<ClientMessage>,
+    state: Arc<Mutex<State>>,
+    account: Option<Arc<str>>,
+}
+
+/// What the client knows about the room, kept up to date from the events.
+struct State {
+    uuid: Uid,
+    usernames: HashMap<Uid, Arc<str>>,
+}
+
+impl State {
+    fn from_join(accepted: &ServerMessage) -> Option<Self> {
+        let ServerMessage::JoinAccepted {
+            uuid, participants, ..
+        } = accepted
+        else {
+            return None;
+        };
+
+        let usernames = participants
+            .iter()
+            .map(|participant| (participant.uuid.clone(), participant.username.clone()))
+            .collect();
+        Some(Self {
+            uuid: uuid.clone(),
+            usernames,
+        })
+    }
+
+    fn update(&mut self, message: &ServerMessage) {
+        match message {
+            ServerMessage::UserJoined { uuid, username, .. }
+            | ServerMessage::UserRenamed { uuid, username } => {
+                self.usernames.insert(uuid.clone(), username.clone());
+            }
+            ServerMessage::UserLeft { uuid } => {
+                self.usernames.remove(uuid);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl ChatClient {
+    /// Joins the room at `address` as `username`.
+    pub async fn connect(address: impl ToSocketAddrs, username: &str) -> Result<(Self, Events)> {
+        Self::connect_with(address, JoinOptions::new(username)).await
+    }
+
+    /// Joins the room at `address` as a bot account.
+    pub async fn connect_bot(
+        address: impl ToSocketAddrs,
+        username: &str,
+    ) -> Result<(Self, Events)> {
+        let options = JoinOptions {
+            bot: true,
+            ..JoinOptions::new(username)
+        };
+        Self::connect_with(address, options).await
+    }
+
+    pub async fn connect_with(
+        address: impl ToSocketAddrs,
+        options: JoinOptions,
+    ) -> Result<(Self, Events)> {
+        // Resolved once, so that reconnecting does not depend on the resolver.
+        let addresses: Vec<_> = net::lookup_host(address).await?.collect();
+        let (socket, accepted) = join(&addresses, &options).await?;
+        let state = State::from_join(&accepted).ok_or(Error::UnexpectedReply)?;
+        let state = Arc::new(Mutex::new(state));
+
+        let (events, rx) = mpsc::unbounded_channel();
+        let _ = events.send(Event::Server(accepted));
+        let (outgoing, requests) = mpsc::unbounded_channel();
+        let account = options.password.as_ref().map(|_| options.username.clone());
+
+        let connection = Connection {
+            addresses,
+            options,
+            state: state.clone(),
+            events,
+            unsent: None,
+            display_name: None,
+        };
+        tokio::spawn(connection.run(socket, requests));
+
+        Ok((
+            Self {
+                outgoing,
+                state,
+                account,
+            },
+            Events { rx },
+        ))
+    }
+
+    /// The ID the server assigned to this participant. It changes when the
+    /// client rejoins after losing the connection.
+    pub fn uuid(&self) -> Uid {
+        self.state.lock().unwrap().uuid.clone()
+    }
+
+    /// The registered account the client joined as, if it gave a password.
+    /// Unlike the ID it stays the same across reconnects.
+    pub fn account(&self) -> Option<Arc<str>> {
+        self.account.clone()
+    }
+
+    /// Whether this participant sent `message`, in this session or, under
+    /// the same account, in an earlier one.
+    pub fn is_own(&self, message: &ChatMessage) -> bool {
+        message.from == self.uuid() || is_account(message, self.account.as_deref())
+    }
+
+    /// The current username of the participant `uuid`, if they are in the
+    /// room.
+    pub fn username(&self, uuid: &Uid) -> Option<Arc<str>> {
+        self.state.lock().unwrap().usernames.get(uuid).cloned()
+    }
+
+    /// Queues any request for the server. Fails only once the connection is
+    /// closed for good; requests made while reconnecting are sent after
+    /// rejoining.
+    pub fn send(&self, message: ClientMessage) -> Result<()> {
+        self.outgoing.send(message).map_err(|_| Error::Closed)
+    }
+
+    /// Posts `text` to the room. Slash commands are run by the server.
+    pub fn send_message(&self, text: &str) -> Result<()> {
+        self.send(ClientMessage::Chat {
+            text: text.into(),
+            reply_to: None,
+            attachments: Vec::new(),
+        })
+    }
+
+    /// Posts `text` as a reply to `message`.
+    pub fn reply(&self, message: &ChatMessage, text: &str) -> Result<()> {
+        self.send(ClientMessage::Chat {
+            text: text.into(),
+            reply_to: Some(message.id.clone()),
+            attachments: Vec::new(),
+        })
+    }
+
+    pub fn react(&self, message: &ChatMessage, emoji: &str) -> Result<()> {
+        self.send(ClientMessage::AddReaction {
+            message_id: message.id.clone(),
+            emoji: emoji.into(),
+        })
+    }
+
+    pub fn set_typing(&self, active: bool) -> Result<()> {
+        self.send(ClientMessage::Typing { active })
+    }
+
+    /// Uploads `data` in chunks. The server answers with `UploadComplete` or
+    /// `UploadFailed` for the returned upload ID.
+    pub fn upload(&self, name: &str, mime: &str, data: &[u8]) -> Result<Uid> {
+        let upload_id = Uid::new();
+
+        self.send(ClientMessage::BeginUpload {
+            upload_id: upload_id.clone(),
+            name: name.into(),
+            mime: mime.into(),
+            size: data.len() as u64,
+        })?;
+
+        let mut offset = 0;
+        for chunk in data.chunks(CHUNK_SIZE) {
+            self.send(ClientMessage::UploadChunk {
+                upload_id: upload_id.clone(),
+                offset,
+                data: chunk.to_vec(),
+            })?;
+            offset += chunk.len() as u64;
+        }
+
+        self.send(ClientMessage::FinishUpload {
+            upload_id: upload_id.clone(),
+            sha256: Sha256::digest(data).into(),
+        })?;
+
+        Ok(upload_id)
+    }
+}
+
+/// Whether `message` was sent under `account`. Account names are compared
+/// the way the server compares them, ignoring case.
+pub fn is_account(message: &ChatMessage, account: Option<&str>) -> bool {
+    match (&message.author, account) {
+        (Some(author), Some(account)) => author.to_lowercase() == account.to_lowercase(),
+        _ => false,
+    }
+}
+
+/// Connects and sends the join request, returning the socket and the
+/// server's `JoinAccepted`.
+async fn join(
+    addresses: &[SocketAddr],
+    options: &JoinOptions,
+) -> Result<(TcpStream, ServerMessage)> {
+    let mut socket = TcpStream::connect(addresses).await?;
+
+    let join = ClientMessage::JoinRequest {
+        username: options.username.clone(),
+        encoding: Encoding::Bincode,
+        bot: options.bot,
+        password: options.password.clone(),
+    };
+    write_msg(&mut socket, &join).await?;
+
+    match read_msg::<_, ServerMessage>(&mut socket).await? {
+        accepted @ ServerMessage::JoinAccepted { .. } => Ok((socket, accepted)),
+        ServerMessage::JoinRejected { reason } => Err(Error::JoinRejected { reason }),
+        _ => Err(Error::UnexpectedReply),
+    }
+}
+
+/// Why a session ended.
+enum End {
+    /// The client or the events were dropped.
+    Closed,
+    /// The server closed the connection on purpose.
+    Disconnected {
+        reason: Arc<str>,
+    },
+    Lost,
+}
+
+struct Connection {
+    addresses: Vec<SocketAddr>,
+    options: JoinOptions,
+    state: Arc<Mutex<State>>,
+    events: UnboundedSender<Event>,
+    /// A request whose write failed when the connection was lost, sent first
+    /// once the room is rejoined.
+    unsent: Option<ClientMessage>,
+    /// The name taken by renaming, restored after rejoining. The join itself
+    /// uses the name from the options, which is the account.
+    display_name: Option<Arc<str>>,
+}
+
+impl Connection {
+    /// Runs sessions until the connection is closed for good.
+    async fn run(mut self, mut socket: TcpStream, mut requests: UnboundedReceiver<ClientMessage>) {
+        loop {
+            match self.session(socket, &mut requests).await {
+                End::Closed => return,
+                End::Disconnected { reason } => {
+                    let _ = self.events.send(Event::Disconnected {
+                        reason: Some(reason),
+                    });
+                    return;
+                }
+                End::Lost => {}
+            }
+
+            socket = match self.reconnect().await {
+                Ok(socket) => socket,
+                Err(reason) => {
+                    let _ = self.events.send(Event::Disconnected { reason });
+                    return;
+                }
+            };
+        }
+    }
+
+    async fn session(
+        &mut self,
+        socket: TcpStream,
+        requests: &mut UnboundedReceiver<ClientMessage>,
+    ) -> End {
+        let (reader, mut writer) = socket.into_split();
+        if let Some(request) = self.unsent.take()
+            && write_msg(&mut writer, &request).await.is_err()
+        {
+            self.unsent = Some(request);
+            return End::Lost;
+        }
+
+        let (tx, mut incoming) = mpsc::unbounded_channel();
+        // Reading a frame is not cancel-safe, so it gets a task of its own.
+        let reading = tokio::spawn(read_messages(reader, tx));
+
+        let end = loop {
+            tokio::select! {
+                message = incoming.recv() => {
+                    let Some(message) = message else { break End::Lost };
+                    if let ServerMessage::Disconnected { reason } = message {
+                        break End::Disconnected { reason };
+                    }
+                    self.track(&message);
+                    if self.events.send(Event::Server(message)).is_err() {
+                        break End::Closed;
+                    }
+                }
+                request = requests.recv() => {
+                    let Some(request) = request else { break End::Closed };
+                    if write_msg(&mut writer, &request).await.is_err() {
+                        self.unsent = Some(request);
+                        break End::Lost;
+                    }
+                }
+            }
+        };
+
+        reading.abort();
+        end
+    }
+
+    /// Applies `message` to the state, remembering renames to restore after
+    /// rejoining.
+    fn track(&mut self, message: &ServerMessage) {
+        let mut state = self.state.lock().unwrap();
+        if let ServerMessage::UserRenamed { uuid, username } = message
+            && *uuid == state.uuid
+        {
+            self.display_name = (*username != self.options.username).then(|| username.clone());
+        }
+        state.update(message);
+    }
+
+    /// Rejoins with exponential backoff, under the account and then renamed
+    /// to the display name. Fails with the server's reason if it refuses the
+    /// join, or with none once every attempt has failed.
+    async fn reconnect(&self) -> core::result::Result<TcpStream, Option<Arc<str>>> {
+        let mut delay = self.options.reconnect_delay;
+
+        for attempt in 1..=self.options.reconnect_attempts {
+            if self.events.send(Event::Reconnecting { attempt }).is_err() {
+                return Err(None);
+            }
+            time::sleep(delay).await;
+            delay = delay.saturating_mul(2);
+
+            match join(&self.addresses, &self.options).await {
+                Ok((mut socket, accepted)) => {
+                    if let Some(username) = &self.display_name {
+                        let rename = ClientMessage::Rename {
+                            username: username.clone(),
+                        };
+                        if write_msg(&mut socket, &rename).await.is_err() {
+                            continue;
+                        }
+                    }
+                    if let Some(state) = State::from_join(&accepted) {
+                        *self.state.lock().unwrap() = state;
+                    }
+                    let _ = self.events.send(Event::Server(accepted));
+                    return Ok(socket);
+                }
+                Err(Error::JoinRejected { reason }) => return Err(Some(reason)),
+                Err(_) => {}
+            }
+        }
+
+        Err(None)
+    }
+}
+
+async fn read_messages(
+    mut reader: OwnedReadHalf,
+    tx: UnboundedSender<ServerMessage>,
+) -> io::Result<()> {
+    loop {
+        let message = read_msg::<_, ServerMessage>(&mut reader).await?;
+        if tx.send(message).is_err() {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use std::{fmt::Display, sync::Arc};
+
+use tokio::io;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Connecting to, reading from or writing to the server failed.
+    Io(io::Error),
+    JoinRejected {
+        reason: Arc<str>,
+    },
+    /// The server answered the join with something other than a verdict.
+    UnexpectedReply,
+    /// The connection is closed for good, so nothing more can be sent.
+    Closed,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
@@ -0,0 +1,43 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use common::protocol::ServerMessage;
+use futures_core::Stream;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A message from the server. The first is always `JoinAccepted`, and
+    /// another one follows every successful reconnect, describing the room
+    /// afresh.
+    Server(ServerMessage),
+    /// The connection was lost and the client is about to try rejoining.
+    Reconnecting { attempt: u32 },
+    /// The connection has ended for good, with the server's reason if it gave
+    /// one. No further events follow.
+    Disconnected { reason: Option<Arc<str>> },
+}
+
+/// The events of a [`ChatClient`](crate::ChatClient), in the order the server
+/// sent them. Also usable as a [`Stream`].
+pub struct Events {
+    pub(crate) rx: UnboundedReceiver<Event>,
+}
+
+impl Events {
+    /// Waits for the next event. Returns `None` after `Disconnected`.
+    pub async fn next(&mut self) -> Option<Event> {
+        self.rx.recv().await
+    }
+}
+
+impl Stream for Events {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.rx.poll_recv(cx)
+    }
+}
@@ -0,0 +1,16 @@
+//! An async client for the chat server, for bots and other programs that
+//! talk to it without the GUI.
+//!
+//! [`ChatClient::connect`] joins the room and returns the client, used to
+//! send, together with [`Events`], the stream of everything the server sends.
+//! A lost connection is rejoined in the background, as set in
+//! [`JoinOptions`].
+
+mod client;
+mod error;
+mod event;
+
+pub use client::{ChatClient, JoinOptions, is_account};
+pub use common::{protocol, uuid};
+pub use error::{Error, Result};
+pub use event::{Event, Events};
@@ -0,0 +1,702 @@
+//! Runs clients and bots against a server in the same process.
+
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chat_client::{
+    ChatClient, Error, Event, Events, JoinOptions,
+    protocol::{ChatMessage, ClientMessage, Role, ServerMessage},
+    uuid::Uid,
+};
+use server::{
+    server::{ChatRoom, blob_store::BlobStore, handle_connection, room_store::RoomStore},
+    webhooks::Webhooks,
+};
+use tokio::{net::TcpListener, task::JoinHandle, time};
+
+#[path = "../examples/echo_bot/bot.rs"]
+mod echo_bot;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+struct TestServer {
+    address: SocketAddr,
+    chat_room: Arc<ChatRoom>,
+    sessions: Arc<Mutex<Vec<JoinHandle<server::error::Result<()>>>>>,
+    /// Holds the room file and the blobs, and is deleted with the server.
+    dir: PathBuf,
+}
+
+impl TestServer {
+    /// Starts a server with a fresh room.
+    async fn start() -> Self {
+        let dir = std::env::temp_dir().join(format!("chat-client-test-{}", Uid::new().inner()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Real password hashing is slow on purpose, and slower still in
+        // debug builds.
+        let store = RoomStore::load(dir.join("room.json"))
+            .await
+            .unwrap()
+            .with_password_iterations(1);
+        let blobs = BlobStore::load(dir.join("blobs")).await.unwrap();
+        let chat_room = Arc::new(ChatRoom::new(
+            "general".into(),
+            store,
+            blobs,
+            Webhooks::default(),
+        ));
+        let sessions = Arc::new(Mutex::new(Vec::new()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let room = chat_room.clone();
+        let handles = sessions.clone();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                let session = tokio::spawn(handle_connection(socket, room.clone()));
+                handles.lock().unwrap().push(session);
+            }
+        });
+
+        Self {
+            address,
+            chat_room,
+            sessions,
+            dir,
+        }
+    }
+
+    /// Drops every connection without telling the clients.
+    fn cut_connections(&self) {
+        for session in self.sessions.lock().unwrap().drain(..) {
+            session.abort();
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+async fn next(events: &mut Events) -> Event {
+    time::timeout(TIMEOUT, events.next())
+        .await
+        .expect("event arrives in time")
+        .expect("events continue")
+}
+
+/// Skips events until the next chat message.
+async fn next_chat(events: &mut Events) -> Arc<ChatMessage> {
+    loop {
+        match next(events).await {
+            Event::Server(ServerMessage::Chat(message)) => return message,
+            Event::Server(_) => {}
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn join_accepted_comes_first() {
+    let server = TestServer::start().await;
+    let (alice, mut events) = ChatClient::connect(server.address, "alice").await.unwrap();
+
+    match next(&mut events).await {
+        Event::Server(ServerMessage::JoinAccepted { uuid, .. }) => assert_eq!(uuid, alice.uuid()),
+        other => panic!("unexpected event: {:?}", other),
+    }
+    assert_eq!(alice.username(&alice.uuid()).as_deref(), Some("alice"));
+}
+
+#[tokio::test]
+async fn bot_messages_are_marked() {
+    let server = TestServer::start().await;
+    let (alice, mut alice_events) = ChatClient::connect(server.address, "alice").await.unwrap();
+    let (bot, _bot_events) = ChatClient::connect_bot(server.address, "echo-bot")
+        .await
+        .unwrap();
+
+    alice.send_message("hello").unwrap();
+    let message = next_chat(&mut alice_events).await;
+    assert_eq!(&*message.text, "hello");
+    assert_eq!(message.bot, None);
+
+    bot.send_message("beep").unwrap();
+    let message = next_chat(&mut alice_events).await;
+    assert_eq!(&*message.text, "beep");
+    assert_eq!(message.from, bot.uuid());
+    assert_eq!(message.bot.as_deref(), Some("echo-bot"));
+}
+
+#[tokio::test]
+async fn echo_bot_replies() {
+    let server = TestServer::start().await;
+    let (alice, mut alice_events) = ChatClient::connect(server.address, "alice").await.unwrap();
+    start_echo_bot(&server).await;
+
+    alice.send_message("!echo ping").unwrap();
+    let request = next_chat(&mut alice_events).await;
+    let reply = next_chat(&mut alice_events).await;
+    assert_eq!(&*reply.text, "ping");
+    assert_eq!(reply.reply_to, Some(request.id.clone()));
+    assert!(reply.bot.is_some());
+}
+
+#[tokio::test]
+async fn echo_bot_reminds() {
+    let server = TestServer::start().await;
+    let (alice, mut alice_events) = ChatClient::connect(server.address, "alice").await.unwrap();
+    start_echo_bot(&server).await;
+
+    alice.send_message("!remind soon stand-up").unwrap();
+    let request = next_chat(&mut alice_events).await;
+    let usage = next_chat(&mut alice_events).await;
+    assert!(usage.text.starts_with("Usage: !remind"));
+    assert_eq!(usage.reply_to, Some(request.id.clone()));
+
+    alice.send_message("!remind 1s stand-up").unwrap();
+    next_chat(&mut alice_events).await;
+    let reminder = next_chat(&mut alice_events).await;
+    assert_eq!(&*reminder.text, "@alice reminder: stand-up");
+    assert_eq!(reminder.mentions, [alice.uuid()]);
+}
+
+#[test]
+fn reminders_are_parsed() {
+    use echo_bot::parse_reminder;
+
+    assert_eq!(
+        parse_reminder("10m stand-up"),
+        Some((Duration::from_secs(600), "stand-up"))
+    );
+    assert_eq!(
+        parse_reminder(" 2h  water the plants "),
+        Some((Duration::from_secs(7200), "water the plants"))
+    );
+    assert_eq!(
+        parse_reminder("30s x"),
+        Some((Duration::from_secs(30), "x"))
+    );
+    assert_eq!(parse_reminder("10m"), None);
+    assert_eq!(parse_reminder("10m   "), None);
+    assert_eq!(parse_reminder("10d stand-up"), None);
+    assert_eq!(parse_reminder("m stand-up"), None);
+    assert_eq!(parse_reminder("-5m stand-up"), None);
+    assert_eq!(parse_reminder("18446744073709551615h stand-up"), None);
+}
+
+/// Connects the example echo bot and runs it until the server goes away.
+async fn start_echo_bot(server: &TestServer) {
+    let (bot, mut bot_events) = ChatClient::connect_bot(server.address, "echo-bot")
+        .await
+        .unwrap();
+    let bot = Arc::new(bot);
+
+    tokio::spawn(async move {
+        while let Some(event) = bot_events.next().await {
+            if let Event::Server(ServerMessage::Chat(message)) = event {
+                echo_bot::handle(&bot, &message).unwrap();
+            }
+        }
+    });
+}
+
+#[tokio::test]
+async fn usernames_follow_the_room() {
+    let server = TestServer::start().await;
+    let (alice, mut alice_events) = ChatClient::connect(server.address, "alice").await.unwrap();
+    let (bob, _bob_events) = ChatClient::connect(server.address, "bob").await.unwrap();
+
+    bob.send_message("/nick robert").unwrap();
+    loop {
+        if let Event::Server(ServerMessage::UserRenamed { .. }) = next(&mut alice_events).await {
+            break;
+        }
+    }
+    assert_eq!(alice.username(&bob.uuid()).as_deref(), Some("robert"));
+
+    let bob_uuid = bob.uuid();
+    drop(bob);
+    loop {
+        if let Event::Server(ServerMessage::UserLeft { .. }) = next(&mut alice_events).await {
+            break;
+        }
+    }
+    assert_eq!(alice.username(&bob_uuid), None);
+}
+
+#[tokio::test]
+async fn disconnects_end_the_events() {
+    let server = TestServer::start().await;
+    let (_alice, mut events) = ChatClient::connect(server.address, "alice").await.unwrap();
+
+    server.chat_room.close("maintenance".into()).await.unwrap();
+    loop {
+        match next(&mut events).await {
+            Event::Disconnected { reason } => {
+                assert_eq!(reason.as_deref(), Some("maintenance"));
+                break;
+            }
+            Event::Server(_) => {}
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+    let end = time::timeout(TIMEOUT, events.next()).await.unwrap();
+    assert!(end.is_none());
+}
+
+#[tokio::test]
+async fn rejected_joins_are_errors() {
+    let server = TestServer::start().await;
+    let (alice, _events) = ChatClient::connect(server.address, "alice").await.unwrap();
+    server
+        .chat_room
+        .admin_ban(&alice.uuid(), None, "spam".into())
+        .await
+        .unwrap();
+
+    match ChatClient::connect(server.address, "alice").await {
+        Err(Error::JoinRejected { reason }) => assert!(reason.contains("spam")),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("joined while banned"),
+    }
+}
+
+#[tokio::test]
+async fn lost_connections_are_rejoined() {
+    let server = TestServer::start().await;
+    let options = JoinOptions {
+        reconnect_delay: Duration::from_millis(10),
+        ..JoinOptions::new("alice")
+    };
+    let (alice, mut events) = ChatClient::connect_with(server.address, options)
+        .await
+        .unwrap();
+    let first_uuid = alice.uuid();
+
+    server.cut_connections();
+    loop {
+        match next(&mut events).await {
+            Event::Reconnecting { attempt } => {
+                assert_eq!(attempt, 1);
+                break;
+            }
+            Event::Server(_) => {}
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+    match next(&mut events).await {
+        Event::Server(ServerMessage::JoinAccepted { uuid, .. }) => assert_eq!(uuid, alice.uuid()),
+        other => panic!("unexpected event: {:?}", other),
+    }
+    assert_ne!(alice.uuid(), first_uuid);
+
+    alice.send_message("back again").unwrap();
+    assert_eq!(&*next_chat(&mut events).await.text, "back again");
+}
+
+#[tokio::test]
+async fn renamed_accounts_rejoin_under_their_account() {
+    let server = TestServer::start().await;
+    let options = JoinOptions {
+        password: Some("hunter2".into()),
+        reconnect_delay: Duration::from_millis(10),
+        ..JoinOptions::new("alice")
+    };
+    let (alice, mut events) = ChatClient::connect_with(server.address, options)
+        .await
+        .unwrap();
+    alice.send_message("/nick ally").unwrap();
+    loop {
+        if let Event::Server(ServerMessage::UserRenamed { .. }) = next(&mut events).await {
+            break;
+        }
+    }
+
+    server.cut_connections();
+    loop {
+        if let Event::Server(ServerMessage::JoinAccepted { .. }) = next(&mut events).await {
+            break;
+        }
+    }
+    loop {
+        if let Event::Server(ServerMessage::UserRenamed { uuid, username }) =
+            next(&mut events).await
+        {
+            assert_eq!(uuid, alice.uuid());
+            assert_eq!(&*username, "ally");
+            break;
+        }
+    }
+
+    alice.send_message("still me").unwrap();
+    let message = next_chat(&mut events).await;
+    assert_eq!(message.author.as_deref(), Some("alice"));
+}
+
+#[tokio::test]
+async fn registered_authors_edit_after_rejoining() {
+    let server = TestServer::start().await;
+    let options = JoinOptions {
+        password: Some("hunter2".into()),
+        reconnect_delay: Duration::from_millis(10),
+        ..JoinOptions::new("alice")
+    };
+    let (alice, mut events) = ChatClient::connect_with(server.address, options)
+        .await
+        .unwrap();
+    alice.send_message("frist").unwrap();
+    let message = next_chat(&mut events).await;
+    assert_eq!(message.author.as_deref(), Some("alice"));
+
+    server.cut_connections();
+    loop {
+        if let Event::Server(ServerMessage::JoinAccepted { .. }) = next(&mut events).await {
+            break;
+        }
+    }
+    assert_ne!(alice.uuid(), message.from);
+    assert!(alice.is_own(&message));
+
+    alice
+        .send(ClientMessage::EditMessage {
+            id: message.id.clone(),
+            text: "first".into(),
+        })
+        .unwrap();
+    loop {
+        if let Event::Server(ServerMessage::MessageEdited { id, text, .. }) =
+            next(&mut events).await
+        {
+            assert_eq!(id, message.id);
+            assert_eq!(&*text, "first");
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn rejected_requests_are_explained() {
+    let server = TestServer::start().await;
+    let (alice, mut alice_events) = ChatClient::connect(server.address, "alice").await.unwrap();
+    let (bob, mut bob_events) = ChatClient::connect(server.address, "bob").await.unwrap();
+    alice.send_message("mine").unwrap();
+    let message = next_chat(&mut alice_events).await;
+    next_chat(&mut bob_events).await;
+
+    bob.send(ClientMessage::DeleteMessage {
+        id: message.id.clone(),
+    })
+    .unwrap();
+    loop {
+        if let Event::Server(ServerMessage::System { text }) = next(&mut bob_events).await {
+            assert!(text.contains("only the author"), "{}", text);
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn finishing_an_unknown_upload_fails() {
+    let server = TestServer::start().await;
+    let (alice, mut events) = ChatClient::connect(server.address, "alice").await.unwrap();
+    let upload_id = Uid::new();
+
+    alice
+        .send(ClientMessage::FinishUpload {
+            upload_id: upload_id.clone(),
+            sha256: [0; 32],
+        })
+        .unwrap();
+    loop {
+        if let Event::Server(ServerMessage::UploadFailed { upload_id: id, .. }) =
+            next(&mut events).await
+        {
+            assert_eq!(id, upload_id);
+            break;
+        }
+    }
+}
+
+/// Searches for "release" in `room` among messages by `from` sent at or
+/// after `after`, returning the texts found.
+async fn search(
+    client: &ChatClient,
+    events: &mut Events,
+    room: Option<&str>,
+    from: Option<&str>,
+    after: Option<u64>,
+) -> Vec<Arc<str>> {
+    client
+        .send(ClientMessage::Search {
+            query: "release".into(),
+            room: room.map(Into::into),
+            from: from.map(Into::into),
+            before: None,
+            after,
+            limit: 10,
+        })
+        .unwrap();
+    loop {
+        if let Event::Server(ServerMessage::SearchResults { results, .. }) = next(events).await {
+            return results.into_iter().map(|result| result.text).collect();
+        }
+    }
+}
+
+#[tokio::test]
+async fn searches_keep_to_the_room_and_dates() {
+    let server = TestServer::start().await;
+    let (alice, mut events) = ChatClient::connect(server.address, "alice").await.unwrap();
+    alice.send_message("release notes").unwrap();
+    let message = next_chat(&mut events).await;
+
+    let after = Some(message.timestamp);
+    let found = search(&alice, &mut events, Some("general"), None, after).await;
+    assert_eq!(found, [Arc::from("release notes")]);
+    let later = Some(message.timestamp + 1);
+    let found = search(&alice, &mut events, None, None, later).await;
+    assert!(found.is_empty());
+    let found = search(&alice, &mut events, Some("random"), None, None).await;
+    assert!(found.is_empty());
+}
+
+#[tokio::test]
+async fn searches_by_author_follow_the_account() {
+    let server = TestServer::start().await;
+    let options = JoinOptions {
+        password: Some("hunter2".into()),
+        ..JoinOptions::new("alice")
+    };
+    let (alice, mut events) = ChatClient::connect_with(server.address, options)
+        .await
+        .unwrap();
+    alice.send_message("/nick ally").unwrap();
+    alice.send_message("release notes").unwrap();
+    next_chat(&mut events).await;
+    let (bob, mut bob_events) = ChatClient::connect(server.address, "bob").await.unwrap();
+    bob.send_message("release party").unwrap();
+    next_chat(&mut bob_events).await;
+
+    let found = search(&bob, &mut bob_events, None, Some("ALICE"), None).await;
+    assert_eq!(found, [Arc::from("release notes")]);
+    let found = search(&bob, &mut bob_events, None, Some("ally"), None).await;
+    assert!(found.is_empty());
+    let found = search(&bob, &mut bob_events, None, Some("bob"), None).await;
+    assert_eq!(found, [Arc::from("release party")]);
+}
+
+/// Joins as `username` with `password`, expecting to be turned away.
+async fn join_rejected(server: &TestServer, username: &str, password: Option<&str>) -> Arc<str> {
+    let options = JoinOptions {
+        password: password.map(Into::into),
+        ..JoinOptions::new(username)
+    };
+    match ChatClient::connect_with(server.address, options).await {
+        Err(Error::JoinRejected { reason }) => reason,
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("{} joined", username),
+    }
+}
+
+/// Registers `owner` and has the server admin make them the room's owner.
+async fn join_owner(server: &TestServer) -> (ChatClient, Events) {
+    let options = JoinOptions {
+        password: Some("hunter2".into()),
+        ..JoinOptions::new("owner")
+    };
+    let joined = ChatClient::connect_with(server.address, options)
+        .await
+        .unwrap();
+    server
+        .chat_room
+        .admin_set_role("owner", Role::Owner)
+        .await
+        .unwrap();
+    joined
+}
+
+#[tokio::test]
+async fn registered_names_need_their_password() {
+    let server = TestServer::start().await;
+    let options = JoinOptions {
+        password: Some("hunter2".into()),
+        ..JoinOptions::new("alice")
+    };
+    let (_alice, mut alice_events) = ChatClient::connect_with(server.address, options.clone())
+        .await
+        .unwrap();
+    match next(&mut alice_events).await {
+        Event::Server(ServerMessage::JoinAccepted { participants, .. }) => {
+            assert_eq!(participants[0].role, Role::Member);
+        }
+        other => panic!("unexpected event: {:?}", other),
+    }
+
+    join_rejected(&server, "alice", None).await;
+    join_rejected(&server, "Alice", Some("wrong")).await;
+
+    // The right password replaces the earlier session.
+    let (_alice, _events) = ChatClient::connect_with(server.address, options)
+        .await
+        .unwrap();
+    loop {
+        match next(&mut alice_events).await {
+            Event::Disconnected { .. } => break,
+            Event::Server(_) => {}
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn names_in_use_are_refused() {
+    let server = TestServer::start().await;
+    let (_alice, _alice_events) = ChatClient::connect(server.address, "alice").await.unwrap();
+
+    let reason = join_rejected(&server, "ALICE", None).await;
+    assert!(reason.contains("already"));
+
+    let (bob, mut bob_events) = ChatClient::connect(server.address, "bob").await.unwrap();
+    bob.send(ClientMessage::Rename {
+        username: "Alice".into(),
+    })
+    .unwrap();
+    loop {
+        match next(&mut bob_events).await {
+            Event::Server(ServerMessage::CommandReply { text }) => {
+                assert!(text.contains("taken"));
+                break;
+            }
+            Event::Server(ServerMessage::UserRenamed { .. }) => panic!("renamed to a name in use"),
+            Event::Server(_) => {}
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    bob.send_message("/nick aLiCe").unwrap();
+    loop {
+        if let Event::Server(ServerMessage::CommandReply { text }) = next(&mut bob_events).await {
+            assert_eq!(text.as_ref(), "/nick failed: that name is taken.");
+            break;
+        }
+    }
+    assert_eq!(bob.username(&bob.uuid()).as_deref(), Some("bob"));
+}
+
+#[tokio::test]
+async fn roles_need_a_password() {
+    let server = TestServer::start().await;
+
+    // Joining without a password does not claim the room.
+    let (guest, mut guest_events) = ChatClient::connect(server.address, "guest").await.unwrap();
+    match next(&mut guest_events).await {
+        Event::Server(ServerMessage::JoinAccepted { participants, .. }) => {
+            assert_eq!(participants[0].role, Role::Member);
+        }
+        other => panic!("unexpected event: {:?}", other),
+    }
+    drop(guest);
+
+    // Only the server admin names an owner, and only a registered one.
+    let unregistered = server.chat_room.admin_set_role("owner", Role::Owner).await;
+    assert!(matches!(
+        unregistered,
+        Err(server::error::Error::Unregistered { .. })
+    ));
+
+    let options = JoinOptions {
+        password: Some("hunter2".into()),
+        ..JoinOptions::new("owner")
+    };
+    let (owner, mut events) = ChatClient::connect_with(server.address, options)
+        .await
+        .unwrap();
+    match next(&mut events).await {
+        Event::Server(ServerMessage::JoinAccepted { participants, .. }) => {
+            assert!(participants.iter().all(|p| p.role == Role::Member));
+        }
+        other => panic!("unexpected event: {:?}", other),
+    }
+    server
+        .chat_room
+        .admin_set_role("owner", Role::Owner)
+        .await
+        .unwrap();
+    loop {
+        if let Event::Server(ServerMessage::RoleChanged { uuid, role }) = next(&mut events).await {
+            assert_eq!((uuid, role), (owner.uuid(), Role::Owner));
+            break;
+        }
+    }
+    let (bob, mut bob_events) = ChatClient::connect(server.address, "bob").await.unwrap();
+
+    // Promoting a participant who joined without a password is refused.
+    owner
+        .send(ClientMessage::SetRole {
+            target: bob.uuid(),
+            role: Role::Moderator,
+        })
+        .unwrap();
+    owner.send_message("/who").unwrap();
+    loop {
+        if let Event::Server(ServerMessage::CommandReply { text }) = next(&mut events).await {
+            assert!(text.contains("bob") && !text.contains("moderator"));
+            break;
+        }
+    }
+
+    // Taking the owner's name is refused too.
+    bob.send_message("/nick OWNER").unwrap();
+    loop {
+        if let Event::Server(ServerMessage::CommandReply { text }) = next(&mut bob_events).await {
+            assert_eq!(text.as_ref(), "/nick failed: that name is taken.");
+            break;
+        }
+    }
+    let reason = join_rejected(&server, "owner", None).await;
+    assert!(reason.contains("password"));
+}
+
+#[tokio::test]
+async fn muted_participants_keep_their_name() {
+    let server = TestServer::start().await;
+    let (owner, mut owner_events) = join_owner(&server).await;
+    let (bob, mut bob_events) = ChatClient::connect(server.address, "bob").await.unwrap();
+
+    owner.send_message("/mute bob spamming").unwrap();
+    loop {
+        if let Event::Server(ServerMessage::System { text }) = next(&mut bob_events).await
+            && text.contains("was muted")
+        {
+            assert!(text.ends_with(": spamming"), "{}", text);
+            break;
+        }
+    }
+    for request in ["/nick robert", "still here"] {
+        bob.send_message(request).unwrap();
+        loop {
+            match next(&mut bob_events).await {
+                Event::Server(ServerMessage::System { text })
+                    if text.starts_with("You are muted") =>
+                {
+                    break;
+                }
+                Event::Server(ServerMessage::UserRenamed { .. }) => panic!("renamed while muted"),
+                Event::Server(_) => {}
+                other => panic!("unexpected event: {:?}", other),
+            }
+        }
+    }
+
+    owner.send_message("quiet now").unwrap();
+    assert_eq!(&*next_chat(&mut owner_events).await.text, "quiet now");
+    assert_eq!(owner.username(&bob.uuid()).as_deref(), Some("bob"));
+}
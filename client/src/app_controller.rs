@@ -1,24 +1,49 @@
-use std::{collections::HashMap, rc::Rc, sync::Arc};
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use common::uuid::Uid;
-use slint::{ComponentHandle, Model, VecModel};
+use chat_client::is_account;
+use common::{
+    markup,
+    protocol::{
+        self, ChatMessage, CommandInfo, ParticipantInfo, Permission, Presence, ServerMessage,
+    },
+    uuid::Uid,
+};
+use rfd::AsyncFileDialog;
+use slint::{ComponentHandle, Image, Model, ModelRc, Timer, TimerMode, VecModel, Weak};
 use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 
 use crate::{
-    AppState, Chat, User, View,
+    App, AppState, Attachment, Block, Chat, Command, JoinLogic, ModerationAction, Pin, Reaction,
+    SearchResult, Span, User, View,
     message::{NetworkMessage, UiMessage},
     network::handle_networking,
-    ui::Ui,
+    presence::PresenceTracker,
+    search::{format_timestamp, parse_query},
+    ui::{Ui, from_role, to_role, to_status},
 };
 
+/// How often an ongoing typing signal is re-sent; must stay below the server's expiry.
+const TYPING_REFRESH: Duration = Duration::from_secs(2);
+
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Larger images are shown as file cards instead of being previewed inline.
+const MAX_PREVIEW_SIZE: u64 = 2 * 1024 * 1024;
+
 pub struct AppController {
     ui: Ui,
     to_ui: UnboundedSender<NetworkMessage>,
     from_ui: UnboundedReceiver<UiMessage>,
     to_network: UnboundedSender<UiMessage>,
     from_network: UnboundedReceiver<NetworkMessage>,
-    chats_model: Rc<VecModel<Chat>>,
-    users_model: Rc<VecModel<User>>,
     users: HashMap<Uid, Arc<str>>,
 }
 
@@ -27,13 +52,13 @@ impl AppController {
         let (to_network, from_ui) = mpsc::unbounded_channel::<UiMessage>();
         let (to_ui, from_network) = mpsc::unbounded_channel::<NetworkMessage>();
 
-        let chats_model = Rc::new(VecModel::from(vec![]));
-        let users_model = Rc::new(VecModel::from(vec![]));
+        let chats_model = Rc::new(VecModel::<Chat>::from(vec![]));
+        let users_model = Rc::new(VecModel::<User>::from(vec![]));
 
         let ui = Ui::new();
         let app_state = ui.handle().global::<AppState>();
-        app_state.set_chats(chats_model.clone().into());
-        app_state.set_online_users(users_model.clone().into());
+        app_state.set_chats(chats_model.into());
+        app_state.set_online_users(users_model.into());
 
         Self {
             ui,
@@ -41,8 +66,6 @@ impl AppController {
             from_ui,
             to_network,
             from_network,
-            chats_model,
-            users_model,
             users: HashMap::new(),
         }
     }
@@ -50,88 +73,448 @@ impl AppController {
     pub async fn run(mut self) {
         {
             let tx = self.to_network.clone();
-            self.ui.on_join(move |address, username| {
-                let join = UiMessage::JoinRoom { address, username };
+            self.ui.on_join(move |address, username, password| {
+                let join = UiMessage::JoinRoom {
+                    address,
+                    username,
+                    password,
+                };
                 let _ = tx.send(join);
             });
         }
 
+        let typing_sent_at = Rc::new(Cell::new(None::<Instant>));
+        let presence = Rc::new(PresenceTracker::new(self.to_network.clone()));
+
         {
             let tx = self.to_network.clone();
-            let chats_model = self.chats_model.clone();
-
-            self.ui.on_send_message(move |message| {
-                chats_model.push(Chat {
-                    text: message.clone().into(),
-                    username: "You".into(),
-                    is_author: true,
-                    is_system: false,
-                });
+            let typing_sent_at = typing_sent_at.clone();
+            let presence = presence.clone();
 
-                let chat = UiMessage::SendChat { text: message };
+            // The message is added to the chats model once the server echoes it
+            // back with its ID.
+            self.ui.on_send_message(move |message, reply_to| {
+                presence.record_activity();
+
+                // The server clears the typing state when a message is relayed.
+                typing_sent_at.set(None);
+
+                let chat = UiMessage::SendChat {
+                    text: message,
+                    reply_to,
+                    attachments: Vec::new(),
+                };
                 let _ = tx.send(chat);
             });
         }
 
+        {
+            let tx = self.to_network.clone();
+            let presence = presence.clone();
+
+            self.ui.on_typing(move |active| {
+                presence.record_activity();
+
+                let should_send = match (active, typing_sent_at.get()) {
+                    (true, Some(sent_at)) => sent_at.elapsed() >= TYPING_REFRESH,
+                    (true, None) => true,
+                    (false, sent_at) => sent_at.is_some(),
+                };
+
+                if should_send {
+                    typing_sent_at.set(active.then(Instant::now));
+                    let _ = tx.send(UiMessage::Typing { active });
+                }
+            });
+        }
+
+        {
+            let tx = self.to_network.clone();
+            self.ui.on_edit_message(move |id, text| {
+                let _ = tx.send(UiMessage::EditMessage { id, text });
+            });
+        }
+
+        {
+            let tx = self.to_network.clone();
+            self.ui.on_delete_message(move |id| {
+                let _ = tx.send(UiMessage::DeleteMessage { id });
+            });
+        }
+
+        {
+            let ui_weak = self.ui.as_weak();
+            self.ui.on_message_index(move |id| {
+                let ui = ui_weak.upgrade()?;
+                let chats_model = ui.global::<AppState>().get_chats();
+                chats_model.iter().position(|chat| chat.id == id)
+            });
+        }
+
+        {
+            let tx = self.to_network.clone();
+            let ui_weak = self.ui.as_weak();
+            let read_sent = Cell::new(0);
+            let presence = presence.clone();
+
+            self.ui.on_mark_read(move || {
+                presence.record_activity();
+
+                let Some(ui) = ui_weak.upgrade() else {
+                    return;
+                };
+                let app_state = ui.global::<AppState>();
+                app_state.set_unread_mentions(0);
+
+                let chats_model = app_state.get_chats();
+                let seq = chats_model.iter().map(|chat| chat.seq).max().unwrap_or(0) as u64;
+
+                if seq > read_sent.get() {
+                    read_sent.set(seq);
+                    let _ = tx.send(UiMessage::MarkRead { seq });
+                }
+            });
+        }
+
+        {
+            let tx = self.to_network.clone();
+            self.ui.on_react(move |message_id, emoji, add| {
+                let _ = tx.send(UiMessage::React {
+                    message_id,
+                    emoji,
+                    add,
+                });
+            });
+        }
+
+        {
+            let presence = presence.clone();
+            self.ui.on_set_presence(move |status, text| {
+                presence.set(status, text);
+            });
+        }
+
+        {
+            let tx = self.to_network.clone();
+
+            // Sent as a message of its own once the server confirms the upload.
+            self.ui.on_attach_file(move || {
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    if let Some(file) = AsyncFileDialog::new().pick_file().await {
+                        let path = file.path().to_path_buf();
+                        let _ = tx.send(UiMessage::Upload { path });
+                    }
+                });
+            });
+        }
+
+        {
+            let tx = self.to_network.clone();
+            let ui_weak = self.ui.as_weak();
+
+            self.ui.on_download(move |id| {
+                let Some(ui) = ui_weak.upgrade() else {
+                    return;
+                };
+                let Ok(blob_id) = id.parse::<Uid>() else {
+                    return;
+                };
+                let chats_model = ui.global::<AppState>().get_chats();
+                let Some(name) = chats_model
+                    .iter()
+                    .flat_map(|chat| chat.attachments.iter().collect::<Vec<_>>())
+                    .find(|attachment| attachment.id == id)
+                    .map(|attachment| attachment.name.to_string())
+                else {
+                    return;
+                };
+
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let dialog = AsyncFileDialog::new().set_file_name(name);
+                    if let Some(file) = dialog.save_file().await {
+                        let path = file.path().to_path_buf();
+                        let _ = tx.send(UiMessage::Download { blob_id, path });
+                    }
+                });
+            });
+        }
+
+        {
+            let tx = self.to_network.clone();
+            self.ui.on_moderate(move |target, action, minutes| {
+                let duration = (minutes > 0).then(|| minutes as u64 * 60);
+                let reason = String::new();
+                let message = match action {
+                    ModerationAction::Kick => UiMessage::Kick { target, reason },
+                    ModerationAction::Mute => UiMessage::Mute {
+                        target,
+                        duration,
+                        reason,
+                    },
+                    ModerationAction::Ban => UiMessage::Ban {
+                        target,
+                        duration,
+                        reason,
+                    },
+                };
+                let _ = tx.send(message);
+            });
+        }
+
+        {
+            let tx = self.to_network.clone();
+            self.ui.on_set_role(move |target, role| {
+                let _ = tx.send(UiMessage::SetRole { target, role });
+            });
+        }
+
+        {
+            let ui_weak = self.ui.as_weak();
+
+            // Suggestions are shown while the command name is being typed.
+            self.ui.on_complete_command(move |text| {
+                let Some(ui) = ui_weak.upgrade() else {
+                    return;
+                };
+                let app_state = ui.global::<AppState>();
+
+                let suggestions: Vec<_> = match text.strip_prefix('/') {
+                    Some(prefix) if !prefix.contains(char::is_whitespace) => {
+                        let prefix = prefix.to_lowercase();
+                        app_state
+                            .get_commands()
+                            .iter()
+                            .filter(|command| command.name.starts_with(&prefix))
+                            .collect()
+                    }
+                    _ => Vec::new(),
+                };
+                app_state.set_command_suggestions(ModelRc::new(VecModel::from(suggestions)));
+            });
+        }
+
+        {
+            let tx = self.to_network.clone();
+            let ui_weak = self.ui.as_weak();
+
+            // Only the fields that changed are sent, so the server does not
+            // announce unchanged ones.
+            self.ui.on_set_room_info(move |topic, description| {
+                let Some(ui) = ui_weak.upgrade() else {
+                    return;
+                };
+                let app_state = ui.global::<AppState>();
+
+                if topic.trim() != app_state.get_topic().as_str() {
+                    let _ = tx.send(UiMessage::SetTopic { topic });
+                }
+                if description.trim() != app_state.get_description().as_str() {
+                    let _ = tx.send(UiMessage::SetDescription { description });
+                }
+            });
+        }
+
+        {
+            let tx = self.to_network.clone();
+            self.ui.on_pin_message(move |id, pin| {
+                let _ = tx.send(UiMessage::Pin { id, pin });
+            });
+        }
+
+        {
+            let tx = self.to_network.clone();
+            let ui_weak = self.ui.as_weak();
+
+            self.ui.on_search(move |input| {
+                let Some(ui) = ui_weak.upgrade() else {
+                    return;
+                };
+                let query = parse_query(&input);
+                if query.text.is_empty()
+                    && query.from.is_none()
+                    && query.before.is_none()
+                    && query.after.is_none()
+                {
+                    return;
+                }
+
+                ui.global::<AppState>()
+                    .set_search_status("Searching…".into());
+                let _ = tx.send(UiMessage::Search {
+                    query: query.text,
+                    from: query.from,
+                    before: query.before,
+                    after: query.after,
+                });
+            });
+        }
+
+        {
+            let ui_weak = self.ui.as_weak();
+
+            self.ui.on_open_link(move |url| {
+                if let Err(e) = open::that_detached(&url) {
+                    push_system_chat(&ui_weak, format!("Could not open {}: {}", url, e));
+                }
+            });
+        }
+
+        let idle_timer = Timer::default();
+        idle_timer.start(TimerMode::Repeated, IDLE_CHECK_INTERVAL, move || {
+            presence.check_idle();
+        });
+
         tokio::spawn(handle_networking(self.to_ui, self.from_ui));
 
         let ui_weak = self.ui.as_weak();
+        let to_network = self.to_network.clone();
         let mut users = self.users;
+        let mut own_uuid = None;
+        let mut own_account = None;
+        let mut own_role = protocol::Role::default();
+        let mut role_permissions = HashMap::new();
+        let mut commands = Vec::new();
+        // Author of every `/me` message, needed to render edits.
+        let mut emotes = HashMap::new();
+        let mut read_receipts = HashMap::new();
+        // An upload that failed early is reported again when it is finished.
+        let mut failed_upload = None;
         tokio::spawn(async move {
             while let Some(message) = self.from_network.recv().await {
                 match message {
-                    NetworkMessage::InvalidAddress => todo!(),
+                    NetworkMessage::InvalidAddress => {
+                        set_join_error(&ui_weak, "Could not connect to the server".to_string());
+                    }
+                    NetworkMessage::JoinRejected { reason } => {
+                        set_join_error(&ui_weak, reason);
+                    }
+                    NetworkMessage::Joined { account } => {
+                        own_account = account;
+                    }
+                    NetworkMessage::UploadFailed { name, reason } => {
+                        let text = format!("Could not upload {}: {}", name, reason);
+                        push_system_chat(&ui_weak, text);
+                    }
+                    NetworkMessage::Downloaded { blob_id, path } => {
+                        let id = blob_id.inner().to_string();
+
+                        ui_weak
+                            .upgrade_in_event_loop(move |ui| {
+                                let chats_model = ui.global::<AppState>().get_chats();
+                                set_attachment_path(&chats_model, &id, &path);
+                            })
+                            .unwrap();
+                    }
+                    NetworkMessage::DownloadFailed { reason } => {
+                        let text = format!("Download failed: {}", reason);
+                        push_system_chat(&ui_weak, text);
+                    }
+                    NetworkMessage::Reconnecting { attempt } => {
+                        let text = format!(
+                            "Lost the connection to the server, reconnecting (attempt {})",
+                            attempt
+                        );
+                        push_system_chat(&ui_weak, text);
+                    }
+                    NetworkMessage::Disconnected { reason } => {
+                        let text = reason
+                            .unwrap_or_else(|| "Lost the connection to the server".to_string());
+                        push_system_chat(&ui_weak, text);
+                    }
                     NetworkMessage::ServerMessage(server_message) => match server_message {
                         common::protocol::ServerMessage::Chat(chat_message) => {
-                            let username = users.get(&chat_message.from).unwrap();
-
-                            let chat = Chat {
-                                text: chat_message.text.to_string().into(),
-                                username: username.to_string().into(),
-                                is_author: false,
-                                is_system: false,
-                            };
+                            request_previews(&chat_message, &to_network);
+                            if chat_message.emote {
+                                emotes.insert(chat_message.id.clone(), chat_message.from.clone());
+                            }
+                            let chat_message = with_emote_body(chat_message, &users);
+                            let is_author =
+                                is_own(&chat_message, own_uuid.as_ref(), own_account.as_deref());
+                            let username = display_name(&chat_message, &users, is_author);
+                            let own_uuid = own_uuid.clone();
 
                             ui_weak
                                 .upgrade_in_event_loop(move |ui| {
-                                    let chats_model = ui.global::<AppState>().get_chats();
+                                    let app_state = ui.global::<AppState>();
+
+                                    let chats_model = app_state.get_chats();
                                     let chats_model = chats_model
                                         .as_any()
                                         .downcast_ref::<VecModel<Chat>>()
                                         .unwrap();
-                                    chats_model.push(chat);
+                                    let chat = to_chat(
+                                        &chat_message,
+                                        &username,
+                                        is_author,
+                                        own_uuid.as_ref(),
+                                    );
+
+                                    if chat.mentions_me {
+                                        app_state.set_unread_mentions(
+                                            app_state.get_unread_mentions() + 1,
+                                        );
+                                        app_state.set_last_mention_id(chat.id.clone());
+                                    }
+                                    push_chat(chats_model, chat);
                                 })
                                 .unwrap();
                         }
                         common::protocol::ServerMessage::JoinAccepted {
+                            uuid,
                             history,
                             participants,
+                            last_read,
+                            permissions,
+                            commands: available_commands,
+                            room,
                         } => {
-                            participants.iter().for_each(|(uuid, username)| {
-                                users.insert(uuid.clone(), username.clone());
+                            role_permissions = permissions.into_iter().collect();
+                            commands = available_commands;
+                            if let Some(participant) = participants
+                                .iter()
+                                .find(|participant| participant.uuid == uuid)
+                            {
+                                own_role = participant.role;
+                            }
+                            own_uuid = Some(uuid);
+
+                            // After a reconnect the room is described from scratch.
+                            users.clear();
+                            emotes.clear();
+                            read_receipts.clear();
+                            participants.iter().for_each(|participant| {
+                                users
+                                    .insert(participant.uuid.clone(), participant.username.clone());
                             });
 
-                            let chats: Vec<_> = history
+                            history
                                 .iter()
+                                .for_each(|chat| request_previews(chat, &to_network));
+
+                            history.iter().filter(|chat| chat.emote).for_each(|chat| {
+                                emotes.insert(chat.id.clone(), chat.from.clone());
+                            });
+
+                            let history: Vec<_> = history
+                                .into_iter()
+                                .map(|chat| with_emote_body(chat, &users))
                                 .map(|chat| {
-                                    let username = users
-                                        .get(&chat.from)
-                                        .map_or("Unknown".to_string(), |username| {
-                                            username.to_string()
-                                        });
-                                    Chat {
-                                        text: chat.text.to_string().into(),
-                                        username: username.into(),
-                                        is_author: false,
-                                        is_system: false,
-                                    }
+                                    let is_author =
+                                        is_own(&chat, own_uuid.as_ref(), own_account.as_deref());
+                                    let username = display_name(&chat, &users, is_author);
+                                    (chat, username, is_author)
                                 })
                                 .collect();
+                            let own_uuid = own_uuid.clone();
 
+                            let permissions = permissions_of(&role_permissions, own_role);
+                            let commands = commands.clone();
                             let users: Vec<_> = participants
                                 .iter()
-                                .map(|(_, username)| User {
-                                    username: username.to_string().into(),
+                                .map(|participant| {
+                                    to_user(participant, own_uuid.as_ref(), own_role)
                                 })
                                 .collect();
 
@@ -151,54 +534,361 @@ impl AppController {
                                         .downcast_ref::<VecModel<User>>()
                                         .unwrap();
 
-                                    chats_model.extend(chats);
-                                    users_model.extend(users);
+                                    chats_model.clear();
+                                    users_model.clear();
+
+                                    let mut unread_marked = false;
+                                    for (message, username, is_author) in history {
+                                        let mut chat = to_chat(
+                                            &message,
+                                            &username,
+                                            is_author,
+                                            own_uuid.as_ref(),
+                                        );
+                                        if !unread_marked
+                                            && !chat.is_author
+                                            && message.seq > last_read
+                                        {
+                                            chat.first_unread = true;
+                                            unread_marked = true;
+                                        }
+                                        push_chat(chats_model, chat);
+                                    }
+                                    users
+                                        .into_iter()
+                                        .for_each(|user| insert_user(users_model, user));
+                                    apply_role(&app_state, own_role, &permissions, &commands);
+                                    apply_room_info(&app_state, &room);
+                                    ui.global::<JoinLogic>().set_error("".into());
                                     ui.set_view(View::Chat);
                                 })
                                 .unwrap();
                         }
-                        common::protocol::ServerMessage::UserJoined { uuid, username } => {
-                            users.insert(uuid, username.clone());
+                        common::protocol::ServerMessage::UserJoined {
+                            uuid,
+                            username,
+                            role,
+                        } => {
+                            users.insert(uuid.clone(), username.clone());
 
-                            let chat = Chat {
-                                text: format!("{} joined the chat", username).into(),
-                                username: "".into(),
-                                is_author: false,
-                                is_system: true,
+                            let text = format!("{} joined the chat", username);
+                            let participant = ParticipantInfo {
+                                uuid,
+                                username,
+                                presence: Presence::default(),
+                                role,
                             };
+                            let user = to_user(&participant, own_uuid.as_ref(), own_role);
 
                             ui_weak
                                 .upgrade_in_event_loop(move |ui| {
-                                    let chats_model = ui.global::<AppState>().get_chats();
+                                    let app_state = ui.global::<AppState>();
+
+                                    let chats_model = app_state.get_chats();
                                     let chats_model = chats_model
                                         .as_any()
                                         .downcast_ref::<VecModel<Chat>>()
                                         .unwrap();
-                                    chats_model.push(chat);
+
+                                    let users_model = app_state.get_online_users();
+                                    let users_model = users_model
+                                        .as_any()
+                                        .downcast_ref::<VecModel<User>>()
+                                        .unwrap();
+
+                                    chats_model.push(system_chat(text));
+                                    insert_user(users_model, user);
                                 })
                                 .unwrap();
                         }
                         common::protocol::ServerMessage::UserLeft { uuid } => {
+                            read_receipts.remove(&uuid);
+                            let receipts =
+                                named_read_receipts(&read_receipts, &users, own_uuid.as_ref());
+
                             if let Some(username) = users.remove(&uuid) {
-                                let chat = Chat {
-                                    text: format!("{} left the chat", username).into(),
-                                    username: "".into(),
-                                    is_author: false,
-                                    is_system: true,
-                                };
+                                let text = format!("{} left the chat", username);
+                                let id = uuid.inner().to_string();
 
                                 ui_weak
                                     .upgrade_in_event_loop(move |ui| {
-                                        let chats_model = ui.global::<AppState>().get_chats();
+                                        let app_state = ui.global::<AppState>();
+
+                                        let chats_model = app_state.get_chats();
                                         let chats_model = chats_model
                                             .as_any()
                                             .downcast_ref::<VecModel<Chat>>()
                                             .unwrap();
-                                        chats_model.push(chat);
+
+                                        let users_model = app_state.get_online_users();
+                                        let users_model = users_model
+                                            .as_any()
+                                            .downcast_ref::<VecModel<User>>()
+                                            .unwrap();
+
+                                        chats_model.push(system_chat(text));
+                                        remove_user(users_model, &id);
+                                        apply_read_receipts(chats_model, &receipts);
                                     })
                                     .unwrap();
                             }
                         }
+                        common::protocol::ServerMessage::UserRenamed { uuid, username } => {
+                            let Some(previous) = users.insert(uuid.clone(), username.clone())
+                            else {
+                                continue;
+                            };
+
+                            let text = format!("{} is now known as {}", previous, username);
+                            let id = uuid.inner().to_string();
+
+                            ui_weak
+                                .upgrade_in_event_loop(move |ui| {
+                                    let app_state = ui.global::<AppState>();
+
+                                    let chats_model = app_state.get_chats();
+                                    let chats_model = chats_model
+                                        .as_any()
+                                        .downcast_ref::<VecModel<Chat>>()
+                                        .unwrap();
+
+                                    let users_model = app_state.get_online_users();
+                                    let users_model = users_model
+                                        .as_any()
+                                        .downcast_ref::<VecModel<User>>()
+                                        .unwrap();
+
+                                    chats_model.push(system_chat(text));
+                                    if let Some(mut user) = remove_user(users_model, &id) {
+                                        user.username = username.to_string().into();
+                                        insert_user(users_model, user);
+                                    }
+                                })
+                                .unwrap();
+                        }
+                        common::protocol::ServerMessage::MessageEdited {
+                            id,
+                            text,
+                            body,
+                            mentions,
+                        } => {
+                            let body = match emotes.get(&id) {
+                                Some(author) => {
+                                    let author = users.get(author).map_or("Unknown", |name| name);
+                                    emote_body(author, &body)
+                                }
+                                None => body,
+                            };
+                            let id = id.inner().to_string();
+                            let mentions_me = own_uuid
+                                .as_ref()
+                                .is_some_and(|uuid| mentions.contains(uuid));
+
+                            ui_weak
+                                .upgrade_in_event_loop(move |ui| {
+                                    let chats_model = ui.global::<AppState>().get_chats();
+                                    let chats_model = chats_model
+                                        .as_any()
+                                        .downcast_ref::<VecModel<Chat>>()
+                                        .unwrap();
+                                    update_chat(chats_model, &id, |chat| {
+                                        chat.text = text.to_string().into();
+                                        chat.body = to_blocks(&body);
+                                        chat.edited = true;
+                                        chat.mentions_me = mentions_me && !chat.is_author;
+                                    });
+                                    update_reply_previews(chats_model, &id);
+
+                                    let pins_model = ui.global::<AppState>().get_pins();
+                                    if let Some(index) =
+                                        pins_model.iter().position(|pin| pin.id == id)
+                                    {
+                                        let mut pin = pins_model.row_data(index).unwrap();
+                                        pin.text = text.to_string().into();
+                                        pins_model.set_row_data(index, pin);
+                                    }
+                                })
+                                .unwrap();
+                        }
+                        common::protocol::ServerMessage::MessageDeleted { id } => {
+                            let id = id.inner().to_string();
+
+                            ui_weak
+                                .upgrade_in_event_loop(move |ui| {
+                                    let chats_model = ui.global::<AppState>().get_chats();
+                                    let chats_model = chats_model
+                                        .as_any()
+                                        .downcast_ref::<VecModel<Chat>>()
+                                        .unwrap();
+                                    update_chat(chats_model, &id, |chat| {
+                                        chat.text = "".into();
+                                        chat.body = ModelRc::default();
+                                        chat.deleted = true;
+                                    });
+                                    update_reply_previews(chats_model, &id);
+                                })
+                                .unwrap();
+                        }
+                        common::protocol::ServerMessage::ReactionsUpdated {
+                            message_id,
+                            reactions,
+                        } => {
+                            let id = message_id.inner().to_string();
+                            let own_uuid = own_uuid.clone();
+
+                            ui_weak
+                                .upgrade_in_event_loop(move |ui| {
+                                    let chats_model = ui.global::<AppState>().get_chats();
+                                    let chats_model = chats_model
+                                        .as_any()
+                                        .downcast_ref::<VecModel<Chat>>()
+                                        .unwrap();
+                                    update_chat(chats_model, &id, |chat| {
+                                        chat.reactions =
+                                            to_reactions(&reactions, own_uuid.as_ref());
+                                    });
+                                })
+                                .unwrap();
+                        }
+                        common::protocol::ServerMessage::ReadReceipts { receipts } => {
+                            read_receipts.extend(receipts);
+                            let receipts =
+                                named_read_receipts(&read_receipts, &users, own_uuid.as_ref());
+
+                            ui_weak
+                                .upgrade_in_event_loop(move |ui| {
+                                    let chats_model = ui.global::<AppState>().get_chats();
+                                    let chats_model = chats_model
+                                        .as_any()
+                                        .downcast_ref::<VecModel<Chat>>()
+                                        .unwrap();
+                                    apply_read_receipts(chats_model, &receipts);
+                                })
+                                .unwrap();
+                        }
+                        common::protocol::ServerMessage::PresenceChanged { uuid, presence } => {
+                            let id = uuid.inner().to_string();
+
+                            ui_weak
+                                .upgrade_in_event_loop(move |ui| {
+                                    let users_model = ui.global::<AppState>().get_online_users();
+                                    let users_model = users_model
+                                        .as_any()
+                                        .downcast_ref::<VecModel<User>>()
+                                        .unwrap();
+
+                                    if let Some(index) =
+                                        users_model.iter().position(|user| user.id == id)
+                                    {
+                                        let mut user = users_model.row_data(index).unwrap();
+                                        user.status = to_status(presence.status);
+                                        user.status_text = presence.text.to_string().into();
+                                        users_model.set_row_data(index, user);
+                                    }
+                                })
+                                .unwrap();
+                        }
+                        common::protocol::ServerMessage::RoleChanged { uuid, role } => {
+                            if own_uuid.as_ref() == Some(&uuid) {
+                                own_role = role;
+                            }
+                            let id = uuid.inner().to_string();
+                            let permissions = permissions_of(&role_permissions, own_role);
+                            let commands = commands.clone();
+
+                            ui_weak
+                                .upgrade_in_event_loop(move |ui| {
+                                    let app_state = ui.global::<AppState>();
+                                    let users_model = app_state.get_online_users();
+
+                                    if let Some(index) =
+                                        users_model.iter().position(|user| user.id == id)
+                                    {
+                                        let mut user = users_model.row_data(index).unwrap();
+                                        user.role = to_role(role);
+                                        users_model.set_row_data(index, user);
+                                    }
+                                    apply_role(&app_state, own_role, &permissions, &commands);
+                                })
+                                .unwrap();
+                        }
+                        common::protocol::ServerMessage::SearchResults { query: _, results } => {
+                            let status = match results.len() {
+                                0 => "No messages found".to_string(),
+                                1 => "1 message".to_string(),
+                                count => format!("{} messages", count),
+                            };
+
+                            ui_weak
+                                .upgrade_in_event_loop(move |ui| {
+                                    let results: Vec<_> = results
+                                        .iter()
+                                        .map(|result| SearchResult {
+                                            id: result.id.inner().to_string().into(),
+                                            username: result.author.to_string().into(),
+                                            time: format_timestamp(result.timestamp).into(),
+                                            text: result.text.to_string().into(),
+                                        })
+                                        .collect();
+
+                                    let app_state = ui.global::<AppState>();
+                                    app_state.set_search_status(status.into());
+                                    app_state
+                                        .set_search_results(ModelRc::new(VecModel::from(results)));
+                                })
+                                .unwrap();
+                        }
+                        common::protocol::ServerMessage::RoomInfoChanged { room } => {
+                            ui_weak
+                                .upgrade_in_event_loop(move |ui| {
+                                    apply_room_info(&ui.global::<AppState>(), &room);
+                                })
+                                .unwrap();
+                        }
+                        common::protocol::ServerMessage::UploadComplete {
+                            upload_id: _,
+                            attachment,
+                        } => {
+                            let chat = UiMessage::SendChat {
+                                text: String::new(),
+                                reply_to: None,
+                                attachments: vec![attachment.blob_id],
+                            };
+                            let _ = to_network.send(chat);
+                        }
+                        common::protocol::ServerMessage::UploadFailed { upload_id, reason } => {
+                            if failed_upload.as_ref() != Some(&upload_id) {
+                                push_system_chat(&ui_weak, format!("Upload failed: {}", reason));
+                                failed_upload = Some(upload_id);
+                            }
+                        }
+                        common::protocol::ServerMessage::System { text } => {
+                            push_system_chat(&ui_weak, text.to_string());
+                        }
+                        common::protocol::ServerMessage::CommandReply { text } => {
+                            push_system_chat(&ui_weak, text.to_string());
+                        }
+                        // Rejections are handled while joining, and disconnects
+                        // arrive as `NetworkMessage::Disconnected`.
+                        ServerMessage::JoinRejected { .. } | ServerMessage::Disconnected { .. } => {
+                        }
+                        // Downloads are assembled by the network task.
+                        ServerMessage::DownloadChunk { .. }
+                        | ServerMessage::DownloadFailed { .. } => {}
+                        common::protocol::ServerMessage::TypingState { typing } => {
+                            let names: Vec<_> = typing
+                                .iter()
+                                .filter(|uuid| Some(*uuid) != own_uuid.as_ref())
+                                .filter_map(|uuid| users.get(uuid).cloned())
+                                .collect();
+                            let text = typing_text(&names);
+
+                            ui_weak
+                                .upgrade_in_event_loop(move |ui| {
+                                    ui.global::<AppState>().set_typing_text(text.into());
+                                })
+                                .unwrap();
+                        }
                     },
                 }
             }
@@ -207,3 +897,449 @@ impl AppController {
         self.ui.run();
     }
 }
+
+/// Whether the user sent `message`, in this session or, under the same
+/// account, before a reconnect.
+fn is_own(message: &ChatMessage, own_uuid: Option<&Uid>, own_account: Option<&str>) -> bool {
+    own_uuid == Some(&message.from) || is_account(message, own_account)
+}
+
+fn display_name(message: &ChatMessage, users: &HashMap<Uid, Arc<str>>, is_author: bool) -> String {
+    if is_author {
+        return "You".to_string();
+    }
+    if let Some(bot) = &message.bot {
+        return bot.to_string();
+    }
+
+    users
+        .get(&message.from)
+        .map_or("Unknown".to_string(), |username| username.to_string())
+}
+
+/// Builds the row for `message`. Slint models are not `Send`, so this has to
+/// run on the UI thread.
+fn to_chat(message: &ChatMessage, username: &str, is_author: bool, own_uuid: Option<&Uid>) -> Chat {
+    Chat {
+        id: message.id.inner().to_string().into(),
+        text: message.text.to_string().into(),
+        body: to_blocks(&message.body),
+        username: username.into(),
+        is_author,
+        is_system: false,
+        is_bot: message.bot.is_some(),
+        edited: message.edited,
+        deleted: message.deleted,
+        reply_to: message
+            .reply_to
+            .as_ref()
+            .map_or(String::new(), |id| id.inner().to_string())
+            .into(),
+        reactions: to_reactions(&message.reactions, own_uuid),
+        seq: message.seq as i32,
+        mentions_me: !is_author && own_uuid.is_some_and(|uuid| message.mentions.contains(uuid)),
+        attachments: to_attachments(&message.attachments),
+        ..Default::default()
+    }
+}
+
+/// Shows a `/me` message as an action of its author, as in "*alice waves*".
+fn with_emote_body(message: Arc<ChatMessage>, users: &HashMap<Uid, Arc<str>>) -> Arc<ChatMessage> {
+    if !message.emote {
+        return message;
+    }
+
+    let author = match &message.bot {
+        Some(bot) => bot.clone(),
+        None => users
+            .get(&message.from)
+            .cloned()
+            .unwrap_or_else(|| "Unknown".into()),
+    };
+    let mut message = Arc::unwrap_or_clone(message);
+    message.body = emote_body(&author, &message.body);
+    Arc::new(message)
+}
+
+fn emote_body(author: &str, body: &[markup::Block]) -> Vec<markup::Block> {
+    let mut blocks = body.to_vec();
+    let name = markup::Span {
+        text: format!("{} ", author).into(),
+        bold: true,
+        ..Default::default()
+    };
+    match blocks.first_mut() {
+        Some(markup::Block::Paragraph(spans)) => spans.insert(0, name),
+        _ => blocks.insert(0, markup::Block::Paragraph(vec![name])),
+    }
+
+    for block in &mut blocks {
+        if let markup::Block::Paragraph(spans) = block {
+            spans.iter_mut().for_each(|span| span.italic = true);
+        }
+    }
+    blocks
+}
+
+fn to_blocks(blocks: &[markup::Block]) -> ModelRc<Block> {
+    let blocks: Vec<_> = blocks
+        .iter()
+        .map(|block| match block {
+            markup::Block::Paragraph(spans) => Block {
+                spans: to_spans(spans),
+                ..Default::default()
+            },
+            markup::Block::Code { language, code } => Block {
+                is_code: true,
+                language: language.to_string().into(),
+                code: code.to_string().into(),
+                ..Default::default()
+            },
+        })
+        .collect();
+
+    ModelRc::new(VecModel::from(blocks))
+}
+
+fn to_spans(spans: &[markup::Span]) -> ModelRc<Span> {
+    let spans: Vec<_> = spans
+        .iter()
+        .map(|span| Span {
+            text: span.text.to_string().into(),
+            bold: span.bold,
+            italic: span.italic,
+            code: span.code,
+            link: span.link.as_deref().unwrap_or_default().into(),
+        })
+        .collect();
+
+    ModelRc::new(VecModel::from(spans))
+}
+
+fn to_attachments(attachments: &[protocol::Attachment]) -> ModelRc<Attachment> {
+    let attachments: Vec<_> = attachments
+        .iter()
+        .map(|attachment| {
+            let mut row = Attachment {
+                id: attachment.blob_id.inner().to_string().into(),
+                name: attachment.name.to_string().into(),
+                size_text: format_size(attachment.size).into(),
+                is_image: attachment.is_image(),
+                ..Default::default()
+            };
+
+            let preview = preview_path(&attachment.blob_id);
+            if row.is_image && preview.exists() {
+                set_path(&mut row, &preview);
+            }
+            row
+        })
+        .collect();
+
+    ModelRc::new(VecModel::from(attachments))
+}
+
+/// Downloads small images that have not been previewed before.
+fn request_previews(message: &ChatMessage, tx: &UnboundedSender<UiMessage>) {
+    for attachment in &message.attachments {
+        let path = preview_path(&attachment.blob_id);
+        if attachment.is_image() && attachment.size <= MAX_PREVIEW_SIZE && !path.exists() {
+            let blob_id = attachment.blob_id.clone();
+            let _ = tx.send(UiMessage::Download { blob_id, path });
+        }
+    }
+}
+
+fn preview_path(blob_id: &Uid) -> PathBuf {
+    env::temp_dir()
+        .join("chat-app-previews")
+        .join(blob_id.inner().to_string())
+}
+
+fn set_attachment_path(chats_model: &ModelRc<Chat>, id: &str, path: &Path) {
+    for chat in chats_model.iter() {
+        let attachments = chat.attachments;
+        for index in 0..attachments.row_count() {
+            let mut attachment = attachments.row_data(index).unwrap();
+            if attachment.id == id {
+                set_path(&mut attachment, path);
+                attachments.set_row_data(index, attachment);
+            }
+        }
+    }
+}
+
+fn set_path(attachment: &mut Attachment, path: &Path) {
+    attachment.path = path.to_string_lossy().into_owned().into();
+    if attachment.is_image
+        && let Ok(image) = Image::load_from_path(path)
+    {
+        attachment.image = image;
+    }
+}
+
+fn format_size(size: u64) -> String {
+    const KIB: u64 = 1024;
+    const MIB: u64 = 1024 * KIB;
+
+    match size {
+        size if size >= MIB => format!("{:.1} MB", size as f64 / MIB as f64),
+        size if size >= KIB => format!("{:.1} KB", size as f64 / KIB as f64),
+        size => format!("{} B", size),
+    }
+}
+
+fn push_system_chat(ui_weak: &Weak<App>, text: String) {
+    ui_weak
+        .upgrade_in_event_loop(move |ui| {
+            let chats_model = ui.global::<AppState>().get_chats();
+            let chats_model = chats_model
+                .as_any()
+                .downcast_ref::<VecModel<Chat>>()
+                .unwrap();
+            chats_model.push(system_chat(text));
+        })
+        .unwrap();
+}
+
+fn set_join_error(ui_weak: &Weak<App>, error: String) {
+    ui_weak
+        .upgrade_in_event_loop(move |ui| {
+            ui.global::<JoinLogic>().set_error(error.into());
+        })
+        .unwrap();
+}
+
+fn system_chat(text: String) -> Chat {
+    Chat {
+        text: text.into(),
+        is_system: true,
+        ..Default::default()
+    }
+}
+
+fn to_reactions(reactions: &[protocol::Reaction], own_uuid: Option<&Uid>) -> ModelRc<Reaction> {
+    let reactions: Vec<_> = reactions
+        .iter()
+        .map(|reaction| Reaction {
+            emoji: reaction.emoji.to_string().into(),
+            count: reaction.users.len() as i32,
+            reacted: own_uuid.is_some_and(|uuid| reaction.users.contains(uuid)),
+        })
+        .collect();
+
+    ModelRc::new(VecModel::from(reactions))
+}
+
+/// Appends `chat`, filling in the preview of the message it replies to.
+fn push_chat(chats_model: &VecModel<Chat>, mut chat: Chat) {
+    if !chat.reply_to.is_empty()
+        && let Some(parent) = chats_model.iter().find(|other| other.id == chat.reply_to)
+    {
+        set_reply_preview(&mut chat, &parent);
+    }
+    chats_model.push(chat);
+}
+
+/// Refreshes the previews of all replies to `id` after the parent changed.
+fn update_reply_previews(chats_model: &VecModel<Chat>, id: &str) {
+    let Some(parent) = chats_model.iter().find(|chat| chat.id == id) else {
+        return;
+    };
+
+    for index in 0..chats_model.row_count() {
+        let mut chat = chats_model.row_data(index).unwrap();
+        if chat.reply_to == id {
+            set_reply_preview(&mut chat, &parent);
+            chats_model.set_row_data(index, chat);
+        }
+    }
+}
+
+fn set_reply_preview(chat: &mut Chat, parent: &Chat) {
+    chat.reply_username = parent.username.clone();
+    chat.reply_text = if parent.deleted {
+        "Message deleted".into()
+    } else {
+        parent.text.clone()
+    };
+}
+
+fn update_chat<F: FnOnce(&mut Chat)>(chats_model: &VecModel<Chat>, id: &str, update: F) {
+    if let Some(index) = chats_model.iter().position(|chat| chat.id == id) {
+        let mut chat = chats_model.row_data(index).unwrap();
+        update(&mut chat);
+        chats_model.set_row_data(index, chat);
+    }
+}
+
+/// Resolves the read markers of everyone but the local user to usernames.
+fn named_read_receipts(
+    read_receipts: &HashMap<Uid, u64>,
+    users: &HashMap<Uid, Arc<str>>,
+    own_uuid: Option<&Uid>,
+) -> Vec<(Arc<str>, u64)> {
+    read_receipts
+        .iter()
+        .filter(|(uuid, _)| Some(*uuid) != own_uuid)
+        .filter_map(|(uuid, seq)| Some((users.get(uuid)?.clone(), *seq)))
+        .collect()
+}
+
+/// Shows each reader under the newest message they have read.
+fn apply_read_receipts(chats_model: &VecModel<Chat>, receipts: &[(Arc<str>, u64)]) {
+    let mut readers: HashMap<usize, Vec<&str>> = HashMap::new();
+    for (username, seq) in receipts {
+        let newest_read = chats_model
+            .iter()
+            .enumerate()
+            .filter(|(_, chat)| chat.seq > 0 && chat.seq as u64 <= *seq)
+            .last();
+        if let Some((index, _)) = newest_read {
+            readers.entry(index).or_default().push(username);
+        }
+    }
+
+    for index in 0..chats_model.row_count() {
+        let mut chat = chats_model.row_data(index).unwrap();
+        let read_by = readers.get_mut(&index).map_or(String::new(), |names| {
+            names.sort_unstable();
+            names.join(", ")
+        });
+
+        if chat.read_by != read_by.as_str() {
+            chat.read_by = read_by.into();
+            chats_model.set_row_data(index, chat);
+        }
+    }
+}
+
+fn typing_text(names: &[Arc<str>]) -> String {
+    match names {
+        [] => String::new(),
+        [name] => format!("{} is typing…", name),
+        [first, second] => format!("{} and {} are typing…", first, second),
+        [first, second, rest @ ..] => {
+            format!(
+                "{}, {} and {} others are typing…",
+                first,
+                second,
+                rest.len()
+            )
+        }
+    }
+}
+
+fn to_user(
+    participant: &ParticipantInfo,
+    own_uuid: Option<&Uid>,
+    own_role: protocol::Role,
+) -> User {
+    User {
+        id: participant.uuid.inner().to_string().into(),
+        username: participant.username.to_string().into(),
+        status: to_status(participant.presence.status),
+        status_text: participant.presence.text.to_string().into(),
+        role: to_role(participant.role),
+        manageable: participant.role < own_role,
+        is_self: own_uuid == Some(&participant.uuid),
+    }
+}
+
+/// Shows the topic and description and marks the pinned messages.
+fn apply_room_info(app_state: &AppState, room: &protocol::RoomInfo) {
+    app_state.set_topic(room.topic.to_string().into());
+    app_state.set_description(room.description.to_string().into());
+
+    let pins: Vec<_> = room.pins.iter().map(|id| id.inner().to_string()).collect();
+    let chats_model = app_state.get_chats();
+    for index in 0..chats_model.row_count() {
+        let mut chat = chats_model.row_data(index).unwrap();
+        let pinned = pins.iter().any(|id| chat.id == *id);
+        if chat.pinned != pinned {
+            chat.pinned = pinned;
+            chats_model.set_row_data(index, chat);
+        }
+    }
+
+    let pins: Vec<_> = pins
+        .iter()
+        .filter_map(|id| chats_model.iter().find(|chat| chat.id == id.as_str()))
+        .map(|chat| Pin {
+            id: chat.id,
+            username: chat.username,
+            text: chat.text,
+        })
+        .collect();
+    app_state.set_pins(ModelRc::new(VecModel::from(pins)));
+}
+
+fn permissions_of(
+    role_permissions: &HashMap<protocol::Role, Vec<Permission>>,
+    role: protocol::Role,
+) -> Vec<Permission> {
+    role_permissions.get(&role).cloned().unwrap_or_default()
+}
+
+/// Updates what the UI offers after our role, and so our permissions, changed.
+fn apply_role(
+    app_state: &AppState,
+    own_role: protocol::Role,
+    permissions: &[Permission],
+    commands: &[CommandInfo],
+) {
+    let can = |permission| permissions.contains(&permission);
+
+    let commands: Vec<_> = commands
+        .iter()
+        .filter(|command| command.permission.is_none_or(can))
+        .map(|command| Command {
+            name: command.name.to_string().into(),
+            usage: command.usage().into(),
+            description: command.description.to_string().into(),
+        })
+        .collect();
+    app_state.set_commands(ModelRc::new(VecModel::from(commands)));
+
+    app_state.set_can_send(can(Permission::SendMessages));
+    app_state.set_can_react(can(Permission::React));
+    app_state.set_can_edit(can(Permission::EditMessages));
+    app_state.set_can_delete(can(Permission::DeleteMessages));
+    app_state.set_can_delete_others(can(Permission::DeleteOthersMessages));
+    app_state.set_can_moderate(can(Permission::Moderate));
+    app_state.set_can_invite(can(Permission::Invite));
+    app_state.set_can_manage_roles(can(Permission::ManageRoles));
+    app_state.set_can_manage_room(can(Permission::ManageRoom));
+
+    let assignable: Vec<_> = protocol::Role::ALL
+        .into_iter()
+        .filter(|role| *role < own_role)
+        .map(to_role)
+        .collect();
+    app_state.set_assignable_roles(ModelRc::new(VecModel::from(assignable)));
+
+    let users_model = app_state.get_online_users();
+    for index in 0..users_model.row_count() {
+        let mut user = users_model.row_data(index).unwrap();
+        let manageable = from_role(user.role) < own_role;
+        if user.manageable != manageable {
+            user.manageable = manageable;
+            users_model.set_row_data(index, user);
+        }
+    }
+}
+
+/// Inserts `user` keeping the model sorted case-insensitively by username.
+fn insert_user(users_model: &VecModel<User>, user: User) {
+    let key = user.username.to_lowercase();
+    let index = users_model
+        .iter()
+        .position(|other| other.username.to_lowercase() > key)
+        .unwrap_or(users_model.row_count());
+    users_model.insert(index, user);
+}
+
+fn remove_user(users_model: &VecModel<User>, id: &str) -> Option<User> {
+    let index = users_model.iter().position(|user| user.id == id)?;
+    Some(users_model.remove(index))
+}
@@ -4,6 +4,8 @@ mod app_controller;
 mod error;
 mod message;
 mod network;
+mod presence;
+mod search;
 mod ui;
 
 slint::include_modules!();
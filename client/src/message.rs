@@ -1,13 +1,120 @@
-use common::protocol::ServerMessage;
+use std::{path::PathBuf, sync::Arc};
+
+use common::{
+    protocol::{PresenceStatus, Role, ServerMessage},
+    uuid::Uid,
+};
 
 #[derive(Debug)]
 pub enum UiMessage {
-    JoinRoom { address: String, username: String },
-    SendChat { text: String },
+    JoinRoom {
+        address: String,
+        username: String,
+        /// Empty to join without a password.
+        password: String,
+    },
+    SendChat {
+        text: String,
+        reply_to: Option<String>,
+        attachments: Vec<Uid>,
+    },
+    Typing {
+        active: bool,
+    },
+    EditMessage {
+        id: String,
+        text: String,
+    },
+    DeleteMessage {
+        id: String,
+    },
+    React {
+        message_id: String,
+        emoji: String,
+        add: bool,
+    },
+    MarkRead {
+        seq: u64,
+    },
+    SetPresence {
+        status: PresenceStatus,
+        text: String,
+    },
+    Upload {
+        path: PathBuf,
+    },
+    Kick {
+        target: String,
+        reason: String,
+    },
+    /// Durations are in seconds; `None` is permanent.
+    Ban {
+        target: String,
+        duration: Option<u64>,
+        reason: String,
+    },
+    Mute {
+        target: String,
+        duration: Option<u64>,
+        reason: String,
+    },
+    SetRole {
+        target: String,
+        role: Role,
+    },
+    SetTopic {
+        topic: String,
+    },
+    SetDescription {
+        description: String,
+    },
+    Pin {
+        id: String,
+        pin: bool,
+    },
+    Search {
+        query: String,
+        from: Option<String>,
+        before: Option<u64>,
+        after: Option<u64>,
+    },
+    /// Downloads a blob and writes it to `path`.
+    Download {
+        blob_id: Uid,
+        path: PathBuf,
+    },
 }
 
 #[derive(Debug)]
 pub enum NetworkMessage {
     InvalidAddress,
+    JoinRejected {
+        reason: String,
+    },
+    /// The room was joined, under a registered account if a password was
+    /// given. Sent before the first `JoinAccepted`.
+    Joined {
+        account: Option<Arc<str>>,
+    },
     ServerMessage(ServerMessage),
+    /// The connection was lost and is being re-established. The room is
+    /// described afresh by the next `JoinAccepted`.
+    Reconnecting {
+        attempt: u32,
+    },
+    /// The connection ended for good, with the server's reason if it gave one.
+    Disconnected {
+        reason: Option<String>,
+    },
+    UploadFailed {
+        name: String,
+        reason: String,
+    },
+    Downloaded {
+        blob_id: Uid,
+        path: PathBuf,
+    },
+    DownloadFailed {
+        reason: String,
+    },
 }
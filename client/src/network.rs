@@ -1,9 +1,15 @@
-use common::protocol::{ClientMessage, ServerMessage, read_msg, write_msg};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use chat_client::{
+    ChatClient, Event, Events, JoinOptions,
+    protocol::{ClientMessage, ServerMessage},
+    uuid::Uid,
+};
 use tokio::{
-    net::{
-        TcpStream,
-        tcp::{OwnedReadHalf, OwnedWriteHalf},
-    },
+    fs,
     sync::mpsc::{UnboundedReceiver, UnboundedSender},
 };
 
@@ -12,38 +18,50 @@ use crate::{
     message::{NetworkMessage, UiMessage},
 };
 
+const SEARCH_LIMIT: u32 = 50;
+
 pub async fn handle_networking(
     tx: UnboundedSender<NetworkMessage>,
     mut rx: UnboundedReceiver<UiMessage>,
 ) -> Result<()> {
-    let socket = loop {
-        match join_room(&mut rx, &tx).await {
-            Ok(socket) => break socket,
+    let (client, events) = loop {
+        match join_room(&mut rx).await {
+            Ok(joined) => break joined,
             Err(Error::InvalidAddress) => {
                 tx.send(NetworkMessage::InvalidAddress)
                     .map_err(|_| Error::ChannelClosed)?;
                 continue;
             }
+            Err(Error::JoinRejected { reason }) => {
+                tx.send(NetworkMessage::JoinRejected { reason })
+                    .map_err(|_| Error::ChannelClosed)?;
+                continue;
+            }
             Err(e) => return Err(e),
         }
     };
+    tx.send(NetworkMessage::Joined {
+        account: client.account(),
+    })
+    .map_err(|_| Error::ChannelClosed)?;
 
-    let (reader, writer) = socket.into_split();
-
-    tokio::select! {
-        res = read_from_ui(rx, writer) => { res? },
-        res = write_to_ui(tx, reader) => { res? }
+    Session {
+        client,
+        tx,
+        downloads: HashMap::new(),
+        received: HashMap::new(),
     }
-
-    Ok(())
+    .run(rx, events)
+    .await
 }
 
-async fn join_room(
-    rx: &mut UnboundedReceiver<UiMessage>,
-    tx: &UnboundedSender<NetworkMessage>,
-) -> Result<TcpStream> {
-    let (address, username) = match rx.recv().await {
-        Some(UiMessage::JoinRoom { address, username }) => (address, username),
+async fn join_room(rx: &mut UnboundedReceiver<UiMessage>) -> Result<(ChatClient, Events)> {
+    let (address, username, password) = match rx.recv().await {
+        Some(UiMessage::JoinRoom {
+            address,
+            username,
+            password,
+        }) => (address, username, password),
         Some(_) => return Err(Error::ProtocolError),
         None => return Err(Error::ChannelClosed),
     };
@@ -55,72 +73,281 @@ async fn join_room(
         return Err(Error::InvalidAddress);
     }
 
-    let mut socket = TcpStream::connect(address)
-        .await
-        .map_err(|_| Error::InvalidAddress)?;
-
-    let join = ClientMessage::JoinRequest {
-        username: username.into(),
+    let options = JoinOptions {
+        password: (!password.is_empty()).then(|| password.into()),
+        ..JoinOptions::new(username)
     };
-    write_msg(&mut socket, &join)
-        .await
-        .map_err(|_| Error::ServerError)?;
-
-    match read_msg::<_, ServerMessage>(&mut socket)
-        .await
-        .map_err(|_| Error::ServerError)?
-    {
-        ServerMessage::JoinAccepted {
-            history,
-            participants,
-        } => {
-            tx.send(NetworkMessage::ServerMessage(ServerMessage::JoinAccepted {
-                history,
-                participants,
-            }))
-            .map_err(|_| Error::ChannelClosed)?;
 
-            Ok(socket)
-        }
-        _ => Err(Error::ServerError),
+    match ChatClient::connect_with(address, options).await {
+        Ok(joined) => Ok(joined),
+        Err(chat_client::Error::Io(_)) => Err(Error::InvalidAddress),
+        Err(chat_client::Error::JoinRejected { reason }) => Err(Error::JoinRejected {
+            reason: reason.to_string(),
+        }),
+        Err(_) => Err(Error::ServerError),
     }
 }
 
-async fn read_from_ui(
-    mut rx: UnboundedReceiver<UiMessage>,
-    mut writer: OwnedWriteHalf,
-) -> Result<()> {
-    while let Some(message) = rx.recv().await {
-        match message {
-            UiMessage::JoinRoom {
-                address: _,
-                username: _,
-            } => return Err(Error::ProtocolError),
-            UiMessage::SendChat { text } => {
-                let chat = ClientMessage::Chat { text: text.into() };
-                write_msg(&mut writer, &chat)
-                    .await
+/// Passes requests from the UI to the client and its events back, saving
+/// downloads on the way.
+struct Session {
+    client: ChatClient,
+    tx: UnboundedSender<NetworkMessage>,
+    /// Destination of each requested download.
+    downloads: HashMap<Uid, PathBuf>,
+    /// Chunks of the downloads in progress.
+    received: HashMap<Uid, Vec<u8>>,
+}
+
+impl Session {
+    async fn run(mut self, mut rx: UnboundedReceiver<UiMessage>, mut events: Events) -> Result<()> {
+        loop {
+            tokio::select! {
+                message = rx.recv() => {
+                    let Some(message) = message else { return Ok(()) };
+                    self.handle_ui_message(message).await?;
+                }
+                event = events.next() => {
+                    let Some(event) = event else { return Ok(()) };
+                    if !self.handle_event(event).await? {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    async fn handle_ui_message(&mut self, message: UiMessage) -> Result<()> {
+        let message = match message {
+            UiMessage::JoinRoom { .. } => return Err(Error::ProtocolError),
+            UiMessage::Upload { path } => return self.upload(&path).await,
+            UiMessage::Download { blob_id, path } => {
+                self.downloads.insert(blob_id.clone(), path);
+                ClientMessage::Download { blob_id }
+            }
+            message => match to_client_message(message) {
+                Some(message) => message,
+                None => return Ok(()),
+            },
+        };
+
+        self.client.send(message).map_err(|_| Error::ServerError)
+    }
+
+    async fn upload(&self, path: &Path) -> Result<()> {
+        let name = path
+            .file_name()
+            .map_or(String::new(), |name| name.to_string_lossy().into_owned());
+
+        match fs::read(path).await {
+            Ok(data) => {
+                self.client
+                    .upload(&name, guess_mime(&name), &data)
                     .map_err(|_| Error::ServerError)?;
+                Ok(())
             }
+            Err(e) => self
+                .tx
+                .send(NetworkMessage::UploadFailed {
+                    name,
+                    reason: e.to_string(),
+                })
+                .map_err(|_| Error::ChannelClosed),
         }
     }
 
-    Ok(())
+    /// Returns whether more events will follow.
+    async fn handle_event(&mut self, event: Event) -> Result<bool> {
+        let message = match event {
+            Event::Server(message) => message,
+            Event::Reconnecting { attempt } => {
+                self.send_to_ui(NetworkMessage::Reconnecting { attempt })?;
+                return Ok(true);
+            }
+            Event::Disconnected { reason } => {
+                let reason = reason.map(|reason| reason.to_string());
+                self.send_to_ui(NetworkMessage::Disconnected { reason })?;
+                return Ok(false);
+            }
+        };
+
+        match message {
+            ServerMessage::DownloadChunk {
+                blob_id,
+                offset,
+                total,
+                data,
+            } => {
+                let buffer = self.received.entry(blob_id.clone()).or_default();
+                if offset != buffer.len() as u64 {
+                    self.received.remove(&blob_id);
+                    return Ok(true);
+                }
+                buffer.extend_from_slice(&data);
+                if (buffer.len() as u64) < total {
+                    return Ok(true);
+                }
+
+                let data = self.received.remove(&blob_id).unwrap_or_default();
+                let Some(path) = self.downloads.remove(&blob_id) else {
+                    return Ok(true);
+                };
+
+                let message = match save_download(&path, &data).await {
+                    Ok(()) => NetworkMessage::Downloaded { blob_id, path },
+                    Err(e) => NetworkMessage::DownloadFailed {
+                        reason: e.to_string(),
+                    },
+                };
+                self.send_to_ui(message)?;
+            }
+            ServerMessage::DownloadFailed { blob_id, reason } => {
+                self.received.remove(&blob_id);
+                self.downloads.remove(&blob_id);
+
+                let message = NetworkMessage::DownloadFailed {
+                    reason: reason.to_string(),
+                };
+                self.send_to_ui(message)?;
+            }
+            server_message => {
+                // Downloads in progress do not survive a reconnect.
+                if let ServerMessage::JoinAccepted { .. } = server_message {
+                    self.received.clear();
+                }
+                self.send_to_ui(NetworkMessage::ServerMessage(server_message))?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn send_to_ui(&self, message: NetworkMessage) -> Result<()> {
+        self.tx.send(message).map_err(|_| Error::ChannelClosed)
+    }
 }
 
-async fn write_to_ui(tx: UnboundedSender<NetworkMessage>, mut reader: OwnedReadHalf) -> Result<()> {
-    loop {
-        match read_msg::<_, ServerMessage>(&mut reader)
-            .await
-            .map_err(|_| Error::ServerError)?
-        {
-            ServerMessage::JoinAccepted {
-                history: _,
-                participants: _,
-            } => return Err(Error::ServerError),
-            server_message => tx
-                .send(NetworkMessage::ServerMessage(server_message))
-                .map_err(|_| Error::ChannelClosed)?,
+/// The request for a UI message that maps directly onto one. IDs that do not
+/// parse are dropped.
+fn to_client_message(message: UiMessage) -> Option<ClientMessage> {
+    let message = match message {
+        UiMessage::SendChat {
+            text,
+            reply_to,
+            attachments,
+        } => ClientMessage::Chat {
+            text: text.into(),
+            reply_to: reply_to.and_then(|id| id.parse().ok()),
+            attachments,
+        },
+        UiMessage::Typing { active } => ClientMessage::Typing { active },
+        UiMessage::EditMessage { id, text } => ClientMessage::EditMessage {
+            id: id.parse().ok()?,
+            text: text.into(),
+        },
+        UiMessage::DeleteMessage { id } => ClientMessage::DeleteMessage {
+            id: id.parse().ok()?,
+        },
+        UiMessage::React {
+            message_id,
+            emoji,
+            add,
+        } => {
+            let message_id = message_id.parse().ok()?;
+            let emoji = emoji.into();
+            if add {
+                ClientMessage::AddReaction { message_id, emoji }
+            } else {
+                ClientMessage::RemoveReaction { message_id, emoji }
+            }
+        }
+        UiMessage::MarkRead { seq } => ClientMessage::MarkRead { seq },
+        UiMessage::SetPresence { status, text } => ClientMessage::SetPresence {
+            status,
+            text: text.into(),
+        },
+        UiMessage::Kick { target, reason } => ClientMessage::Kick {
+            target: target.parse().ok()?,
+            reason: reason.into(),
+        },
+        UiMessage::Ban {
+            target,
+            duration,
+            reason,
+        } => ClientMessage::Ban {
+            target: target.parse().ok()?,
+            duration,
+            reason: reason.into(),
+        },
+        UiMessage::Mute {
+            target,
+            duration,
+            reason,
+        } => ClientMessage::Mute {
+            target: target.parse().ok()?,
+            duration,
+            reason: reason.into(),
+        },
+        UiMessage::SetRole { target, role } => ClientMessage::SetRole {
+            target: target.parse().ok()?,
+            role,
+        },
+        UiMessage::SetTopic { topic } => ClientMessage::SetTopic {
+            topic: topic.into(),
+        },
+        UiMessage::SetDescription { description } => ClientMessage::SetDescription {
+            description: description.into(),
+        },
+        UiMessage::Pin { id, pin } => {
+            let id = id.parse().ok()?;
+            if pin {
+                ClientMessage::PinMessage { id }
+            } else {
+                ClientMessage::UnpinMessage { id }
+            }
         }
+        UiMessage::Search {
+            query,
+            from,
+            before,
+            after,
+        } => ClientMessage::Search {
+            query: query.into(),
+            room: None,
+            from: from.map(Into::into),
+            before,
+            after,
+            limit: SEARCH_LIMIT,
+        },
+        UiMessage::JoinRoom { .. } | UiMessage::Upload { .. } | UiMessage::Download { .. } => {
+            return None;
+        }
+    };
+
+    Some(message)
+}
+
+fn guess_mime(name: &str) -> &'static str {
+    let extension = Path::new(name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+
+    match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        Some("txt" | "md") => "text/plain",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+async fn save_download(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
     }
+    fs::write(path, data).await
 }
@@ -0,0 +1,66 @@
+use std::{
+    cell::{Cell, RefCell},
+    time::{Duration, Instant},
+};
+
+use common::protocol::PresenceStatus;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::message::UiMessage;
+
+/// How long the user has to be idle before being marked as away.
+pub const AWAY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// Tracks the presence chosen by the user and switches to away while idle.
+///
+/// Only an `Online` status is changed automatically, so an explicit away or
+/// do-not-disturb choice is never overridden.
+pub struct PresenceTracker {
+    tx: UnboundedSender<UiMessage>,
+    status: Cell<PresenceStatus>,
+    text: RefCell<String>,
+    last_activity: Cell<Instant>,
+    auto_away: Cell<bool>,
+}
+
+impl PresenceTracker {
+    pub fn new(tx: UnboundedSender<UiMessage>) -> Self {
+        Self {
+            tx,
+            status: Cell::new(PresenceStatus::Online),
+            text: RefCell::new(String::new()),
+            last_activity: Cell::new(Instant::now()),
+            auto_away: Cell::new(false),
+        }
+    }
+
+    pub fn set(&self, status: PresenceStatus, text: String) {
+        self.status.set(status);
+        self.text.replace(text);
+        self.auto_away.set(false);
+        self.last_activity.set(Instant::now());
+        self.send(status);
+    }
+
+    pub fn record_activity(&self) {
+        self.last_activity.set(Instant::now());
+
+        if self.auto_away.replace(false) {
+            self.send(self.status.get());
+        }
+    }
+
+    pub fn check_idle(&self) {
+        let idle = self.last_activity.get().elapsed() >= AWAY_AFTER;
+
+        if idle && self.status.get() == PresenceStatus::Online && !self.auto_away.get() {
+            self.auto_away.set(true);
+            self.send(PresenceStatus::Away);
+        }
+    }
+
+    fn send(&self, status: PresenceStatus) {
+        let text = self.text.borrow().clone();
+        let _ = self.tx.send(UiMessage::SetPresence { status, text });
+    }
+}
@@ -0,0 +1,101 @@
+/// A search typed into the search panel. Besides plain words, the input
+/// may contain `from:<username>`, `before:<YYYY-MM-DD>` and
+/// `after:<YYYY-MM-DD>` filters. Neither date is included.
+#[derive(Debug, Default)]
+pub struct SearchQuery {
+    pub text: String,
+    pub from: Option<String>,
+    /// Unix time in seconds of midnight UTC on the given date.
+    pub before: Option<u64>,
+    /// Unix time in seconds of midnight UTC after the given date.
+    pub after: Option<u64>,
+}
+
+pub fn parse_query(input: &str) -> SearchQuery {
+    let mut query = SearchQuery::default();
+    let mut words = Vec::new();
+
+    for word in input.split_whitespace() {
+        if let Some(from) = word.strip_prefix("from:") {
+            let from = from.strip_prefix('@').unwrap_or(from);
+            query.from = (!from.is_empty()).then(|| from.to_string());
+        } else if let Some(before) = word.strip_prefix("before:")
+            && let Some(timestamp) = parse_date(before)
+        {
+            query.before = Some(timestamp);
+        } else if let Some(after) = word.strip_prefix("after:")
+            && let Some(timestamp) = parse_date(after)
+        {
+            query.after = Some(timestamp + 86_400);
+        } else {
+            words.push(word);
+        }
+    }
+    query.text = words.join(" ");
+
+    query
+}
+
+/// Formats a Unix time in seconds as `YYYY-MM-DD HH:MM` in UTC.
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86_400) as i64;
+    let seconds = timestamp % 86_400;
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3_600,
+        seconds % 3_600 / 60
+    )
+}
+
+fn parse_date(date: &str) -> Option<u64> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    u64::try_from(days).ok().map(|days| days * 86_400)
+}
+
+// Conversions between days since the Unix epoch and dates in the proleptic
+// Gregorian calendar, after Howard Hinnant's `days_from_civil` and
+// `civil_from_days`.
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
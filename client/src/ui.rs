@@ -1,6 +1,7 @@
+use common::protocol::{self, PresenceStatus};
 use slint::{ComponentHandle, Weak};
 
-use crate::{App, AppState, JoinLogic};
+use crate::{App, AppState, JoinLogic, ModerationAction, Role, Status};
 
 pub struct Ui {
     app: App,
@@ -21,19 +22,159 @@ impl Ui {
         self.app.as_weak()
     }
 
-    pub fn on_join<F: Fn(String, String) + 'static>(&self, f: F) {
+    pub fn on_join<F: Fn(String, String, String) + 'static>(&self, f: F) {
         self.app
             .global::<JoinLogic>()
-            .on_join_room(move |address, username| f(address.to_string(), username.to_string()));
+            .on_join_room(move |address, username, password| {
+                f(
+                    address.to_string(),
+                    username.to_string(),
+                    password.to_string(),
+                )
+            });
     }
 
-    pub fn on_send_message<F: Fn(String) + 'static>(&self, f: F) {
+    pub fn on_send_message<F: Fn(String, Option<String>) + 'static>(&self, f: F) {
         self.app
             .global::<AppState>()
-            .on_send_message(move |message| f(message.to_string()));
+            .on_send_message(move |message, reply_to| {
+                let reply_to = (!reply_to.is_empty()).then(|| reply_to.to_string());
+                f(message.to_string(), reply_to)
+            });
+    }
+
+    pub fn on_react<F: Fn(String, String, bool) + 'static>(&self, f: F) {
+        self.app
+            .global::<AppState>()
+            .on_react(move |message_id, emoji, add| {
+                f(message_id.to_string(), emoji.to_string(), add)
+            });
+    }
+
+    pub fn on_mark_read<F: Fn() + 'static>(&self, f: F) {
+        self.app.global::<AppState>().on_mark_read(f);
+    }
+
+    pub fn on_set_presence<F: Fn(PresenceStatus, String) + 'static>(&self, f: F) {
+        self.app
+            .global::<AppState>()
+            .on_set_presence(move |status, text| f(from_status(status), text.to_string()));
+    }
+
+    pub fn on_message_index<F: Fn(&str) -> Option<usize> + 'static>(&self, f: F) {
+        self.app
+            .global::<AppState>()
+            .on_message_index(move |id| f(id.as_str()).map_or(-1, |index| index as i32));
+    }
+
+    pub fn on_typing<F: Fn(bool) + 'static>(&self, f: F) {
+        self.app.global::<AppState>().on_typing(f);
+    }
+
+    pub fn on_edit_message<F: Fn(String, String) + 'static>(&self, f: F) {
+        self.app
+            .global::<AppState>()
+            .on_edit_message(move |id, text| f(id.to_string(), text.to_string()));
+    }
+
+    pub fn on_delete_message<F: Fn(String) + 'static>(&self, f: F) {
+        self.app
+            .global::<AppState>()
+            .on_delete_message(move |id| f(id.to_string()));
+    }
+
+    pub fn on_attach_file<F: Fn() + 'static>(&self, f: F) {
+        self.app.global::<AppState>().on_attach_file(f);
+    }
+
+    pub fn on_download<F: Fn(String) + 'static>(&self, f: F) {
+        self.app
+            .global::<AppState>()
+            .on_download(move |id| f(id.to_string()));
+    }
+
+    pub fn on_open_link<F: Fn(String) + 'static>(&self, f: F) {
+        self.app
+            .global::<AppState>()
+            .on_open_link(move |url| f(url.to_string()));
+    }
+
+    /// `minutes` is zero for actions without a duration or permanent ones.
+    pub fn on_moderate<F: Fn(String, ModerationAction, i32) + 'static>(&self, f: F) {
+        self.app
+            .global::<AppState>()
+            .on_moderate(move |user_id, action, minutes| f(user_id.to_string(), action, minutes));
+    }
+
+    pub fn on_set_role<F: Fn(String, protocol::Role) + 'static>(&self, f: F) {
+        self.app
+            .global::<AppState>()
+            .on_set_role(move |user_id, role| f(user_id.to_string(), from_role(role)));
+    }
+
+    pub fn on_complete_command<F: Fn(String) + 'static>(&self, f: F) {
+        self.app
+            .global::<AppState>()
+            .on_complete_command(move |text| f(text.to_string()));
+    }
+
+    pub fn on_set_room_info<F: Fn(String, String) + 'static>(&self, f: F) {
+        self.app
+            .global::<AppState>()
+            .on_set_room_info(move |topic, description| {
+                f(topic.to_string(), description.to_string())
+            });
+    }
+
+    pub fn on_pin_message<F: Fn(String, bool) + 'static>(&self, f: F) {
+        self.app
+            .global::<AppState>()
+            .on_pin_message(move |id, pin| f(id.to_string(), pin));
+    }
+
+    pub fn on_search<F: Fn(String) + 'static>(&self, f: F) {
+        self.app
+            .global::<AppState>()
+            .on_search(move |query| f(query.to_string()));
     }
 
     pub fn run(self) {
         self.app.run().unwrap();
     }
 }
+
+pub fn to_status(status: PresenceStatus) -> Status {
+    match status {
+        PresenceStatus::Online => Status::Online,
+        PresenceStatus::Away => Status::Away,
+        PresenceStatus::DoNotDisturb => Status::DoNotDisturb,
+    }
+}
+
+fn from_status(status: Status) -> PresenceStatus {
+    match status {
+        Status::Online => PresenceStatus::Online,
+        Status::Away => PresenceStatus::Away,
+        Status::DoNotDisturb => PresenceStatus::DoNotDisturb,
+    }
+}
+
+pub fn to_role(role: protocol::Role) -> Role {
+    match role {
+        protocol::Role::ReadOnly => Role::ReadOnly,
+        protocol::Role::Guest => Role::Guest,
+        protocol::Role::Member => Role::Member,
+        protocol::Role::Moderator => Role::Moderator,
+        protocol::Role::Owner => Role::Owner,
+    }
+}
+
+pub fn from_role(role: Role) -> protocol::Role {
+    match role {
+        Role::ReadOnly => protocol::Role::ReadOnly,
+        Role::Guest => protocol::Role::Guest,
+        Role::Member => protocol::Role::Member,
+        Role::Moderator => protocol::Role::Moderator,
+        Role::Owner => protocol::Role::Owner,
+    }
+}
@@ -1,2 +1,3 @@
+pub mod markup;
 pub mod protocol;
 pub mod uuid;
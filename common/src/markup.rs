@@ -0,0 +1,384 @@
+//! A small, safe subset of Markdown: `**bold**`, `*italic*` or `_italic_`,
+//! `` `inline code` ``, fenced code blocks and links. Everything else is
+//! kept as plain text, so the result never contains markup of its own.
+
+use std::{collections::HashMap, sync::Arc};
+
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+/// Link schemes that are turned into clickable links.
+const LINK_SCHEMES: [&str; 3] = ["https://", "http://", "mailto:"];
+
+const ESCAPABLE: [char; 7] = ['\\', '*', '_', '`', '[', ']', '('];
+
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Block {
+    Paragraph(Vec<Span>),
+    Code { language: Arc<str>, code: Arc<str> },
+}
+
+/// A run of text sharing the same style.
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Span {
+    pub text: Arc<str>,
+    pub bold: bool,
+    pub italic: bool,
+    pub code: bool,
+    pub link: Option<Arc<str>>,
+}
+
+impl Span {
+    fn plain(text: &str) -> Self {
+        Self {
+            text: text.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Splits `text` into paragraphs and fenced code blocks. Paragraphs are
+/// separated by blank lines; single line breaks are kept.
+pub fn parse(text: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut paragraph = Vec::new();
+    let mut lines = text.lines();
+
+    while let Some(line) = lines.next() {
+        if let Some(language) = code_fence(line) {
+            push_paragraph(&mut blocks, &mut paragraph);
+
+            // An unterminated fence runs to the end of the message.
+            let code: Vec<_> = lines
+                .by_ref()
+                .take_while(|line| code_fence(line).is_none_or(|rest| !rest.is_empty()))
+                .collect();
+            blocks.push(Block::Code {
+                language: language.into(),
+                code: code.join("\n").into(),
+            });
+        } else if line.trim().is_empty() {
+            push_paragraph(&mut blocks, &mut paragraph);
+        } else {
+            paragraph.push(line);
+        }
+    }
+    push_paragraph(&mut blocks, &mut paragraph);
+
+    blocks
+}
+
+/// Returns the info string after an opening or closing ```` ``` ```` fence.
+fn code_fence(line: &str) -> Option<&str> {
+    let rest = line.trim().strip_prefix("```")?;
+    (!rest.contains('`')).then(|| rest.trim())
+}
+
+fn push_paragraph(blocks: &mut Vec<Block>, lines: &mut Vec<&str>) {
+    if !lines.is_empty() {
+        blocks.push(Block::Paragraph(parse_inline(&lines.join("\n"))));
+        lines.clear();
+    }
+}
+
+fn parse_inline(text: &str) -> Vec<Span> {
+    let chars: Vec<char> = text.chars().collect();
+    let ahead = Lookahead::new(&chars);
+    let mut inline = Inline::default();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let previous = i.checked_sub(1).map(|i| chars[i]);
+
+        match c {
+            '\\' if next.is_some_and(|next| ESCAPABLE.contains(&next)) => {
+                inline.push(chars[i + 1]);
+                i += 2;
+            }
+            '`' => {
+                let run = chars[i..].iter().take_while(|c| **c == '`').count();
+                match ahead.backticks(i + run, run) {
+                    Some(end) => {
+                        let code: String = chars[i + run..end].iter().collect();
+                        inline.push_code(&code);
+                        i = end + run;
+                    }
+                    None => {
+                        chars[i..i + run].iter().for_each(|c| inline.push(*c));
+                        i += run;
+                    }
+                }
+            }
+            '*' if next == Some('*') => {
+                if inline.bold || ahead.has_double_star(i + 2) {
+                    inline.bold = !inline.bold;
+                    inline.after_word = false;
+                } else {
+                    inline.push('*');
+                    inline.push('*');
+                }
+                i += 2;
+            }
+            '*' | '_' => {
+                // Underscores inside words, as in snake_case, are not emphasis.
+                let word_char = |c: Option<char>| c.is_some_and(char::is_alphanumeric);
+                let can_open = next.is_some_and(|next| !next.is_whitespace())
+                    && (c == '*' || !word_char(previous));
+                let can_close = previous.is_some_and(|previous| !previous.is_whitespace())
+                    && (c == '*' || !word_char(next));
+
+                if inline.italic == Some(c) && can_close {
+                    inline.italic = None;
+                    inline.after_word = false;
+                } else if inline.italic.is_none() && can_open && ahead.has_closing(i + 1, c) {
+                    inline.italic = Some(c);
+                    inline.after_word = false;
+                } else {
+                    inline.push(c);
+                }
+                i += 1;
+            }
+            '[' => match parse_link(&chars, &ahead, i) {
+                Some((label, url, end)) => {
+                    inline.push_link(&label, &url);
+                    i = end;
+                }
+                None => {
+                    inline.push(c);
+                    i += 1;
+                }
+            },
+            // Checking the word first keeps a long run of letters from being
+            // scanned again for every scheme inside it.
+            _ => match (!inline.after_word)
+                .then(|| bare_link(&chars[i..]))
+                .flatten()
+            {
+                Some(url) => {
+                    inline.push_link(&url, &url);
+                    i += url.chars().count();
+                }
+                None => {
+                    inline.push(c);
+                    i += 1;
+                }
+            },
+        }
+    }
+    inline.flush();
+
+    inline.spans
+}
+
+#[derive(Default)]
+struct Inline {
+    spans: Vec<Span>,
+    text: String,
+    /// The style `text` was written in. `bold` and `italic` may have moved
+    /// on since, but the text is only flushed once more text follows, so
+    /// neighbouring spans never share a style.
+    text_bold: bool,
+    text_italic: bool,
+    bold: bool,
+    italic: Option<char>,
+    /// Whether the last character written since the style changed is a
+    /// letter or digit, in which case a link can't start here.
+    after_word: bool,
+}
+
+impl Inline {
+    fn push(&mut self, c: char) {
+        let style = (self.bold, self.italic.is_some());
+        if (self.text_bold, self.text_italic) != style {
+            self.flush();
+            (self.text_bold, self.text_italic) = style;
+        }
+        self.text.push(c);
+        self.after_word = c.is_alphanumeric();
+    }
+
+    fn flush(&mut self) {
+        if self.text.is_empty() {
+            return;
+        }
+
+        self.spans.push(Span {
+            bold: self.text_bold,
+            italic: self.text_italic,
+            ..Span::plain(&self.text)
+        });
+        self.text.clear();
+    }
+
+    fn push_code(&mut self, code: &str) {
+        self.flush();
+        self.after_word = false;
+
+        // A single space on both sides allows the code to start or end with
+        // a backtick.
+        let code = match code
+            .strip_prefix(' ')
+            .and_then(|code| code.strip_suffix(' '))
+        {
+            Some(trimmed) if !trimmed.is_empty() => trimmed,
+            _ => code,
+        };
+        self.spans.push(Span {
+            code: true,
+            ..Span::plain(code)
+        });
+    }
+
+    fn push_link(&mut self, label: &str, url: &str) {
+        self.flush();
+        self.after_word = false;
+        self.spans.push(Span {
+            bold: self.bold,
+            italic: self.italic.is_some(),
+            link: Some(url.into()),
+            ..Span::plain(label)
+        });
+    }
+}
+
+/// Where the delimiters of a paragraph are, found in one pass so that the
+/// parser never scans ahead for a closing delimiter. Each `next_*` table
+/// holds, for every position, the position of the next such character at or
+/// after it, or the length of the text if there is none.
+struct Lookahead {
+    next_bracket: Vec<usize>,
+    next_paren: Vec<usize>,
+    next_space: Vec<usize>,
+    next_non_space: Vec<usize>,
+    /// Whether a closing `*` or `_` lies at or after each position.
+    star_closing: Vec<bool>,
+    underscore_closing: Vec<bool>,
+    /// Start of the last `**`.
+    last_double_star: Option<usize>,
+    /// Starts of the runs of backticks, by run length.
+    backtick_runs: HashMap<usize, Vec<usize>>,
+}
+
+impl Lookahead {
+    fn new(chars: &[char]) -> Self {
+        let len = chars.len();
+        let mut ahead = Self {
+            next_bracket: vec![len; len + 1],
+            next_paren: vec![len; len + 1],
+            next_space: vec![len; len + 1],
+            next_non_space: vec![len; len + 1],
+            star_closing: vec![false; len + 1],
+            underscore_closing: vec![false; len + 1],
+            last_double_star: chars.windows(2).rposition(|pair| pair == ['*', '*']),
+            backtick_runs: HashMap::new(),
+        };
+
+        for i in (0..len).rev() {
+            let c = chars[i];
+            let after = |table: &[usize], found: bool| if found { i } else { table[i + 1] };
+            ahead.next_bracket[i] = after(&ahead.next_bracket, c == ']');
+            ahead.next_paren[i] = after(&ahead.next_paren, c == ')');
+            ahead.next_space[i] = after(&ahead.next_space, c.is_whitespace());
+            ahead.next_non_space[i] = after(&ahead.next_non_space, !c.is_whitespace());
+            ahead.star_closing[i] = ahead.star_closing[i + 1] || is_closing(chars, i, '*');
+            ahead.underscore_closing[i] =
+                ahead.underscore_closing[i + 1] || is_closing(chars, i, '_');
+        }
+
+        let mut i = 0;
+        while i < len {
+            let run = chars[i..].iter().take_while(|c| **c == '`').count();
+            if run > 0 {
+                ahead.backtick_runs.entry(run).or_default().push(i);
+            }
+            i += run.max(1);
+        }
+
+        ahead
+    }
+
+    /// Position of the next run of exactly `run` backticks at or after `from`.
+    fn backticks(&self, from: usize, run: usize) -> Option<usize> {
+        let starts = self.backtick_runs.get(&run)?;
+        starts
+            .get(starts.partition_point(|start| *start < from))
+            .copied()
+    }
+
+    fn has_double_star(&self, from: usize) -> bool {
+        self.last_double_star.is_some_and(|last| last >= from)
+    }
+
+    /// Whether a closing single `marker` follows `from`.
+    fn has_closing(&self, from: usize, marker: char) -> bool {
+        let closing = match marker {
+            '*' => &self.star_closing,
+            _ => &self.underscore_closing,
+        };
+        closing.get(from + 1).copied().unwrap_or(false)
+    }
+}
+
+/// Whether the `marker` at `i` can close emphasis.
+fn is_closing(chars: &[char], i: usize, marker: char) -> bool {
+    i > 0
+        && chars[i] == marker
+        && !chars[i - 1].is_whitespace()
+        && chars.get(i + 1) != Some(&marker)
+        && (marker == '*' || !chars.get(i + 1).is_some_and(|c| c.is_alphanumeric()))
+}
+
+/// Parses `[label](url)` starting at `start`, returning the label, the URL
+/// and the position after the closing parenthesis. Links with other schemes
+/// are left as text.
+fn parse_link(chars: &[char], ahead: &Lookahead, start: usize) -> Option<(String, String, usize)> {
+    let label_end = ahead.next_bracket[start + 1];
+    if label_end == start + 1 || chars.get(label_end + 1) != Some(&'(') {
+        return None;
+    }
+    let url_start = label_end + 2;
+    let url_end = ahead.next_paren[url_start];
+    if url_end == chars.len() {
+        return None;
+    }
+
+    // The URL may be padded with whitespace but not contain any.
+    let url_first = ahead.next_non_space[url_start].min(url_end);
+    let space = ahead.next_space[url_first];
+    if space < url_end && ahead.next_non_space[space] < url_end {
+        return None;
+    }
+    let url = &chars[url_first..space.min(url_end)];
+    if !starts_with_link(url) {
+        return None;
+    }
+
+    let label: String = chars[start + 1..label_end].iter().collect();
+    Some((label, url.iter().collect(), url_end + 1))
+}
+
+fn starts_with_link(chars: &[char]) -> bool {
+    LINK_SCHEMES.iter().any(|scheme| {
+        scheme.len() < chars.len()
+            && scheme
+                .chars()
+                .zip(chars)
+                .all(|(expected, c)| expected == c.to_ascii_lowercase())
+    })
+}
+
+/// Takes a URL up to the next whitespace, leaving out trailing punctuation.
+fn bare_link(chars: &[char]) -> Option<String> {
+    if !starts_with_link(chars) {
+        return None;
+    }
+
+    let url: String = chars.iter().take_while(|c| !c.is_whitespace()).collect();
+    let url = url.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '\'', '"']);
+    LINK_SCHEMES
+        .iter()
+        .all(|scheme| !scheme.eq_ignore_ascii_case(url))
+        .then(|| url.to_string())
+}
@@ -1,38 +1,526 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use bincode::{Decode, Encode, config};
 use bytes::{BufMut, Bytes, BytesMut};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
-use crate::uuid::Uid;
+use crate::{
+    markup::{self, Block},
+    uuid::Uid,
+};
 
-#[derive(Debug, Clone, Encode, Decode)]
+/// Maximum payload of a single upload or download chunk.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
 pub struct ChatMessage {
+    pub id: Uid,
+    /// Position of the message in the room's history, assigned by the server.
+    pub seq: u64,
     pub from: Uid,
+    /// Account of the author, set by the server when they joined with its
+    /// password. Unlike `from`, which is a connection ID, it stays the same
+    /// when the author reconnects, so they can still edit and delete the
+    /// message.
+    pub author: Option<Arc<str>>,
     pub text: Arc<str>,
+    /// `text` parsed as rich text, so that all clients render it alike.
+    pub body: Vec<Block>,
     pub timestamp: u64,
+    pub edited: bool,
+    pub deleted: bool,
+    pub reply_to: Option<Uid>,
+    pub reactions: Vec<Reaction>,
+    /// Participants mentioned with `@username`, resolved by the server.
+    pub mentions: Vec<Uid>,
+    pub attachments: Vec<Attachment>,
+    /// Sent with `/me`; shown as an action of the author.
+    pub emote: bool,
+    /// Name of the bot that posted the message, set by the server for bot
+    /// accounts and incoming webhooks. A webhook's bot is not a participant.
+    pub bot: Option<Arc<str>>,
+}
+
+impl ChatMessage {
+    pub fn new(from: Uid, text: Arc<str>) -> Self {
+        Self {
+            id: Uid::new(),
+            seq: 0,
+            from,
+            author: None,
+            body: markup::parse(&text),
+            text,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            edited: false,
+            deleted: false,
+            reply_to: None,
+            reactions: Vec::new(),
+            mentions: Vec::new(),
+            attachments: Vec::new(),
+            emote: false,
+            bot: None,
+        }
+    }
+}
+
+/// A file stored on the server, referenced from a chat message.
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+pub struct Attachment {
+    pub blob_id: Uid,
+    pub name: Arc<str>,
+    pub mime: Arc<str>,
+    pub size: u64,
+}
+
+impl Attachment {
+    pub fn is_image(&self) -> bool {
+        self.mime.starts_with("image/")
+    }
+}
+
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+pub struct Reaction {
+    pub emoji: Arc<str>,
+    pub users: Vec<Uid>,
+}
+
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PresenceStatus {
+    #[default]
+    Online,
+    Away,
+    DoNotDisturb,
+}
+
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Presence {
+    pub status: PresenceStatus,
+    /// Custom status text, empty when unset.
+    pub text: Arc<str>,
+}
+
+/// Roles ordered from least to most privileged.
+#[derive(
+    Encode,
+    Decode,
+    Serialize,
+    Deserialize,
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    ReadOnly,
+    Guest,
+    #[default]
+    Member,
+    Moderator,
+    Owner,
+}
+
+impl Role {
+    pub const ALL: [Role; 5] = [
+        Role::ReadOnly,
+        Role::Guest,
+        Role::Member,
+        Role::Moderator,
+        Role::Owner,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::ReadOnly => "read-only",
+            Role::Guest => "guest",
+            Role::Member => "member",
+            Role::Moderator => "moderator",
+            Role::Owner => "owner",
+        }
+    }
+
+    /// Permissions of the role in rooms that do not override them.
+    pub fn default_permissions(self) -> Vec<Permission> {
+        use Permission::*;
+
+        match self {
+            Role::ReadOnly => vec![],
+            Role::Guest => vec![SendMessages, React, EditMessages, DeleteMessages],
+            Role::Member => vec![SendMessages, React, EditMessages, DeleteMessages, Invite],
+            Role::Moderator => vec![
+                SendMessages,
+                React,
+                EditMessages,
+                DeleteMessages,
+                Invite,
+                DeleteOthersMessages,
+                Moderate,
+                ManageRoom,
+            ],
+            Role::Owner => Permission::ALL.to_vec(),
+        }
+    }
+}
+
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum Permission {
+    /// Also covers uploading attachments.
+    SendMessages,
+    React,
+    /// Editing one's own messages.
+    EditMessages,
+    /// Deleting one's own messages.
+    DeleteMessages,
+    DeleteOthersMessages,
+    /// Kicking, banning and muting participants with a lower role.
+    Moderate,
+    /// Promoting guests to members.
+    Invite,
+    /// Assigning any role below one's own.
+    ManageRoles,
+    /// Changing the room's topic and description and pinning messages.
+    ManageRoom,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 9] = [
+        Permission::SendMessages,
+        Permission::React,
+        Permission::EditMessages,
+        Permission::DeleteMessages,
+        Permission::DeleteOthersMessages,
+        Permission::Moderate,
+        Permission::Invite,
+        Permission::ManageRoles,
+        Permission::ManageRoom,
+    ];
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+/// Metadata shown in the header of a room.
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RoomInfo {
+    pub topic: Arc<str>,
+    pub description: Arc<str>,
+    /// Pinned messages in the order they were pinned.
+    pub pins: Vec<Uid>,
+}
+
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone)]
+pub struct SearchResult {
+    pub id: Uid,
+    pub seq: u64,
+    /// Username of the author when the message was sent.
+    pub author: Arc<str>,
+    pub timestamp: u64,
+    pub text: Arc<str>,
+}
+
+/// A slash command as advertised to clients for autocompletion.
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone)]
+pub struct CommandInfo {
+    /// Name without the leading slash.
+    pub name: Arc<str>,
+    pub description: Arc<str>,
+    pub arguments: Vec<ArgumentInfo>,
+    /// Permission needed to run the command, if any.
+    pub permission: Option<Permission>,
+}
+
+impl CommandInfo {
+    /// Usage line such as `/kick <user> [reason]`.
+    pub fn usage(&self) -> String {
+        let mut usage = format!("/{}", self.name);
+        for argument in &self.arguments {
+            if argument.required {
+                usage.push_str(&format!(" <{}>", argument.name));
+            } else {
+                usage.push_str(&format!(" [{}]", argument.name));
+            }
+        }
+        usage
+    }
+}
+
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone)]
+pub struct ArgumentInfo {
+    pub name: Arc<str>,
+    pub kind: ArgumentKind,
+    pub required: bool,
+}
+
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgumentKind {
+    /// A single word.
+    Word,
+    /// A participant's username, optionally prefixed with `@`.
+    User,
+    /// A duration such as `30s`, `10m`, `2h` or `1d`.
+    Duration,
+    /// The rest of the line.
+    Text,
+}
+
+/// How messages are encoded on the wire. A client picks one in its join
+/// request and may send frames in either.
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Bincode,
+    Json,
+}
+
+impl Encoding {
+    /// Tells the encoding of an encoded message. Bincode starts with the
+    /// variant index, which is always far below `{`, while every JSON message
+    /// is an object.
+    pub fn detect(payload: &[u8]) -> Self {
+        if payload.first() == Some(&b'{') {
+            Encoding::Json
+        } else {
+            Encoding::Bincode
+        }
+    }
+}
+
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone)]
+pub struct ParticipantInfo {
+    pub uuid: Uid,
+    pub username: Arc<str>,
+    pub presence: Presence,
+    pub role: Role,
+}
+
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone)]
 pub enum ClientMessage {
-    Chat { text: Arc<str> },
-    JoinRequest { username: Arc<str> },
+    Chat {
+        text: Arc<str>,
+        reply_to: Option<Uid>,
+        /// Blobs uploaded beforehand, referenced by ID.
+        attachments: Vec<Uid>,
+    },
+    /// The first message of a session. Its `encoding` is used for all
+    /// messages the server sends afterwards.
+    JoinRequest {
+        username: Arc<str>,
+        encoding: Encoding,
+        /// Joins as a bot account, whose messages are marked as such.
+        #[serde(default)]
+        bot: bool,
+        /// Proves the right to a registered username, or registers the name
+        /// if it has no password yet. Roles above the room's default only go
+        /// to names joined with their password.
+        #[serde(default)]
+        password: Option<Arc<str>>,
+    },
+    Rename {
+        username: Arc<str>,
+    },
+    Typing {
+        active: bool,
+    },
+    EditMessage {
+        id: Uid,
+        text: Arc<str>,
+    },
+    DeleteMessage {
+        id: Uid,
+    },
+    AddReaction {
+        message_id: Uid,
+        emoji: Arc<str>,
+    },
+    RemoveReaction {
+        message_id: Uid,
+        emoji: Arc<str>,
+    },
+    MarkRead {
+        seq: u64,
+    },
+    SetPresence {
+        status: PresenceStatus,
+        text: Arc<str>,
+    },
+    /// Starts an upload. The ID is chosen by the client and used to correlate
+    /// the following chunks and the server's reply.
+    BeginUpload {
+        upload_id: Uid,
+        name: Arc<str>,
+        mime: Arc<str>,
+        size: u64,
+    },
+    UploadChunk {
+        upload_id: Uid,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    FinishUpload {
+        upload_id: Uid,
+        sha256: [u8; 32],
+    },
+    Download {
+        blob_id: Uid,
+    },
+    Kick {
+        target: Uid,
+        reason: Arc<str>,
+    },
+    /// Bans the target's username and address. The duration is in seconds;
+    /// `None` bans permanently.
+    Ban {
+        target: Uid,
+        duration: Option<u64>,
+        reason: Arc<str>,
+    },
+    Mute {
+        target: Uid,
+        duration: Option<u64>,
+        reason: Arc<str>,
+    },
+    SetRole {
+        target: Uid,
+        role: Role,
+    },
+    SetTopic {
+        topic: Arc<str>,
+    },
+    SetDescription {
+        description: Arc<str>,
+    },
+    PinMessage {
+        id: Uid,
+    },
+    UnpinMessage {
+        id: Uid,
+    },
+    /// Searches the history for messages containing every word of `query`.
+    /// An empty query matches all messages that pass the filters.
+    Search {
+        query: Arc<str>,
+        /// Room to search. A server hosts a single room, which finds
+        /// nothing when another one is named.
+        room: Option<Arc<str>>,
+        /// Account of the author, or the name they used if they have none.
+        from: Option<Arc<str>>,
+        /// Unix time in seconds; only older messages match.
+        before: Option<u64>,
+        /// Unix time in seconds; only messages sent at or after it match.
+        after: Option<u64>,
+        /// Maximum number of results, capped by the server.
+        limit: u32,
+    },
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone)]
 pub enum ServerMessage {
     Chat(Arc<ChatMessage>),
     JoinAccepted {
+        uuid: Uid,
         history: Vec<Arc<ChatMessage>>,
-        participants: Vec<(Uid, Arc<str>)>,
+        participants: Vec<ParticipantInfo>,
+        last_read: u64,
+        /// The room's permission set for each role.
+        permissions: Vec<(Role, Vec<Permission>)>,
+        commands: Vec<CommandInfo>,
+        room: RoomInfo,
     },
     UserJoined {
         uuid: Uid,
         username: Arc<str>,
+        role: Role,
     },
     UserLeft {
         uuid: Uid,
     },
+    UserRenamed {
+        uuid: Uid,
+        username: Arc<str>,
+    },
+    TypingState {
+        typing: Vec<Uid>,
+    },
+    MessageEdited {
+        id: Uid,
+        text: Arc<str>,
+        body: Vec<Block>,
+        mentions: Vec<Uid>,
+    },
+    MessageDeleted {
+        id: Uid,
+    },
+    ReactionsUpdated {
+        message_id: Uid,
+        reactions: Vec<Reaction>,
+    },
+    /// Last read sequence number per participant. Sent in full after joining
+    /// and as individual updates afterwards.
+    ReadReceipts {
+        receipts: Vec<(Uid, u64)>,
+    },
+    PresenceChanged {
+        uuid: Uid,
+        presence: Presence,
+    },
+    RoleChanged {
+        uuid: Uid,
+        role: Role,
+    },
+    /// Newest results first.
+    SearchResults {
+        query: Arc<str>,
+        results: Vec<SearchResult>,
+    },
+    /// Sent whenever the topic, description or pins change.
+    RoomInfoChanged {
+        room: RoomInfo,
+    },
+    UploadComplete {
+        upload_id: Uid,
+        attachment: Attachment,
+    },
+    UploadFailed {
+        upload_id: Uid,
+        reason: Arc<str>,
+    },
+    DownloadChunk {
+        blob_id: Uid,
+        offset: u64,
+        total: u64,
+        data: Vec<u8>,
+    },
+    DownloadFailed {
+        blob_id: Uid,
+        reason: Arc<str>,
+    },
+    /// A notice from the server, shown as a system message.
+    System {
+        text: Arc<str>,
+    },
+    /// Output of a slash command, sent only to the participant who ran it.
+    CommandReply {
+        text: Arc<str>,
+    },
+    /// Sent in place of `JoinAccepted` when the join is refused.
+    JoinRejected {
+        reason: Arc<str>,
+    },
+    /// Sent right before the server closes the connection.
+    Disconnected {
+        reason: Arc<str>,
+    },
 }
 
 pub async fn write_msg<W: AsyncWrite + Unpin, M: Encode>(
@@ -50,7 +538,7 @@ pub async fn write_msg<W: AsyncWrite + Unpin, M: Encode>(
 pub async fn read_msg<R, M>(reader: &mut R) -> io::Result<M>
 where
     R: AsyncRead + Unpin,
-    M: Decode<()>,
+    M: Decode<()> + DeserializeOwned,
 {
     let mut len_buf = [0u8; 4];
     reader.read_exact(&mut len_buf).await?;
@@ -59,19 +547,30 @@ where
     let mut data = vec![0u8; len];
     reader.read_exact(&mut data).await?;
 
-    let config = config::standard();
-
-    let (message, _) = bincode::decode_from_slice(&data, config)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-
-    Ok(message)
+    decode_message(&data)
 }
 
-pub async fn encode_message<T: Encode>(message: &T) -> io::Result<Bytes> {
-    let config = config::standard();
+/// Decodes a message without the length prefix, as carried in a WebSocket
+/// frame, in whichever encoding it was sent.
+pub fn decode_message<M: Decode<()> + DeserializeOwned>(data: &[u8]) -> io::Result<M> {
+    match Encoding::detect(data) {
+        Encoding::Bincode => {
+            let (message, _) = bincode::decode_from_slice(data, config::standard())
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            Ok(message)
+        }
+        Encoding::Json => serde_json::from_slice(data).map_err(io::Error::from),
+    }
+}
 
-    let data = bincode::encode_to_vec(message, config)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+/// Encodes `message` with a length prefix, ready to be written to a
+/// connection.
+pub fn encode_frame<T: Encode + Serialize>(message: &T, encoding: Encoding) -> io::Result<Bytes> {
+    let data = match encoding {
+        Encoding::Bincode => bincode::encode_to_vec(message, config::standard())
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?,
+        Encoding::Json => serde_json::to_vec(message)?,
+    };
 
     let mut buf = BytesMut::with_capacity(4 + data.len());
 
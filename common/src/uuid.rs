@@ -1,7 +1,12 @@
+use std::str::FromStr;
+
 use bincode::{BorrowDecode, Decode, Encode, de::read::Reader, enc::write::Writer};
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Clone, Debug, PartialEq, Hash, Eq)]
+/// Encoded as 16 raw bytes by bincode and as a hyphenated string in JSON.
+#[derive(Clone, Debug, PartialEq, Hash, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct Uid(pub Uuid);
 
 impl Uid {
@@ -14,6 +19,14 @@ impl Uid {
     }
 }
 
+impl FromStr for Uid {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Uuid::parse_str(s).map(Self)
+    }
+}
+
 impl Encode for Uid {
     fn encode<E: bincode::enc::Encoder>(
         &self,
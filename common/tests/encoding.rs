@@ -0,0 +1,451 @@
+//! Conformance tests for the wire encodings: every message must survive a
+//! round trip through bincode and JSON and come back the same either way.
+
+use std::{collections::BTreeSet, fmt::Debug, sync::Arc};
+
+use bincode::{Decode, Encode};
+use common::{
+    markup,
+    protocol::{
+        ArgumentInfo, ArgumentKind, Attachment, ChatMessage, ClientMessage, CommandInfo, Encoding,
+        ParticipantInfo, Permission, Presence, PresenceStatus, Reaction, Role, RoomInfo,
+        SearchResult, ServerMessage, decode_message, encode_frame,
+    },
+    uuid::Uid,
+};
+use serde::{Serialize, de::DeserializeOwned};
+
+const ENCODINGS: [Encoding; 2] = [Encoding::Bincode, Encoding::Json];
+
+/// Encodes and decodes `message`, checking the length prefix on the way.
+fn round_trip<M>(message: &M, encoding: Encoding) -> M
+where
+    M: Encode + Decode<()> + Serialize + DeserializeOwned,
+{
+    let frame = encode_frame(message, encoding).expect("message encodes");
+    let (prefix, payload) = frame.split_at(4);
+    assert_eq!(
+        u32::from_be_bytes(prefix.try_into().unwrap()) as usize,
+        payload.len()
+    );
+    assert_eq!(Encoding::detect(payload), encoding);
+
+    decode_message(payload).expect("message decodes")
+}
+
+fn assert_round_trips<M>(message: &M)
+where
+    M: Encode + Decode<()> + Serialize + DeserializeOwned + Debug,
+{
+    let expected = format!("{:?}", message);
+    for encoding in ENCODINGS {
+        let decoded = format!("{:?}", round_trip(message, encoding));
+        assert_eq!(
+            decoded, expected,
+            "{:?} round trip changed the message",
+            encoding
+        );
+    }
+}
+
+fn chat_message() -> ChatMessage {
+    ChatMessage {
+        seq: 42,
+        author: Some("alice".into()),
+        edited: true,
+        reply_to: Some(Uid::new()),
+        reactions: vec![Reaction {
+            emoji: "👍".into(),
+            users: vec![Uid::new(), Uid::new()],
+        }],
+        mentions: vec![Uid::new()],
+        attachments: vec![attachment()],
+        emote: true,
+        bot: Some("CI".into()),
+        ..ChatMessage::new(
+            Uid::new(),
+            "**Hello** _there_, see [docs](https://example.com)\n```rust\nfn main() {}\n```".into(),
+        )
+    }
+}
+
+fn attachment() -> Attachment {
+    Attachment {
+        blob_id: Uid::new(),
+        name: "photo \"1\".png".into(),
+        mime: "image/png".into(),
+        size: u64::MAX,
+    }
+}
+
+fn presence() -> Presence {
+    Presence {
+        status: PresenceStatus::DoNotDisturb,
+        text: "In a meeting 📅".into(),
+    }
+}
+
+fn room_info() -> RoomInfo {
+    RoomInfo {
+        topic: "Release planning".into(),
+        description: "Line one\nLine two".into(),
+        pins: vec![Uid::new(), Uid::new()],
+    }
+}
+
+fn client_messages() -> Vec<ClientMessage> {
+    vec![
+        ClientMessage::Chat {
+            text: "hi".into(),
+            reply_to: Some(Uid::new()),
+            attachments: vec![Uid::new()],
+        },
+        ClientMessage::JoinRequest {
+            username: "alice".into(),
+            encoding: Encoding::Json,
+            bot: true,
+            password: Some("hunter2".into()),
+        },
+        ClientMessage::Rename {
+            username: "bob".into(),
+        },
+        ClientMessage::Typing { active: true },
+        ClientMessage::EditMessage {
+            id: Uid::new(),
+            text: "edited".into(),
+        },
+        ClientMessage::DeleteMessage { id: Uid::new() },
+        ClientMessage::AddReaction {
+            message_id: Uid::new(),
+            emoji: "🎉".into(),
+        },
+        ClientMessage::RemoveReaction {
+            message_id: Uid::new(),
+            emoji: "🎉".into(),
+        },
+        ClientMessage::MarkRead { seq: 7 },
+        ClientMessage::SetPresence {
+            status: PresenceStatus::Away,
+            text: String::new().into(),
+        },
+        ClientMessage::BeginUpload {
+            upload_id: Uid::new(),
+            name: "notes.txt".into(),
+            mime: "text/plain".into(),
+            size: 1024,
+        },
+        ClientMessage::UploadChunk {
+            upload_id: Uid::new(),
+            offset: 512,
+            data: vec![0, 1, 2, 123, 255],
+        },
+        ClientMessage::FinishUpload {
+            upload_id: Uid::new(),
+            sha256: [0xab; 32],
+        },
+        ClientMessage::Download {
+            blob_id: Uid::new(),
+        },
+        ClientMessage::Kick {
+            target: Uid::new(),
+            reason: "spam".into(),
+        },
+        ClientMessage::Ban {
+            target: Uid::new(),
+            duration: None,
+            reason: String::new().into(),
+        },
+        ClientMessage::Mute {
+            target: Uid::new(),
+            duration: Some(600),
+            reason: "cool down".into(),
+        },
+        ClientMessage::SetRole {
+            target: Uid::new(),
+            role: Role::Moderator,
+        },
+        ClientMessage::SetTopic {
+            topic: "New topic".into(),
+        },
+        ClientMessage::SetDescription {
+            description: "New description".into(),
+        },
+        ClientMessage::PinMessage { id: Uid::new() },
+        ClientMessage::UnpinMessage { id: Uid::new() },
+        ClientMessage::Search {
+            query: "release notes".into(),
+            room: Some("general".into()),
+            from: Some("alice".into()),
+            before: Some(1_700_000_000),
+            after: Some(1_600_000_000),
+            limit: 50,
+        },
+    ]
+}
+
+fn server_messages() -> Vec<ServerMessage> {
+    let message = chat_message();
+
+    vec![
+        ServerMessage::Chat(Arc::new(message.clone())),
+        ServerMessage::JoinAccepted {
+            uuid: Uid::new(),
+            history: vec![Arc::new(message.clone())],
+            participants: vec![ParticipantInfo {
+                uuid: Uid::new(),
+                username: "alice".into(),
+                presence: presence(),
+                role: Role::Owner,
+            }],
+            last_read: 41,
+            permissions: Role::ALL
+                .iter()
+                .map(|role| (*role, role.default_permissions()))
+                .collect(),
+            commands: vec![CommandInfo {
+                name: "kick".into(),
+                description: "Remove a participant".into(),
+                arguments: vec![
+                    ArgumentInfo {
+                        name: "user".into(),
+                        kind: ArgumentKind::User,
+                        required: true,
+                    },
+                    ArgumentInfo {
+                        name: "reason".into(),
+                        kind: ArgumentKind::Text,
+                        required: false,
+                    },
+                ],
+                permission: Some(Permission::Moderate),
+            }],
+            room: room_info(),
+        },
+        ServerMessage::UserJoined {
+            uuid: Uid::new(),
+            username: "carol".into(),
+            role: Role::Guest,
+        },
+        ServerMessage::UserLeft { uuid: Uid::new() },
+        ServerMessage::UserRenamed {
+            uuid: Uid::new(),
+            username: "dave".into(),
+        },
+        ServerMessage::TypingState {
+            typing: vec![Uid::new()],
+        },
+        ServerMessage::MessageEdited {
+            id: message.id.clone(),
+            text: message.text.clone(),
+            body: markup::parse("*edited* `code`"),
+            mentions: vec![Uid::new()],
+        },
+        ServerMessage::MessageDeleted { id: Uid::new() },
+        ServerMessage::ReactionsUpdated {
+            message_id: Uid::new(),
+            reactions: message.reactions.clone(),
+        },
+        ServerMessage::ReadReceipts {
+            receipts: vec![(Uid::new(), 3), (Uid::new(), u64::MAX)],
+        },
+        ServerMessage::PresenceChanged {
+            uuid: Uid::new(),
+            presence: presence(),
+        },
+        ServerMessage::RoleChanged {
+            uuid: Uid::new(),
+            role: Role::ReadOnly,
+        },
+        ServerMessage::SearchResults {
+            query: "release".into(),
+            results: vec![SearchResult {
+                id: Uid::new(),
+                seq: 9,
+                author: "alice".into(),
+                timestamp: 1_700_000_000,
+                text: "release on friday".into(),
+            }],
+        },
+        ServerMessage::RoomInfoChanged { room: room_info() },
+        ServerMessage::UploadComplete {
+            upload_id: Uid::new(),
+            attachment: attachment(),
+        },
+        ServerMessage::UploadFailed {
+            upload_id: Uid::new(),
+            reason: "too large".into(),
+        },
+        ServerMessage::DownloadChunk {
+            blob_id: Uid::new(),
+            offset: 0,
+            total: 3,
+            data: vec![1, 2, 3],
+        },
+        ServerMessage::DownloadFailed {
+            blob_id: Uid::new(),
+            reason: "unknown blob".into(),
+        },
+        ServerMessage::System {
+            text: "alice was kicked".into(),
+        },
+        ServerMessage::CommandReply {
+            text: "Usage: /kick <user> [reason]".into(),
+        },
+        ServerMessage::JoinRejected {
+            reason: "You are banned".into(),
+        },
+        ServerMessage::Disconnected {
+            reason: "The server is shutting down".into(),
+        },
+    ]
+}
+
+/// Adding a variant fails to compile here until it is given a sample above.
+fn client_variant(message: &ClientMessage) -> &'static str {
+    match message {
+        ClientMessage::Chat { .. } => "Chat",
+        ClientMessage::JoinRequest { .. } => "JoinRequest",
+        ClientMessage::Rename { .. } => "Rename",
+        ClientMessage::Typing { .. } => "Typing",
+        ClientMessage::EditMessage { .. } => "EditMessage",
+        ClientMessage::DeleteMessage { .. } => "DeleteMessage",
+        ClientMessage::AddReaction { .. } => "AddReaction",
+        ClientMessage::RemoveReaction { .. } => "RemoveReaction",
+        ClientMessage::MarkRead { .. } => "MarkRead",
+        ClientMessage::SetPresence { .. } => "SetPresence",
+        ClientMessage::BeginUpload { .. } => "BeginUpload",
+        ClientMessage::UploadChunk { .. } => "UploadChunk",
+        ClientMessage::FinishUpload { .. } => "FinishUpload",
+        ClientMessage::Download { .. } => "Download",
+        ClientMessage::Kick { .. } => "Kick",
+        ClientMessage::Ban { .. } => "Ban",
+        ClientMessage::Mute { .. } => "Mute",
+        ClientMessage::SetRole { .. } => "SetRole",
+        ClientMessage::SetTopic { .. } => "SetTopic",
+        ClientMessage::SetDescription { .. } => "SetDescription",
+        ClientMessage::PinMessage { .. } => "PinMessage",
+        ClientMessage::UnpinMessage { .. } => "UnpinMessage",
+        ClientMessage::Search { .. } => "Search",
+    }
+}
+
+const CLIENT_VARIANTS: usize = 23;
+
+/// Adding a variant fails to compile here until it is given a sample above.
+fn server_variant(message: &ServerMessage) -> &'static str {
+    match message {
+        ServerMessage::Chat(_) => "Chat",
+        ServerMessage::JoinAccepted { .. } => "JoinAccepted",
+        ServerMessage::UserJoined { .. } => "UserJoined",
+        ServerMessage::UserLeft { .. } => "UserLeft",
+        ServerMessage::UserRenamed { .. } => "UserRenamed",
+        ServerMessage::TypingState { .. } => "TypingState",
+        ServerMessage::MessageEdited { .. } => "MessageEdited",
+        ServerMessage::MessageDeleted { .. } => "MessageDeleted",
+        ServerMessage::ReactionsUpdated { .. } => "ReactionsUpdated",
+        ServerMessage::ReadReceipts { .. } => "ReadReceipts",
+        ServerMessage::PresenceChanged { .. } => "PresenceChanged",
+        ServerMessage::RoleChanged { .. } => "RoleChanged",
+        ServerMessage::SearchResults { .. } => "SearchResults",
+        ServerMessage::RoomInfoChanged { .. } => "RoomInfoChanged",
+        ServerMessage::UploadComplete { .. } => "UploadComplete",
+        ServerMessage::UploadFailed { .. } => "UploadFailed",
+        ServerMessage::DownloadChunk { .. } => "DownloadChunk",
+        ServerMessage::DownloadFailed { .. } => "DownloadFailed",
+        ServerMessage::System { .. } => "System",
+        ServerMessage::CommandReply { .. } => "CommandReply",
+        ServerMessage::JoinRejected { .. } => "JoinRejected",
+        ServerMessage::Disconnected { .. } => "Disconnected",
+    }
+}
+
+const SERVER_VARIANTS: usize = 22;
+
+#[test]
+fn samples_cover_every_client_variant() {
+    let variants = client_messages()
+        .iter()
+        .map(client_variant)
+        .collect::<BTreeSet<_>>();
+    assert_eq!(variants.len(), CLIENT_VARIANTS);
+}
+
+#[test]
+fn samples_cover_every_server_variant() {
+    let variants = server_messages()
+        .iter()
+        .map(server_variant)
+        .collect::<BTreeSet<_>>();
+    assert_eq!(variants.len(), SERVER_VARIANTS);
+}
+
+#[test]
+fn client_messages_round_trip() {
+    for message in client_messages() {
+        assert_round_trips(&message);
+    }
+}
+
+#[test]
+fn server_messages_round_trip() {
+    for message in server_messages() {
+        assert_round_trips(&message);
+    }
+}
+
+#[test]
+fn bincode_is_never_mistaken_for_json() {
+    let client = client_messages()
+        .iter()
+        .map(|message| encode_frame(message, Encoding::Bincode).unwrap())
+        .collect::<Vec<_>>();
+    let server = server_messages()
+        .iter()
+        .map(|message| encode_frame(message, Encoding::Bincode).unwrap())
+        .collect::<Vec<_>>();
+
+    for frame in client.iter().chain(&server) {
+        assert_eq!(Encoding::detect(&frame[4..]), Encoding::Bincode);
+    }
+}
+
+#[test]
+fn json_is_readable() {
+    let uuid: Uid = "67e55044-10b1-426f-9247-bb680e5fe0c8".parse().unwrap();
+    let message = ClientMessage::SetRole {
+        target: uuid,
+        role: Role::ReadOnly,
+    };
+
+    let frame = encode_frame(&message, Encoding::Json).unwrap();
+    assert_eq!(
+        std::str::from_utf8(&frame[4..]).unwrap(),
+        r#"{"SetRole":{"target":"67e55044-10b1-426f-9247-bb680e5fe0c8","role":"read-only"}}"#
+    );
+}
+
+#[test]
+fn hand_written_json_decodes() {
+    let json = r#"{"JoinRequest":{"username":"alice","encoding":"json"}}"#;
+
+    match decode_message::<ClientMessage>(json.as_bytes()).unwrap() {
+        ClientMessage::JoinRequest {
+            username,
+            encoding,
+            bot,
+            password,
+        } => {
+            assert_eq!(&*username, "alice");
+            assert_eq!(encoding, Encoding::Json);
+            assert!(!bot);
+            assert!(password.is_none());
+        }
+        other => panic!("unexpected message: {:?}", other),
+    }
+}
+
+#[test]
+fn malformed_frames_are_rejected() {
+    assert!(decode_message::<ClientMessage>(b"{\"Typing\":{}}").is_err());
+    assert!(decode_message::<ClientMessage>(&[250, 0, 0]).is_err());
+}
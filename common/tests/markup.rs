@@ -0,0 +1,63 @@
+//! Tests for the inline markup parser, including inputs full of unclosed
+//! delimiters that must not make parsing quadratic.
+
+use std::time::{Duration, Instant};
+
+use common::markup::{self, Block, Span};
+
+fn spans(text: &str) -> Vec<Span> {
+    match markup::parse(text).as_slice() {
+        [Block::Paragraph(spans)] => spans.clone(),
+        other => panic!("expected a single paragraph: {:?}", other),
+    }
+}
+
+#[test]
+fn styles_and_links_are_parsed() {
+    let spans = spans("**bold** *it* `code` [docs](https://example.com) snake_case");
+    let styled: Vec<_> = spans
+        .iter()
+        .map(|span| (&*span.text, span.bold, span.italic, span.code))
+        .collect();
+    assert_eq!(
+        styled,
+        [
+            ("bold", true, false, false),
+            (" ", false, false, false),
+            ("it", false, true, false),
+            (" ", false, false, false),
+            ("code", false, false, true),
+            (" ", false, false, false),
+            ("docs", false, false, false),
+            (" snake_case", false, false, false),
+        ]
+    );
+    assert_eq!(spans[6].link.as_deref(), Some("https://example.com"));
+}
+
+#[test]
+fn unclosed_delimiters_stay_text() {
+    let text = "**a *b _c `d [e](f";
+    assert_eq!(
+        spans(text),
+        [Span {
+            text: text.into(),
+            ..Default::default()
+        }]
+    );
+}
+
+#[test]
+fn unclosed_delimiters_parse_in_linear_time() {
+    for pattern in ["*a ", "_a ", "[", "[a](", "xhttp://", "a****", "` ``"] {
+        let text = pattern.repeat(200_000);
+        let start = Instant::now();
+        markup::parse(&text);
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "{:?} took {:?}",
+            pattern,
+            start.elapsed()
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use std::sync::Arc;
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query, Request, State},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+};
+use common::{
+    protocol::{ChatMessage, Role},
+    uuid::Uid,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs,
+    io::{self, ErrorKind},
+    net::{TcpListener, UnixListener},
+    sync::Notify,
+};
+use tracing::{info, warn};
+
+use crate::{
+    config::AdminConfig,
+    http::{ApiError, bearer_token, constant_time_eq},
+    server::ChatRoom,
+};
+
+const DEFAULT_HISTORY_LIMIT: usize = 50;
+
+const MAX_HISTORY_LIMIT: usize = 500;
+
+#[derive(Clone)]
+struct AdminState {
+    chat_room: Arc<ChatRoom>,
+    token: Arc<str>,
+    shutdown: Arc<Notify>,
+}
+
+/// Serves the admin API described by `config` until the process exits.
+/// `shutdown` is notified when a client requests a shutdown.
+pub async fn serve(
+    config: AdminConfig,
+    chat_room: Arc<ChatRoom>,
+    shutdown: Arc<Notify>,
+) -> io::Result<()> {
+    let Some(token) = config.token.filter(|token| !token.is_empty()) else {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "the admin API requires a token",
+        ));
+    };
+
+    let state = AdminState {
+        chat_room,
+        token: token.into(),
+        shutdown,
+    };
+    let app = Router::new()
+        .route("/rooms", get(list_rooms))
+        .route("/rooms/{room}/participants", get(list_participants))
+        .route("/rooms/{room}/history", get(history))
+        .route("/rooms/{room}/announce", post(announce))
+        .route("/rooms/{room}/participants/{uuid}/kick", post(kick))
+        .route("/rooms/{room}/participants/{uuid}/ban", post(ban))
+        .route("/rooms/{room}/roles/{username}", put(set_role))
+        .route("/shutdown", post(request_shutdown))
+        .layer(middleware::from_fn_with_state(state.clone(), authorize))
+        .with_state(state);
+
+    if let Some(path) = config.socket {
+        // A socket left behind by a previous run would make the bind fail.
+        match fs::remove_file(&path).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let listener = UnixListener::bind(&path)?;
+        info!(socket = %path.display(), "serving admin API");
+        axum::serve(listener, app).await
+    } else {
+        if !config.address.ip().is_loopback() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "the admin API must bind to a loopback address",
+            ));
+        }
+        let listener = TcpListener::bind(config.address).await?;
+        info!(address = %listener.local_addr()?, "serving admin API");
+        axum::serve(listener, app).await
+    }
+}
+
+/// Rejects requests without `Authorization: Bearer <token>`.
+async fn authorize(State(state): State<AdminState>, request: Request, next: Next) -> Response {
+    match bearer_token(request.headers()) {
+        Some(token) if constant_time_eq(token.as_bytes(), state.token.as_bytes()) => {
+            next.run(request).await
+        }
+        _ => {
+            warn!("rejected admin request with a missing or invalid token");
+            ApiError::new(StatusCode::UNAUTHORIZED, "missing or invalid token").into_response()
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RoomSummary {
+    name: String,
+    participants: usize,
+}
+
+#[derive(Serialize)]
+struct ParticipantSummary {
+    uuid: String,
+    username: Arc<str>,
+    role: &'static str,
+}
+
+#[derive(Serialize)]
+struct HistoryEntry {
+    id: String,
+    seq: u64,
+    from: String,
+    timestamp: u64,
+    text: Arc<str>,
+    edited: bool,
+    deleted: bool,
+}
+
+impl From<&ChatMessage> for HistoryEntry {
+    fn from(message: &ChatMessage) -> Self {
+        Self {
+            id: message.id.inner().to_string(),
+            seq: message.seq,
+            from: message.from.inner().to_string(),
+            timestamp: message.timestamp,
+            text: message.text.clone(),
+            edited: message.edited,
+            deleted: message.deleted,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    /// Only return messages with a lower sequence number.
+    before: Option<u64>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct Announcement {
+    text: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct KickRequest {
+    reason: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct BanRequest {
+    /// In seconds; permanent when omitted.
+    duration: Option<u64>,
+    reason: String,
+}
+
+#[derive(Deserialize)]
+struct SetRoleRequest {
+    role: Role,
+}
+
+async fn list_rooms(State(state): State<AdminState>) -> Json<Vec<RoomSummary>> {
+    let chat_room = &state.chat_room;
+    Json(vec![RoomSummary {
+        name: chat_room.name().to_string(),
+        participants: chat_room.get_participants().await.len(),
+    }])
+}
+
+async fn list_participants(
+    State(state): State<AdminState>,
+    Path(room): Path<String>,
+) -> Result<Json<Vec<ParticipantSummary>>, ApiError> {
+    let chat_room = find_room(&state, &room)?;
+    let participants = chat_room
+        .get_participants()
+        .await
+        .into_iter()
+        .map(|participant| ParticipantSummary {
+            uuid: participant.uuid.inner().to_string(),
+            username: participant.username,
+            role: participant.role.name(),
+        })
+        .collect();
+
+    Ok(Json(participants))
+}
+
+async fn history(
+    State(state): State<AdminState>,
+    Path(room): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<HistoryEntry>>, ApiError> {
+    let chat_room = find_room(&state, &room)?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_HISTORY_LIMIT)
+        .min(MAX_HISTORY_LIMIT);
+
+    let history = chat_room.get_history().await;
+    let mut entries = history
+        .iter()
+        .rev()
+        .filter(|message| query.before.is_none_or(|before| message.seq < before))
+        .take(limit)
+        .map(|message| HistoryEntry::from(&**message))
+        .collect::<Vec<_>>();
+    entries.reverse();
+
+    Ok(Json(entries))
+}
+
+async fn announce(
+    State(state): State<AdminState>,
+    Path(room): Path<String>,
+    Json(announcement): Json<Announcement>,
+) -> Result<StatusCode, ApiError> {
+    let chat_room = find_room(&state, &room)?;
+    let text = announcement.text.trim();
+    if text.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "the announcement is empty",
+        ));
+    }
+
+    chat_room.announce(text.to_string()).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn kick(
+    State(state): State<AdminState>,
+    Path((room, uuid)): Path<(String, String)>,
+    request: Option<Json<KickRequest>>,
+) -> Result<StatusCode, ApiError> {
+    let chat_room = find_room(&state, &room)?;
+    let target = parse_uuid(&uuid)?;
+    let Json(request) = request.unwrap_or_default();
+
+    chat_room.admin_kick(&target, request.reason.into()).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn ban(
+    State(state): State<AdminState>,
+    Path((room, uuid)): Path<(String, String)>,
+    request: Option<Json<BanRequest>>,
+) -> Result<StatusCode, ApiError> {
+    let chat_room = find_room(&state, &room)?;
+    let target = parse_uuid(&uuid)?;
+    let Json(request) = request.unwrap_or_default();
+
+    chat_room
+        .admin_ban(&target, request.duration, request.reason.into())
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn set_role(
+    State(state): State<AdminState>,
+    Path((room, username)): Path<(String, String)>,
+    Json(request): Json<SetRoleRequest>,
+) -> Result<StatusCode, ApiError> {
+    let chat_room = find_room(&state, &room)?;
+
+    chat_room.admin_set_role(&username, request.role).await?;
+    info!(%username, role = request.role.name(), "role set through the admin API");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn request_shutdown(State(state): State<AdminState>) -> StatusCode {
+    info!("shutdown requested through the admin API");
+    state.shutdown.notify_one();
+    StatusCode::ACCEPTED
+}
+
+fn find_room<'a>(state: &'a AdminState, room: &str) -> Result<&'a ChatRoom, ApiError> {
+    if state.chat_room.name() == room {
+        Ok(&state.chat_room)
+    } else {
+        Err(ApiError::new(StatusCode::NOT_FOUND, "unknown room"))
+    }
+}
+
+fn parse_uuid(uuid: &str) -> Result<Uid, ApiError> {
+    uuid.parse()
+        .map_err(|_| ApiError::new(StatusCode::BAD_REQUEST, "invalid participant ID"))
+}
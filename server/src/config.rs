@@ -0,0 +1,290 @@
+use std::{collections::HashSet, net::SocketAddr, path::PathBuf};
+
+use serde::Deserialize;
+use tokio::{
+    fs,
+    io::{self, ErrorKind},
+};
+use tracing_subscriber::EnvFilter;
+
+use crate::server::room_store::PASSWORD_ITERATIONS;
+
+/// Read from `server.toml`, or the file named by `CHAT_SERVER_CONFIG`.
+pub const CONFIG_ENV: &str = "CHAT_SERVER_CONFIG";
+const CONFIG_FILE: &str = "server.toml";
+
+/// Server settings. Every field is optional in the file.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub address: SocketAddr,
+    pub room_file: PathBuf,
+    /// PBKDF2 rounds for newly registered passwords.
+    pub password_iterations: u32,
+    /// Where uploaded files are stored, along with their index.
+    pub blob_dir: PathBuf,
+    /// Name of the room, used to label its metrics.
+    pub room_name: String,
+    pub log: LogConfig,
+    pub websocket: WebSocketConfig,
+    pub metrics: MetricsConfig,
+    pub admin: AdminConfig,
+    pub webhooks: Vec<WebhookConfig>,
+    pub incoming_webhooks: IncomingWebhookConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            room_file: PathBuf::from("room.json"),
+            password_iterations: PASSWORD_ITERATIONS,
+            blob_dir: PathBuf::from("blobs"),
+            room_name: "general".to_string(),
+            log: LogConfig::default(),
+            websocket: WebSocketConfig::default(),
+            metrics: MetricsConfig::default(),
+            admin: AdminConfig::default(),
+            webhooks: Vec::new(),
+            incoming_webhooks: IncomingWebhookConfig::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    /// Filter directives such as `info` or `server=debug,info`. `RUST_LOG`
+    /// takes precedence when set.
+    pub filter: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Human,
+            filter: "info".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Human,
+    Json,
+}
+
+/// A WebSocket listener for clients that cannot use raw TCP.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebSocketConfig {
+    pub enabled: bool,
+    pub address: SocketAddr,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: SocketAddr::from(([127, 0, 0, 1], 8081)),
+        }
+    }
+}
+
+/// The Prometheus endpoint, served separately from the chat listener.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub address: SocketAddr,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            address: SocketAddr::from(([127, 0, 0, 1], 9090)),
+        }
+    }
+}
+
+/// The admin API. It listens on `socket` when set and on `address`, which
+/// must be a loopback address, otherwise.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    pub enabled: bool,
+    pub address: SocketAddr,
+    pub socket: Option<PathBuf>,
+    /// Expected as `Authorization: Bearer <token>`. Required when enabled.
+    pub token: Option<String>,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: SocketAddr::from(([127, 0, 0, 1], 9091)),
+            socket: None,
+            token: None,
+        }
+    }
+}
+
+/// An outgoing webhook, configured as a `[[webhooks]]` table.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Key for the HMAC-SHA256 signature of each body. Requests are unsigned
+    /// without one.
+    #[serde(default)]
+    pub secret: Option<String>,
+    pub events: Vec<WebhookEvent>,
+    /// Single words that trigger a `mention` event, matched case-insensitively.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// Delay before the first retry in milliseconds, doubled after every
+    /// further failure.
+    #[serde(default = "default_retry_delay_ms")]
+    pub retry_delay_ms: u64,
+}
+
+fn default_max_attempts() -> u32 {
+    5
+}
+
+fn default_retry_delay_ms() -> u64 {
+    1000
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WebhookEvent {
+    Chat,
+    UserJoined,
+    Mention,
+}
+
+impl WebhookEvent {
+    pub fn name(self) -> &'static str {
+        match self {
+            WebhookEvent::Chat => "chat",
+            WebhookEvent::UserJoined => "user-joined",
+            WebhookEvent::Mention => "mention",
+        }
+    }
+}
+
+/// The endpoint through which the configured bots post messages into the
+/// room.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct IncomingWebhookConfig {
+    pub enabled: bool,
+    pub address: SocketAddr,
+    pub bots: Vec<BotConfig>,
+}
+
+impl Default for IncomingWebhookConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: SocketAddr::from(([127, 0, 0, 1], 9092)),
+            bots: Vec::new(),
+        }
+    }
+}
+
+/// A bot allowed to post, configured as an `[[incoming_webhooks.bots]]`
+/// table.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BotConfig {
+    /// Shown as the author of the bot's messages.
+    pub name: String,
+    /// Expected as `Authorization: Bearer <token>`; identifies the bot.
+    pub token: String,
+}
+
+impl Config {
+    /// Loads the configuration, using the defaults if the file does not exist.
+    pub async fn load() -> io::Result<Self> {
+        let path = std::env::var_os(CONFIG_ENV).map_or(PathBuf::from(CONFIG_FILE), PathBuf::from);
+
+        let config: Self = match fs::read_to_string(&path).await {
+            Ok(text) => toml::from_str(&text).map_err(|e| {
+                io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+            })?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e),
+        };
+
+        if config.admin.enabled && config.admin.token.as_deref().is_none_or(str::is_empty) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: the admin API requires a token", path.display()),
+            ));
+        }
+
+        if config.password_iterations == 0 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: password_iterations must be at least 1", path.display()),
+            ));
+        }
+
+        if let Some(problem) = config.incoming_webhooks.problem() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), problem),
+            ));
+        }
+
+        Ok(config)
+    }
+}
+
+impl IncomingWebhookConfig {
+    fn problem(&self) -> Option<&'static str> {
+        if !self.enabled {
+            return None;
+        }
+
+        let mut tokens = HashSet::new();
+        for bot in &self.bots {
+            if bot.name.trim().is_empty() {
+                return Some("every bot needs a name");
+            }
+            if bot.token.is_empty() {
+                return Some("every bot needs a token");
+            }
+            if !tokens.insert(&bot.token) {
+                return Some("bots must not share a token");
+            }
+        }
+
+        None
+    }
+}
+
+/// Installs the global subscriber described by `config`.
+pub fn init_logging(config: &LogConfig) -> io::Result<()> {
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::try_new(&config.filter)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("log filter: {}", e)))?,
+    };
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match config.format {
+        LogFormat::Human => builder.init(),
+        LogFormat::Json => builder.json().with_current_span(true).init(),
+    }
+
+    Ok(())
+}
@@ -1,22 +1,213 @@
 use std::{fmt::Display, sync::Arc};
 
-use common::{protocol::ServerMessage, uuid::Uid};
+use common::{
+    protocol::{Permission, Role, ServerMessage},
+    uuid::Uid,
+};
+use tokio::io;
 
 pub type Result<T> = core::result::Result<T, Error>;
 
 #[derive(Debug)]
 #[allow(unused)]
 pub enum Error {
-    ConnectionClosed { uuid: Uid, username: Arc<str> },
-    EncodeError { message: ServerMessage },
+    ConnectionClosed {
+        uuid: Uid,
+        username: Arc<str>,
+    },
+    EncodeError {
+        message: Box<ServerMessage>,
+    },
     FailedToJoin,
-    AlreadyJoined { uuid: Uid, username: Arc<str> },
+    AlreadyJoined {
+        uuid: Uid,
+        username: Arc<str>,
+    },
+    UnknownMessage {
+        id: Uid,
+    },
+    NotAuthor {
+        uuid: Uid,
+        id: Uid,
+    },
+    InvalidReaction {
+        emoji: Arc<str>,
+    },
+    UnknownUpload {
+        id: Uid,
+    },
+    DuplicateUpload {
+        id: Uid,
+    },
+    UnknownBlob {
+        id: Uid,
+    },
+    UploadTooLarge {
+        size: u64,
+        limit: u64,
+    },
+    QuotaExceeded {
+        username: Arc<str>,
+    },
+    TooManyUploads {
+        username: Arc<str>,
+    },
+    InvalidChunk {
+        id: Uid,
+        offset: u64,
+    },
+    ChecksumMismatch {
+        id: Uid,
+    },
+    TooManyAttachments {
+        count: usize,
+    },
+    TooManyPins {
+        count: usize,
+    },
+    MissingPermission {
+        uuid: Uid,
+        permission: Permission,
+    },
+    UnknownParticipant {
+        uuid: Uid,
+    },
+    CannotModerate {
+        uuid: Uid,
+    },
+    CannotAssignRole {
+        uuid: Uid,
+        role: Role,
+    },
+    Storage(io::Error),
+    Banned {
+        username: Arc<str>,
+    },
+    WrongPassword {
+        username: Arc<str>,
+    },
+    /// The name holds a role that needs its password.
+    ReservedName {
+        username: Arc<str>,
+    },
+    NameTaken {
+        username: Arc<str>,
+    },
+    /// Roles above the default only go to names with a password.
+    Unregistered {
+        username: Arc<str>,
+    },
+    /// The participant joined without a password, so cannot hold a role
+    /// above the default.
+    Unverified {
+        uuid: Uid,
+    },
+}
+
+impl Error {
+    /// Whether the error only rejects a single request and should leave the
+    /// connection open.
+    pub fn is_rejection(&self) -> bool {
+        matches!(
+            self,
+            Error::UnknownMessage { .. }
+                | Error::NotAuthor { .. }
+                | Error::InvalidReaction { .. }
+                | Error::UnknownUpload { .. }
+                | Error::DuplicateUpload { .. }
+                | Error::UnknownBlob { .. }
+                | Error::UploadTooLarge { .. }
+                | Error::QuotaExceeded { .. }
+                | Error::TooManyUploads { .. }
+                | Error::InvalidChunk { .. }
+                | Error::ChecksumMismatch { .. }
+                | Error::TooManyAttachments { .. }
+                | Error::TooManyPins { .. }
+                | Error::MissingPermission { .. }
+                | Error::UnknownParticipant { .. }
+                | Error::CannotModerate { .. }
+                | Error::CannotAssignRole { .. }
+                | Error::NameTaken { .. }
+                | Error::Unregistered { .. }
+                | Error::Unverified { .. }
+                | Error::Storage(_)
+        )
+    }
+}
+
+impl Error {
+    /// The error as told to a client. Storage errors can name paths on the
+    /// server, so they are only described in general terms.
+    pub fn reason(&self) -> Arc<str> {
+        match self {
+            Error::Storage(_) => "the server could not store or read the file".into(),
+            e => e.to_string().into(),
+        }
+    }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Error::ConnectionClosed { username, .. } => {
+                write!(f, "the connection of {} closed", username)
+            }
+            Error::EncodeError { .. } => write!(f, "a message could not be encoded"),
+            Error::FailedToJoin => write!(f, "failed to join the room"),
+            Error::AlreadyJoined { username, .. } => write!(f, "{} has already joined", username),
+            Error::UnknownMessage { .. } => write!(f, "no such message"),
+            Error::NotAuthor { .. } => write!(f, "only the author can change the message"),
+            Error::InvalidReaction { .. } => write!(f, "not a valid reaction"),
+            Error::UnknownUpload { .. } => write!(f, "no such upload"),
+            Error::DuplicateUpload { .. } => write!(f, "the upload ID is already in use"),
+            Error::UnknownBlob { .. } => write!(f, "no such file"),
+            Error::UploadTooLarge { size, limit } => write!(
+                f,
+                "the file has {} bytes, more than the limit of {}",
+                size, limit
+            ),
+            Error::QuotaExceeded { .. } => write!(f, "the storage quota is used up"),
+            Error::TooManyUploads { .. } => write!(f, "too many uploads in progress"),
+            Error::InvalidChunk { offset, .. } => {
+                write!(f, "unexpected chunk at offset {}", offset)
+            }
+            Error::ChecksumMismatch { .. } => write!(f, "the file did not arrive intact"),
+            Error::TooManyAttachments { count } => {
+                write!(f, "too many attachments ({})", count)
+            }
+            Error::TooManyPins { count } => write!(f, "too many pinned messages ({})", count),
+            Error::MissingPermission { permission, .. } => {
+                write!(f, "missing the {:?} permission", permission)
+            }
+            Error::UnknownParticipant { .. } => write!(f, "no such participant"),
+            Error::CannotModerate { .. } => write!(f, "their role is not below yours"),
+            Error::CannotAssignRole { role, .. } => {
+                write!(f, "cannot assign the {} role", role.name())
+            }
+            Error::Storage(e) => write!(f, "storage failed: {}", e),
+            Error::Banned { username } => write!(f, "{} is banned", username),
+            Error::WrongPassword { username } => write!(f, "wrong password for {}", username),
+            Error::ReservedName { username } => {
+                write!(f, "{} has a role here but is not registered", username)
+            }
+            Error::NameTaken { username } => write!(f, "the name {} is taken", username),
+            Error::Unregistered { username } => write!(f, "{} is not registered", username),
+            Error::Unverified { .. } => write!(f, "they joined without a password"),
+        }
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Storage(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Storage(error)
+    }
+}
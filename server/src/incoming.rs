@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    routing::post,
+};
+use common::{protocol::ChatMessage, uuid::Uid};
+use serde::{Deserialize, Serialize};
+use tokio::{io, net::TcpListener};
+use tracing::{info, warn};
+
+use crate::{
+    config::IncomingWebhookConfig,
+    http::{ApiError, bearer_token, constant_time_eq},
+    server::ChatRoom,
+};
+
+/// Longest message a bot may post, in bytes.
+const MAX_TEXT_LEN: usize = 4000;
+
+#[derive(Clone)]
+struct IncomingState {
+    chat_room: Arc<ChatRoom>,
+    bots: Arc<[Bot]>,
+}
+
+/// A configured bot. Its ID is fixed for the lifetime of the process and
+/// never belongs to a participant.
+struct Bot {
+    uuid: Uid,
+    name: Arc<str>,
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct Post {
+    text: String,
+}
+
+#[derive(Serialize)]
+struct Posted {
+    id: Uid,
+}
+
+/// Serves the incoming webhook endpoint described by `config` until the
+/// process exits.
+pub async fn serve(config: IncomingWebhookConfig, chat_room: Arc<ChatRoom>) -> io::Result<()> {
+    let bots = config
+        .bots
+        .into_iter()
+        .map(|bot| Bot {
+            uuid: Uid::new(),
+            name: bot.name.trim().into(),
+            token: bot.token,
+        })
+        .collect();
+    let state = IncomingState { chat_room, bots };
+    let app = Router::new()
+        .route("/rooms/{room}/messages", post(post_message))
+        .with_state(state);
+
+    let listener = TcpListener::bind(config.address).await?;
+    info!(address = %listener.local_addr()?, "serving incoming webhooks");
+    axum::serve(listener, app).await
+}
+
+/// Posts `{"text": ...}` into the room as the bot whose token authorizes the
+/// request.
+async fn post_message(
+    State(state): State<IncomingState>,
+    Path(room): Path<String>,
+    headers: HeaderMap,
+    Json(post): Json<Post>,
+) -> Result<(StatusCode, Json<Posted>), ApiError> {
+    let Some(bot) = authenticate(&state, &headers) else {
+        warn!("rejected incoming webhook with a missing or invalid token");
+        return Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "missing or invalid token",
+        ));
+    };
+    if state.chat_room.name() != room {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, "unknown room"));
+    }
+
+    let text = post.text.trim();
+    if text.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "the message is empty",
+        ));
+    }
+    if text.len() > MAX_TEXT_LEN {
+        return Err(ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "the message is too long",
+        ));
+    }
+
+    let message = ChatMessage {
+        bot: Some(bot.name.clone()),
+        ..ChatMessage::new(bot.uuid.clone(), text.into())
+    };
+    let id = message.id.clone();
+    state.chat_room.relay_message(message, &bot.uuid).await?;
+    info!(bot = %bot.name, "posted a bot message");
+
+    Ok((StatusCode::CREATED, Json(Posted { id })))
+}
+
+/// The bot whose token is in the `Authorization` header. Every token is
+/// compared, so the timing does not reveal which bot matched.
+fn authenticate<'a>(state: &'a IncomingState, headers: &HeaderMap) -> Option<&'a Bot> {
+    let token = bearer_token(headers)?;
+    state.bots.iter().fold(None, |found, bot| {
+        if constant_time_eq(token.as_bytes(), bot.token.as_bytes()) {
+            Some(bot)
+        } else {
+            found
+        }
+    })
+}
@@ -0,0 +1,8 @@
+pub mod admin;
+pub mod config;
+pub mod error;
+mod http;
+pub mod incoming;
+pub mod metrics;
+pub mod server;
+pub mod webhooks;
@@ -1,29 +1,132 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
-use tokio::{io, net::TcpListener};
+use tokio::{io, net::TcpListener, sync::Notify, time};
+use tracing::{Instrument, error, field, info, info_span};
 
-use crate::server::{ChatRoom, handle_connection};
+use server::{
+    admin,
+    config::{self, Config},
+    error::{Error, Result},
+    incoming, metrics,
+    server::{
+        ChatRoom, blob_store::BlobStore, handle_connection, handle_websocket, room_store::RoomStore,
+    },
+    webhooks::Webhooks,
+};
 
-mod error;
-mod server;
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(500);
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    let chat_room = Arc::new(ChatRoom::new());
+    let config = Config::load().await?;
+    config::init_logging(&config.log)?;
 
-    let listener = TcpListener::bind("127.0.0.1:8080").await?;
-    println!("Listening on {}", listener.local_addr().unwrap());
+    let store = RoomStore::load(&config.room_file)
+        .await?
+        .with_password_iterations(config.password_iterations);
+    let blobs = BlobStore::load(&config.blob_dir).await?;
+    let webhooks = Webhooks::new(config.webhooks);
+    let chat_room = Arc::new(ChatRoom::new(
+        config.room_name.into(),
+        store,
+        blobs,
+        webhooks,
+    ));
+    tokio::spawn(chat_room.clone().expire_typing());
 
-    loop {
-        let (socket, addr) = listener.accept().await?;
-        println!("New client: {}", addr);
+    if config.metrics.enabled {
+        let address = config.metrics.address;
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(address).await {
+                error!(error = %e, "metrics endpoint stopped");
+            }
+        });
+    }
+
+    let shutdown = Arc::new(Notify::new());
+    if config.admin.enabled {
+        let chat_room = chat_room.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            if let Err(e) = admin::serve(config.admin, chat_room, shutdown).await {
+                error!(error = %e, "admin API stopped");
+            }
+        });
+    }
+
+    if config.incoming_webhooks.enabled {
+        let chat_room = chat_room.clone();
+        tokio::spawn(async move {
+            if let Err(e) = incoming::serve(config.incoming_webhooks, chat_room).await {
+                error!(error = %e, "incoming webhook endpoint stopped");
+            }
+        });
+    }
 
-        let chat_room_clone = chat_room.clone();
+    if config.websocket.enabled {
+        let listener = TcpListener::bind(config.websocket.address).await?;
+        info!(address = %listener.local_addr()?, "listening for WebSocket clients");
 
+        let chat_room = chat_room.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(socket, chat_room_clone).await {
-                eprintln!("Client connection error: {:?}", e);
+            loop {
+                match listener.accept().await {
+                    Ok((socket, addr)) => spawn_session(
+                        addr,
+                        "websocket",
+                        handle_websocket(socket, chat_room.clone()),
+                    ),
+                    Err(e) => error!(error = %e, "failed to accept a WebSocket client"),
+                }
             }
         });
     }
+
+    let listener = TcpListener::bind(config.address).await?;
+    info!(address = %listener.local_addr()?, "listening");
+
+    loop {
+        let (socket, addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown.notified() => break,
+        };
+        spawn_session(addr, "tcp", handle_connection(socket, chat_room.clone()));
+    }
+
+    info!("shutting down");
+    if let Err(e) = chat_room.close("The server is shutting down".into()).await {
+        error!(error = %e, "failed to disconnect participants");
+    }
+    // Give the write tasks a moment to flush the disconnect notices.
+    time::sleep(SHUTDOWN_GRACE).await;
+
+    Ok(())
+}
+
+/// Runs a client session in its own task, logging under a span for the
+/// connection.
+fn spawn_session(
+    peer: SocketAddr,
+    transport: &'static str,
+    session: impl Future<Output = Result<()>> + Send + 'static,
+) {
+    // The ID and username are recorded once the client has joined.
+    let span = info_span!(
+        "connection",
+        %peer,
+        transport,
+        uuid = field::Empty,
+        username = field::Empty
+    );
+
+    tokio::spawn(
+        async move {
+            info!("connected");
+            match session.await {
+                Ok(()) | Err(Error::ConnectionClosed { .. }) => info!("disconnected"),
+                Err(e) => error!(error = %e, "connection closed with an error"),
+            }
+        }
+        .instrument(span),
+    );
 }
@@ -0,0 +1,108 @@
+use std::{net::SocketAddr, sync::LazyLock};
+
+use axum::{Router, http::header, response::IntoResponse, routing::get};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use tokio::{io, net::TcpListener};
+use tracing::info;
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    pub participants: IntGauge,
+    pub messages_relayed: IntCounterVec,
+    pub bytes_received: IntCounter,
+    pub bytes_sent: IntCounter,
+    /// Encoded messages waiting in the participants' outgoing queues.
+    pub queued_messages: IntGauge,
+    pub broadcast_seconds: Histogram,
+    pub join_failures: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry =
+            Registry::new_custom(Some("chat".to_string()), None).expect("metric prefix is valid");
+
+        let metrics = Self {
+            participants: IntGauge::new("participants", "Connected participants")
+                .expect("metric is valid"),
+            messages_relayed: IntCounterVec::new(
+                Opts::new("messages_relayed_total", "Chat messages relayed"),
+                &["room"],
+            )
+            .expect("metric is valid"),
+            bytes_received: IntCounter::new("received_bytes_total", "Bytes read from clients")
+                .expect("metric is valid"),
+            bytes_sent: IntCounter::new("sent_bytes_total", "Bytes written to clients")
+                .expect("metric is valid"),
+            queued_messages: IntGauge::new(
+                "queued_messages",
+                "Messages waiting to be written to clients",
+            )
+            .expect("metric is valid"),
+            broadcast_seconds: Histogram::with_opts(
+                HistogramOpts::new(
+                    "broadcast_duration_seconds",
+                    "Time taken to encode and queue a broadcast",
+                )
+                .buckets(vec![
+                    0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1,
+                ]),
+            )
+            .expect("metric is valid"),
+            join_failures: IntCounterVec::new(
+                Opts::new("join_failures_total", "Rejected or failed joins"),
+                &["reason"],
+            )
+            .expect("metric is valid"),
+            registry,
+        };
+
+        let collectors: [Box<dyn prometheus::core::Collector>; 7] = [
+            Box::new(metrics.participants.clone()),
+            Box::new(metrics.messages_relayed.clone()),
+            Box::new(metrics.bytes_received.clone()),
+            Box::new(metrics.bytes_sent.clone()),
+            Box::new(metrics.queued_messages.clone()),
+            Box::new(metrics.broadcast_seconds.clone()),
+            Box::new(metrics.join_failures.clone()),
+        ];
+        for collector in collectors {
+            metrics
+                .registry
+                .register(collector)
+                .expect("metric names are unique");
+        }
+
+        metrics
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        // Encoding into a Vec only fails for malformed metric families.
+        let _ = TextEncoder::new().encode(&self.registry.gather(), &mut buffer);
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+/// Serves `GET /metrics` on `address` until the process exits.
+pub async fn serve(address: SocketAddr) -> io::Result<()> {
+    let app = Router::new().route("/metrics", get(metrics));
+
+    let listener = TcpListener::bind(address).await?;
+    info!(address = %listener.local_addr()?, "serving metrics");
+
+    axum::serve(listener, app).await
+}
+
+async fn metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        METRICS.render(),
+    )
+}
@@ -0,0 +1,403 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use common::{
+    protocol::{Attachment, CHUNK_SIZE},
+    uuid::Uid,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::{self, File},
+    io::{self, AsyncWriteExt, ErrorKind},
+    sync::{Mutex, RwLock},
+};
+use tracing::warn;
+
+use crate::error::{Error, Result};
+
+/// Largest file that can be uploaded.
+const MAX_BLOB_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Storage available to a single user, including uploads in progress.
+const USER_QUOTA: u64 = 100 * 1024 * 1024;
+
+/// Storage available to all users together.
+const STORE_QUOTA: u64 = 1024 * 1024 * 1024;
+
+/// Uploads a single account may have in progress at once.
+const MAX_PENDING_UPLOADS: usize = 8;
+
+/// Uploads in progress across all accounts.
+const MAX_PENDING_TOTAL: usize = 256;
+
+const MAX_NAME_LEN: usize = 255;
+
+/// Lists the stored blobs and their owners, next to the blobs themselves.
+const INDEX_FILE: &str = "index.json";
+
+/// Files uploaded by participants, stored on disk under their blob ID.
+///
+/// Quotas are accounted per account, the name a participant joined with,
+/// matching how read markers identify returning users, while uploads in
+/// progress belong to the connection that started them. The index of stored
+/// blobs is saved with them, so files and quotas survive a restart.
+pub struct BlobStore {
+    dir: PathBuf,
+    uploads: Mutex<HashMap<Uid, PendingUpload>>,
+    blobs: RwLock<HashMap<Uid, StoredBlob>>,
+}
+
+struct PendingUpload {
+    uploader: Uid,
+    owner: Arc<str>,
+    attachment: Attachment,
+    /// Locked on its own, so that writing a chunk holds up neither the map
+    /// of uploads nor other uploads.
+    progress: Arc<Mutex<Progress>>,
+}
+
+struct Progress {
+    /// Taken once the upload is finished or aborted, after which no more
+    /// chunks are written.
+    file: Option<File>,
+    received: u64,
+    hasher: Sha256,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredBlob {
+    owner: Arc<str>,
+    attachment: Attachment,
+}
+
+impl BlobStore {
+    /// Opens the store in `dir`, reading its index if there is one. Blobs
+    /// whose file has gone are dropped from the index, and uploads left
+    /// unfinished by an earlier run are deleted.
+    pub async fn load(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        let stored: Vec<StoredBlob> = match fs::read(dir.join(INDEX_FILE)).await {
+            Ok(data) => serde_json::from_slice(&data).map_err(io::Error::other)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        let mut blobs = HashMap::new();
+        for blob in stored {
+            let blob_id = blob.attachment.blob_id.clone();
+            if fs::try_exists(blob_path(&dir, &blob_id)).await? {
+                blobs.insert(blob_id, blob);
+            } else {
+                warn!(blob_id = %blob_id.inner(), "dropping a blob whose file is missing");
+            }
+        }
+        remove_partial_files(&dir).await?;
+
+        Ok(Self {
+            dir,
+            uploads: Mutex::new(HashMap::new()),
+            blobs: RwLock::new(blobs),
+        })
+    }
+
+    pub async fn begin(
+        &self,
+        uploader: &Uid,
+        owner: Arc<str>,
+        upload_id: Uid,
+        name: &str,
+        mime: Arc<str>,
+        size: u64,
+    ) -> Result<()> {
+        if size > MAX_BLOB_SIZE {
+            return Err(Error::UploadTooLarge {
+                size,
+                limit: MAX_BLOB_SIZE,
+            });
+        }
+
+        let attachment = Attachment {
+            blob_id: Uid::new(),
+            name: sanitize_name(name).into(),
+            mime,
+            size,
+        };
+
+        // The partial file is named after the new blob, so no other upload
+        // uses it and it can be created before the uploads are locked.
+        fs::create_dir_all(&self.dir).await?;
+        let partial_path = self.partial_path(&attachment.blob_id);
+        let file = File::create(&partial_path).await?;
+
+        let rejection = {
+            let mut uploads = self.uploads.lock().await;
+            let (user_usage, total_usage) = self.usage(&uploads, &owner).await;
+            let over_quota = user_usage + size > USER_QUOTA || total_usage + size > STORE_QUOTA;
+            let user_pending = uploads
+                .values()
+                .filter(|upload| upload.owner == owner)
+                .count();
+            let too_many =
+                user_pending >= MAX_PENDING_UPLOADS || uploads.len() >= MAX_PENDING_TOTAL;
+            match uploads.entry(upload_id) {
+                Entry::Occupied(entry) => Some(Error::DuplicateUpload {
+                    id: entry.key().clone(),
+                }),
+                Entry::Vacant(_) if too_many => Some(Error::TooManyUploads { username: owner }),
+                Entry::Vacant(_) if over_quota => Some(Error::QuotaExceeded { username: owner }),
+                Entry::Vacant(entry) => {
+                    let progress = Progress {
+                        file: Some(file),
+                        received: 0,
+                        hasher: Sha256::new(),
+                    };
+                    entry.insert(PendingUpload {
+                        uploader: uploader.clone(),
+                        owner,
+                        attachment,
+                        progress: Arc::new(Mutex::new(progress)),
+                    });
+                    None
+                }
+            }
+        };
+
+        match rejection {
+            Some(error) => {
+                let _ = fs::remove_file(&partial_path).await;
+                Err(error)
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub async fn write_chunk(
+        &self,
+        uploader: &Uid,
+        upload_id: &Uid,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<()> {
+        let unknown = || Error::UnknownUpload {
+            id: upload_id.clone(),
+        };
+        let (progress, size) = {
+            let uploads = self.uploads.lock().await;
+            let upload = uploads
+                .get(upload_id)
+                .filter(|upload| upload.uploader == *uploader)
+                .ok_or_else(unknown)?;
+            (upload.progress.clone(), upload.attachment.size)
+        };
+
+        let mut progress = progress.lock().await;
+        let progress = &mut *progress;
+        let Some(file) = progress.file.as_mut() else {
+            return Err(unknown());
+        };
+
+        let in_order = offset == progress.received;
+        let fits = data.len() <= CHUNK_SIZE && progress.received + data.len() as u64 <= size;
+        if !in_order || !fits {
+            return Err(Error::InvalidChunk {
+                id: upload_id.clone(),
+                offset,
+            });
+        }
+
+        file.write_all(data).await?;
+        progress.hasher.update(data);
+        progress.received += data.len() as u64;
+
+        Ok(())
+    }
+
+    pub async fn finish(
+        &self,
+        uploader: &Uid,
+        upload_id: &Uid,
+        sha256: &[u8; 32],
+    ) -> Result<Attachment> {
+        let upload = {
+            let mut uploads = self.uploads.lock().await;
+            match uploads.get(upload_id) {
+                Some(upload) if upload.uploader == *uploader => uploads.remove(upload_id).unwrap(),
+                _ => {
+                    return Err(Error::UnknownUpload {
+                        id: upload_id.clone(),
+                    });
+                }
+            }
+        };
+
+        let partial_path = self.partial_path(&upload.attachment.blob_id);
+        let (file, complete, digest) = {
+            let mut progress = upload.progress.lock().await;
+            let complete = progress.received == upload.attachment.size;
+            let digest: [u8; 32] = std::mem::take(&mut progress.hasher).finalize().into();
+            (progress.file.take(), complete, digest)
+        };
+        let Some(mut file) = file else {
+            return Err(Error::UnknownUpload {
+                id: upload_id.clone(),
+            });
+        };
+        if !complete || digest != *sha256 {
+            drop(file);
+            let _ = fs::remove_file(&partial_path).await;
+            return Err(Error::ChecksumMismatch {
+                id: upload_id.clone(),
+            });
+        }
+
+        file.flush().await?;
+        drop(file);
+
+        let attachment = upload.attachment;
+        fs::rename(&partial_path, self.blob_path(&attachment.blob_id)).await?;
+
+        let blob = StoredBlob {
+            owner: upload.owner,
+            attachment: attachment.clone(),
+        };
+        let mut blobs = self.blobs.write().await;
+        blobs.insert(attachment.blob_id.clone(), blob);
+        self.save_index(&blobs).await?;
+
+        Ok(attachment)
+    }
+
+    /// Discards the uploads started by `uploader` that were never finished.
+    pub async fn abort_uploads(&self, uploader: &Uid) {
+        let aborted: Vec<_> = {
+            let mut uploads = self.uploads.lock().await;
+            uploads
+                .extract_if(|_, upload| upload.uploader == *uploader)
+                .map(|(_, upload)| upload)
+                .collect()
+        };
+
+        for upload in aborted {
+            self.discard(upload).await;
+        }
+    }
+
+    pub async fn abort(&self, uploader: &Uid, upload_id: &Uid) {
+        let removed = {
+            let mut uploads = self.uploads.lock().await;
+            match uploads.get(upload_id) {
+                Some(upload) if upload.uploader == *uploader => uploads.remove(upload_id),
+                _ => None,
+            }
+        };
+
+        if let Some(upload) = removed {
+            self.discard(upload).await;
+        }
+    }
+
+    /// Closes the file of an upload taken out of the map, so that chunks
+    /// still on their way are refused, and deletes it.
+    async fn discard(&self, upload: PendingUpload) {
+        upload.progress.lock().await.file.take();
+        let _ = fs::remove_file(self.partial_path(&upload.attachment.blob_id)).await;
+    }
+
+    pub async fn get(&self, blob_id: &Uid) -> Result<Attachment> {
+        self.blobs
+            .read()
+            .await
+            .get(blob_id)
+            .map(|blob| blob.attachment.clone())
+            .ok_or_else(|| Error::UnknownBlob {
+                id: blob_id.clone(),
+            })
+    }
+
+    pub async fn read(&self, blob_id: &Uid) -> Result<Vec<u8>> {
+        self.get(blob_id).await?;
+        Ok(fs::read(self.blob_path(blob_id)).await?)
+    }
+
+    /// Bytes used by `owner` and by everyone, counting uploads in progress
+    /// at their announced size.
+    async fn usage(&self, uploads: &HashMap<Uid, PendingUpload>, owner: &str) -> (u64, u64) {
+        let blobs = self.blobs.read().await;
+        let stored = blobs
+            .values()
+            .map(|blob| (blob.owner.as_ref(), blob.attachment.size));
+        let pending = uploads
+            .values()
+            .map(|upload| (upload.owner.as_ref(), upload.attachment.size));
+
+        stored
+            .chain(pending)
+            .fold((0, 0), |(user, total), (blob_owner, size)| {
+                let user = if blob_owner == owner {
+                    user + size
+                } else {
+                    user
+                };
+                (user, total + size)
+            })
+    }
+
+    async fn save_index(&self, blobs: &HashMap<Uid, StoredBlob>) -> io::Result<()> {
+        let stored: Vec<_> = blobs.values().collect();
+        let data = serde_json::to_vec_pretty(&stored).map_err(io::Error::other)?;
+        let path = self.dir.join(INDEX_FILE);
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, data).await?;
+        fs::rename(&temporary, &path).await
+    }
+
+    fn blob_path(&self, blob_id: &Uid) -> PathBuf {
+        blob_path(&self.dir, blob_id)
+    }
+
+    fn partial_path(&self, blob_id: &Uid) -> PathBuf {
+        self.dir.join(format!("{}.part", blob_id.inner()))
+    }
+}
+
+fn blob_path(dir: &Path, blob_id: &Uid) -> PathBuf {
+    dir.join(blob_id.inner().to_string())
+}
+
+async fn remove_partial_files(dir: &Path) -> io::Result<()> {
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        if entry
+            .path()
+            .extension()
+            .is_some_and(|extension| extension == "part")
+        {
+            fs::remove_file(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Keeps only the final path component of a client supplied file name.
+fn sanitize_name(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NAME_LEN)
+        .collect();
+
+    if name.is_empty() {
+        "file".to_string()
+    } else {
+        name
+    }
+}
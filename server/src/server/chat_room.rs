@@ -1,69 +1,945 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
+use bytes::Bytes;
 use common::{
-    protocol::{ChatMessage, ServerMessage, encode_message},
+    markup,
+    protocol::{
+        Attachment, CHUNK_SIZE, ChatMessage, Encoding, ParticipantInfo, Permission, Presence,
+        Reaction, Role, RoomInfo, ServerMessage, encode_frame,
+    },
     uuid::Uid,
 };
-use tokio::sync::{Mutex, RwLock};
+use tokio::{
+    sync::{Mutex, RwLock},
+    time,
+};
+use tracing::{debug, info, warn};
 
 use crate::{
     error::{Error, Result},
-    server::participant::Participant,
+    metrics::METRICS,
+    server::{
+        blob_store::BlobStore,
+        commands,
+        mentions::resolve_mentions,
+        moderation::{self, Ban, Mute},
+        participant::Participant,
+        room_store::{RoomStore, Verification, same_name},
+        search::{Query, SearchIndex},
+    },
+    webhooks::Webhooks,
 };
 
+/// How long a typing signal stays valid without being refreshed by the client.
+const TYPING_EXPIRY: Duration = Duration::from_secs(5);
+
+/// Upper bound in bytes for a reaction, enough for multi-codepoint emoji sequences.
+const MAX_EMOJI_LEN: usize = 32;
+
+/// Custom status texts are truncated to this many characters.
+const MAX_STATUS_TEXT_LEN: usize = 100;
+
+const MAX_ATTACHMENTS: usize = 10;
+
+/// Moderation reasons are truncated to this many characters.
+const MAX_REASON_LEN: usize = 200;
+
+const MAX_TOPIC_LEN: usize = 200;
+
+/// Longest message or command a participant may send, in bytes. Longer
+/// messages are refused before their markup is parsed.
+const MAX_MESSAGE_LEN: usize = 4000;
+
+const MAX_DESCRIPTION_LEN: usize = 1000;
+
+const MAX_PINS: usize = 50;
+
+const MAX_SEARCH_RESULTS: usize = 100;
+
+/// Shown as the moderator for actions taken through the admin API.
+const ADMIN_NAME: &str = "the server admin";
+
 pub struct ChatRoom {
+    name: Arc<str>,
     participants: RwLock<HashMap<Uid, Participant>>,
     history: Mutex<Vec<Arc<ChatMessage>>>,
+    /// Pinned message IDs, in the order they were pinned.
+    pins: Mutex<Vec<Uid>>,
+    search_index: Mutex<SearchIndex>,
+    typing: Mutex<HashMap<Uid, Instant>>,
+    /// Last read sequence number, keyed by account so it survives reconnects
+    /// and renames.
+    read_markers: Mutex<HashMap<Arc<str>, u64>>,
+    blobs: BlobStore,
+    store: RoomStore,
+    webhooks: Webhooks,
 }
 
 impl ChatRoom {
-    pub fn new() -> Self {
+    pub fn new(name: Arc<str>, store: RoomStore, blobs: BlobStore, webhooks: Webhooks) -> Self {
         Self {
+            name,
             participants: RwLock::new(HashMap::new()),
             history: Mutex::new(Vec::new()),
+            pins: Mutex::new(Vec::new()),
+            search_index: Mutex::new(SearchIndex::default()),
+            typing: Mutex::new(HashMap::new()),
+            read_markers: Mutex::new(HashMap::new()),
+            blobs,
+            store,
+            webhooks,
         }
     }
 
+    /// Adds `participant` to the room. Their name must not be in use, unless
+    /// they verified as its account, in which case the earlier session is
+    /// replaced.
     pub async fn join(&self, uuid: &Uid, participant: Participant) -> Result<()> {
-        self.add_participant(uuid.clone(), participant.clone())
-            .await;
+        let replaced = self
+            .add_participant(uuid.clone(), participant.clone())
+            .await?;
 
         let join_accepted = ServerMessage::JoinAccepted {
+            uuid: uuid.clone(),
             history: self.get_history().await,
-            participants: self.get_usernames().await,
+            participants: self.get_participants().await,
+            last_read: self.get_read_marker(&participant.account).await,
+            permissions: self.store.permissions().await,
+            commands: commands::command_info(),
+            room: self.room_info().await,
         };
-        let bytes = encode_message(&join_accepted)
-            .await
-            .map_err(|_| Error::EncodeError {
-                message: join_accepted,
-            })?;
-        let _ = participant.tx.send(bytes);
+        send(&participant, join_accepted).await?;
+
+        let receipts = ServerMessage::ReadReceipts {
+            receipts: self.get_read_receipts().await,
+        };
+        send(&participant, receipts).await?;
 
         let message = ServerMessage::UserJoined {
             uuid: uuid.clone(),
             username: participant.username.clone(),
+            role: participant.role,
         };
-        self.broadcast(message.into(), uuid).await
+        self.broadcast(message, uuid).await?;
+
+        if let Some((replaced_uuid, replaced)) = replaced {
+            let reason = "You joined from another connection".into();
+            self.dismiss(&replaced_uuid, replaced, reason).await?;
+        }
+
+        self.webhooks
+            .user_joined(&self.name, uuid, &participant.username);
+        Ok(())
     }
 
     pub async fn leave(&self, uuid: &Uid) -> Result<()> {
-        self.remove_participant(uuid).await;
+        // Participants removed by a moderator have already been announced.
+        if self.remove_participant(uuid).await.is_none() {
+            return Ok(());
+        }
+
+        self.blobs.abort_uploads(uuid).await;
+
+        info!("left");
 
         let message = ServerMessage::UserLeft { uuid: uuid.clone() };
-        self.broadcast(message, uuid).await
+        self.broadcast(message, uuid).await?;
+
+        self.set_typing(uuid, false).await
     }
 
-    pub async fn relay_message(&self, message: ChatMessage, sender: &Uid) -> Result<()> {
-        let message = Arc::new(message);
-        self.add_history(message.clone()).await;
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Disconnects every participant, telling them why.
+    pub async fn close(&self, reason: Arc<str>) -> Result<()> {
+        let uuids = self
+            .participants
+            .read()
+            .await
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        for uuid in uuids {
+            self.disconnect(&uuid, reason.clone()).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn verify(&self, username: &str, password: Option<&str>) -> Result<Verification> {
+        Ok(self.store.verify(username, password).await?)
+    }
+
+    pub async fn role_for_join(&self, username: &str, verified: bool) -> Option<Role> {
+        self.store.role_for_join(username, verified).await
+    }
+
+    pub async fn find_ban(&self, username: &str, ip: Option<IpAddr>) -> Option<Ban> {
+        self.store.find_ban(username, ip).await
+    }
+
+    pub async fn kick(&self, moderator: &Uid, target: &Uid, reason: Arc<str>) -> Result<()> {
+        let (moderator, target_participant) = self.check_moderation(moderator, target).await?;
+        self.kick_participant(&moderator, target, target_participant, reason)
+            .await
+    }
+
+    /// Kicks `target` on behalf of the server operator, bypassing role checks.
+    pub async fn admin_kick(&self, target: &Uid, reason: Arc<str>) -> Result<()> {
+        let target_participant = self.get_participant(target).await?;
+        self.kick_participant(ADMIN_NAME, target, target_participant, reason)
+            .await
+    }
+
+    async fn kick_participant(
+        &self,
+        moderator: &str,
+        target: &Uid,
+        target_participant: Participant,
+        reason: Arc<str>,
+    ) -> Result<()> {
+        let reason = truncate_reason(reason);
+
+        let text = format!("You were kicked by {}{}", moderator, with_reason(&reason));
+        self.disconnect(target, text.into()).await?;
+
+        let text = format!(
+            "{} was kicked by {}{}",
+            target_participant.username,
+            moderator,
+            with_reason(&reason)
+        );
+        self.announce(text).await
+    }
+
+    pub async fn ban(
+        &self,
+        moderator: &Uid,
+        target: &Uid,
+        duration: Option<u64>,
+        reason: Arc<str>,
+    ) -> Result<()> {
+        let (moderator, target_participant) = self.check_moderation(moderator, target).await?;
+        self.ban_participant(&moderator, target, target_participant, duration, reason)
+            .await
+    }
+
+    /// Bans `target` on behalf of the server operator, bypassing role checks.
+    pub async fn admin_ban(
+        &self,
+        target: &Uid,
+        duration: Option<u64>,
+        reason: Arc<str>,
+    ) -> Result<()> {
+        let target_participant = self.get_participant(target).await?;
+        self.ban_participant(ADMIN_NAME, target, target_participant, duration, reason)
+            .await
+    }
+
+    async fn ban_participant(
+        &self,
+        moderator: &str,
+        target: &Uid,
+        target_participant: Participant,
+        duration: Option<u64>,
+        reason: Arc<str>,
+    ) -> Result<()> {
+        let reason = truncate_reason(reason);
+
+        let ban = Ban {
+            username: target_participant.account.clone(),
+            ip: target_participant.ip,
+            until: duration.map(|duration| moderation::now().saturating_add(duration)),
+            reason: reason.clone(),
+        };
+        self.store.ban(ban).await?;
+
+        let duration = moderation::describe_duration(duration);
+        let text = format!(
+            "You were banned {} by {}{}",
+            duration,
+            moderator,
+            with_reason(&reason)
+        );
+        self.disconnect(target, text.into()).await?;
+
+        let text = format!(
+            "{} was banned {} by {}{}",
+            target_participant.username,
+            duration,
+            moderator,
+            with_reason(&reason)
+        );
+        self.announce(text).await
+    }
+
+    pub async fn mute(
+        &self,
+        moderator: &Uid,
+        target: &Uid,
+        duration: Option<u64>,
+        reason: Arc<str>,
+    ) -> Result<()> {
+        let (moderator, target_participant) = self.check_moderation(moderator, target).await?;
+        let reason = truncate_reason(reason);
+
+        let mute = Mute {
+            username: target_participant.account.clone(),
+            ip: target_participant.ip,
+            until: duration.map(|duration| moderation::now().saturating_add(duration)),
+        };
+        self.store.mute(mute).await?;
+        self.set_typing(target, false).await?;
+
+        let text = format!(
+            "{} was muted {} by {}{}",
+            target_participant.username,
+            moderation::describe_duration(duration),
+            moderator,
+            with_reason(&reason)
+        );
+        self.announce(text).await
+    }
+
+    /// Assigns `role` to `target`. Promoting a guest to member only takes the
+    /// invite permission; other changes need to manage roles and are limited
+    /// to roles below the actor's own.
+    pub async fn set_role(&self, actor: &Uid, target: &Uid, role: Role) -> Result<()> {
+        let actor_participant = self.get_participant(actor).await?;
+        let target_participant = self.get_participant(target).await?;
+
+        let invite = target_participant.role == Role::Guest && role == Role::Member;
+        let permission = if invite {
+            Permission::Invite
+        } else {
+            Permission::ManageRoles
+        };
+        self.ensure_permission(actor, &actor_participant, permission)
+            .await?;
+
+        if !invite
+            && (target_participant.role >= actor_participant.role || role >= actor_participant.role)
+        {
+            return Err(Error::CannotAssignRole {
+                uuid: target.clone(),
+                role,
+            });
+        }
+        if !target_participant.verified && role > self.store.default_role().await {
+            return Err(Error::Unverified {
+                uuid: target.clone(),
+            });
+        }
+
+        self.store
+            .set_role(&target_participant.account, role)
+            .await?;
+        if let Some(participant) = self.participants.write().await.get_mut(target) {
+            participant.role = role;
+        }
+
+        let message = ServerMessage::RoleChanged {
+            uuid: target.clone(),
+            role,
+        };
+        self.broadcast_all(message).await?;
+
+        let text = format!(
+            "{} is now {} {} (set by {})",
+            target_participant.username,
+            article(role.name()),
+            role.name(),
+            actor_participant.username
+        );
+        self.announce(text).await
+    }
+
+    /// Gives the account `username` a role on behalf of the server operator,
+    /// which is how a room gets its owner. Roles above the default only go
+    /// to registered names, as whoever registered the name later would
+    /// otherwise take the role.
+    pub async fn admin_set_role(&self, username: &str, role: Role) -> Result<()> {
+        if role > self.store.default_role().await && !self.store.is_registered(username).await {
+            return Err(Error::Unregistered {
+                username: username.into(),
+            });
+        }
+        self.store.set_role(username, role).await?;
+
+        let changed: Vec<_> = self
+            .participants
+            .write()
+            .await
+            .iter_mut()
+            .filter(|(_, participant)| same_name(&participant.account, username))
+            .map(|(uuid, participant)| {
+                participant.role = role;
+                (uuid.clone(), participant.username.clone())
+            })
+            .collect();
+        for (uuid, username) in changed {
+            self.broadcast_all(ServerMessage::RoleChanged { uuid, role })
+                .await?;
+            let text = format!(
+                "{} is now {} {} (set by {})",
+                username,
+                article(role.name()),
+                role.name(),
+                ADMIN_NAME
+            );
+            self.announce(text).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn topic(&self) -> Arc<str> {
+        self.store.topic().await
+    }
+
+    pub async fn set_topic(&self, uuid: &Uid, topic: Arc<str>) -> Result<()> {
+        self.require(uuid, Permission::ManageRoom).await?;
+        let Some(username) = self.get_username(uuid).await else {
+            return Ok(());
+        };
+
+        let topic = truncate(&topic, MAX_TOPIC_LEN);
+        self.store.set_topic(topic.clone()).await?;
+        self.broadcast_room_info().await?;
 
-        self.broadcast(ServerMessage::Chat(message), sender).await
+        let text = if topic.is_empty() {
+            format!("{} cleared the topic", username)
+        } else {
+            format!("{} changed the topic to: {}", username, topic)
+        };
+        self.announce(text).await
+    }
+
+    pub async fn set_description(&self, uuid: &Uid, description: Arc<str>) -> Result<()> {
+        self.require(uuid, Permission::ManageRoom).await?;
+        let Some(username) = self.get_username(uuid).await else {
+            return Ok(());
+        };
+
+        let description = truncate(&description, MAX_DESCRIPTION_LEN);
+        self.store.set_description(description).await?;
+        self.broadcast_room_info().await?;
+
+        let text = format!("{} changed the room description", username);
+        self.announce(text).await
+    }
+
+    pub async fn pin_message(&self, uuid: &Uid, id: &Uid) -> Result<()> {
+        self.require(uuid, Permission::ManageRoom).await?;
+        let Some(username) = self.get_username(uuid).await else {
+            return Ok(());
+        };
+        let exists = self
+            .history
+            .lock()
+            .await
+            .iter()
+            .any(|message| message.id == *id && !message.deleted);
+        if !exists {
+            return Err(Error::UnknownMessage { id: id.clone() });
+        }
+
+        {
+            let mut pins = self.pins.lock().await;
+            if pins.contains(id) {
+                return Ok(());
+            }
+            if pins.len() >= MAX_PINS {
+                return Err(Error::TooManyPins { count: pins.len() });
+            }
+            pins.push(id.clone());
+        }
+        self.broadcast_room_info().await?;
+
+        self.announce(format!("{} pinned a message", username))
+            .await
+    }
+
+    pub async fn unpin_message(&self, uuid: &Uid, id: &Uid) -> Result<()> {
+        self.require(uuid, Permission::ManageRoom).await?;
+        let Some(username) = self.get_username(uuid).await else {
+            return Ok(());
+        };
+
+        if !self.remove_pin(id).await {
+            return Ok(());
+        }
+        self.broadcast_room_info().await?;
+
+        self.announce(format!("{} unpinned a message", username))
+            .await
+    }
+
+    /// Answers a search with the newest matching messages.
+    pub async fn search(&self, uuid: &Uid, mut query: Query<'_>) -> Result<()> {
+        query.limit = query.limit.min(MAX_SEARCH_RESULTS);
+        if let Some(from) = query.from {
+            query.registered = self.store.is_registered(from).await;
+        }
+        let results = match query.room {
+            Some(room) if room != &*self.name => Vec::new(),
+            _ => self.search_index.lock().await.search(&query),
+        };
+
+        let message = ServerMessage::SearchResults {
+            query: query.text.into(),
+            results,
+        };
+        self.send_to(uuid, message).await
+    }
+
+    pub async fn room_info(&self) -> RoomInfo {
+        RoomInfo {
+            topic: self.store.topic().await,
+            description: self.store.description().await,
+            pins: self.pins.lock().await.clone(),
+        }
+    }
+
+    pub async fn has_permission(&self, uuid: &Uid, permission: Permission) -> bool {
+        self.require(uuid, permission).await.is_ok()
+    }
+
+    /// Sends `text` to a single participant, used for command output.
+    pub async fn reply(&self, uuid: &Uid, text: String) -> Result<()> {
+        let message = ServerMessage::CommandReply { text: text.into() };
+        self.send_to(uuid, message).await
+    }
+
+    /// Renames `uuid`, provided nobody else is using the name and it is not
+    /// reserved for another account. Muted participants keep their name.
+    pub async fn rename(&self, uuid: &Uid, username: Arc<str>) -> Result<()> {
+        if self.notify_if_muted(uuid).await? {
+            return Ok(());
+        }
+
+        let Some(account) = self
+            .get_participant(uuid)
+            .await
+            .ok()
+            .map(|participant| participant.account)
+        else {
+            return Ok(());
+        };
+        let own_account = same_name(&account, &username);
+        if !own_account && self.store.is_reserved(&username).await {
+            return Err(Error::NameTaken { username });
+        }
+
+        {
+            let mut participants = self.participants.write().await;
+            if participants
+                .iter()
+                .any(|(other, participant)| other != uuid && participant.holds_name(&username))
+            {
+                return Err(Error::NameTaken { username });
+            }
+            let Some(participant) = participants.get_mut(uuid) else {
+                return Ok(());
+            };
+            participant.username = username.clone();
+        }
+
+        let message = ServerMessage::UserRenamed {
+            uuid: uuid.clone(),
+            username,
+        };
+        self.broadcast_all(message).await
+    }
+
+    pub async fn set_presence(&self, uuid: &Uid, mut presence: Presence) -> Result<()> {
+        if presence.text.chars().count() > MAX_STATUS_TEXT_LEN {
+            let text: String = presence.text.chars().take(MAX_STATUS_TEXT_LEN).collect();
+            presence.text = text.into();
+        }
+
+        {
+            let mut participants = self.participants.write().await;
+            let Some(participant) = participants.get_mut(uuid) else {
+                return Ok(());
+            };
+            participant.presence = presence.clone();
+        }
+
+        let message = ServerMessage::PresenceChanged {
+            uuid: uuid.clone(),
+            presence,
+        };
+        self.broadcast_all(message).await
+    }
+
+    /// Adds `message` to the history and broadcasts it. A message that
+    /// already names its bot comes from an incoming webhook: `sender` is then
+    /// the bot's ID rather than a participant's, and the participant checks
+    /// are skipped. Otherwise `message.bot` is set from the sender's account.
+    pub async fn relay_message(&self, mut message: ChatMessage, sender: &Uid) -> Result<()> {
+        let from_webhook = message.bot.is_some() && self.get_username(sender).await.is_none();
+        if !from_webhook {
+            let participant = self.get_participant(sender).await?;
+            self.ensure_permission(sender, &participant, Permission::SendMessages)
+                .await?;
+            if self.notify_if_muted(sender).await? {
+                return Ok(());
+            }
+            message.author = participant.verified.then(|| participant.account.clone());
+            message.bot = participant.bot.then_some(participant.username);
+        }
+
+        message.mentions = resolve_mentions(&message.text, &self.get_usernames().await);
+        let message = self.add_history(message).await?;
+        let seq = message.seq;
+        debug!(seq, "relayed message");
+        METRICS
+            .messages_relayed
+            .with_label_values(&[&*self.name])
+            .inc();
+        let author = match &message.bot {
+            Some(bot) => Some(bot.clone()),
+            None => self.get_username(sender).await,
+        };
+        if let Some(author) = &author {
+            self.search_index
+                .lock()
+                .await
+                .insert(&message, author.clone());
+        }
+
+        self.broadcast_all(ServerMessage::Chat(message.clone()))
+            .await?;
+        if let Some(author) = &author {
+            self.webhooks.chat_message(&self.name, &message, author);
+        }
+
+        if from_webhook {
+            return Ok(());
+        }
+        self.set_typing(sender, false).await?;
+        self.mark_read(sender, seq).await
+    }
+
+    /// Advances the read marker of `uuid` to `seq`. Markers never move backwards.
+    pub async fn mark_read(&self, uuid: &Uid, seq: u64) -> Result<()> {
+        let seq = seq.min(self.history.lock().await.len() as u64);
+        let Some(account) = self.get_account(uuid).await else {
+            return Ok(());
+        };
+
+        {
+            let mut read_markers = self.read_markers.lock().await;
+            let marker = read_markers.entry(account).or_default();
+            if seq <= *marker {
+                return Ok(());
+            }
+            *marker = seq;
+        }
+
+        let message = ServerMessage::ReadReceipts {
+            receipts: vec![(uuid.clone(), seq)],
+        };
+        self.broadcast_all(message).await
+    }
+
+    pub async fn edit_message(&self, editor: &Uid, id: &Uid, text: Arc<str>) -> Result<()> {
+        let participant = self.get_participant(editor).await?;
+        self.ensure_permission(editor, &participant, Permission::EditMessages)
+            .await?;
+        if self.notify_if_muted(editor).await? || self.notify_if_too_long(editor, &text).await? {
+            return Ok(());
+        }
+
+        let mentions = resolve_mentions(&text, &self.get_usernames().await);
+        let body = markup::parse(&text);
+
+        let edited = self
+            .update_message(id, |message| {
+                ensure_author(message, editor, &participant)?;
+                message.text = text.clone();
+                message.body = body.clone();
+                message.mentions = mentions.clone();
+                message.edited = true;
+                Ok(message.clone())
+            })
+            .await?;
+        self.search_index.lock().await.update(&edited);
+
+        let message = ServerMessage::MessageEdited {
+            id: id.clone(),
+            text,
+            body,
+            mentions,
+        };
+        self.broadcast_all(message).await
+    }
+
+    pub async fn delete_message(&self, editor: &Uid, id: &Uid) -> Result<()> {
+        let participant = self.get_participant(editor).await?;
+        let delete_own = self
+            .store
+            .has_permission(participant.role, Permission::DeleteMessages)
+            .await;
+        let delete_others = self
+            .store
+            .has_permission(participant.role, Permission::DeleteOthersMessages)
+            .await;
+
+        let seq = self
+            .update_message(id, |message| {
+                if is_author(message, editor, &participant) && !delete_own {
+                    return Err(Error::MissingPermission {
+                        uuid: editor.clone(),
+                        permission: Permission::DeleteMessages,
+                    });
+                }
+                if !delete_others {
+                    ensure_author(message, editor, &participant)?;
+                }
+                message.text = "".into();
+                message.body.clear();
+                message.deleted = true;
+                message.reactions.clear();
+                Ok(message.seq)
+            })
+            .await?;
+        self.search_index.lock().await.remove(seq);
+
+        let message = ServerMessage::MessageDeleted { id: id.clone() };
+        self.broadcast_all(message).await?;
+
+        if self.remove_pin(id).await {
+            self.broadcast_room_info().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn add_reaction(&self, uuid: &Uid, message_id: &Uid, emoji: Arc<str>) -> Result<()> {
+        self.require(uuid, Permission::React).await?;
+        validate_emoji(&emoji)?;
+
+        let reactions = self
+            .update_message(message_id, |message| {
+                match message
+                    .reactions
+                    .iter_mut()
+                    .find(|reaction| reaction.emoji == emoji)
+                {
+                    Some(reaction) if reaction.users.contains(uuid) => return Ok(None),
+                    Some(reaction) => reaction.users.push(uuid.clone()),
+                    None => message.reactions.push(Reaction {
+                        emoji: emoji.clone(),
+                        users: vec![uuid.clone()],
+                    }),
+                }
+                Ok(Some(message.reactions.clone()))
+            })
+            .await?;
+
+        self.broadcast_reactions(message_id, reactions).await
+    }
+
+    pub async fn remove_reaction(
+        &self,
+        uuid: &Uid,
+        message_id: &Uid,
+        emoji: Arc<str>,
+    ) -> Result<()> {
+        let reactions = self
+            .update_message(message_id, |message| {
+                let Some(reaction) = message
+                    .reactions
+                    .iter_mut()
+                    .find(|reaction| reaction.emoji == emoji)
+                else {
+                    return Ok(None);
+                };
+
+                let before = reaction.users.len();
+                reaction.users.retain(|user| user != uuid);
+                if reaction.users.len() == before {
+                    return Ok(None);
+                }
+
+                message
+                    .reactions
+                    .retain(|reaction| !reaction.users.is_empty());
+                Ok(Some(message.reactions.clone()))
+            })
+            .await?;
+
+        self.broadcast_reactions(message_id, reactions).await
+    }
+
+    /// Records whether `uuid` is composing a message.
+    ///
+    /// Repeated `active` signals only refresh the expiry deadline, so the
+    /// typing state is broadcast once per change rather than once per keystroke.
+    pub async fn set_typing(&self, uuid: &Uid, active: bool) -> Result<()> {
+        let changed = {
+            let mut typing = self.typing.lock().await;
+            if active {
+                typing.insert(uuid.clone(), Instant::now()).is_none()
+            } else {
+                typing.remove(uuid).is_some()
+            }
+        };
+
+        if changed {
+            self.broadcast_typing().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Periodically drops typing signals that have not been refreshed in time.
+    pub async fn expire_typing(self: Arc<Self>) -> Result<()> {
+        let mut interval = time::interval(Duration::from_secs(1));
+
+        loop {
+            interval.tick().await;
+
+            let expired = {
+                let mut typing = self.typing.lock().await;
+                let before = typing.len();
+                typing.retain(|_, refreshed| refreshed.elapsed() < TYPING_EXPIRY);
+                typing.len() != before
+            };
+
+            if expired {
+                self.broadcast_typing().await?;
+            }
+        }
+    }
+
+    pub async fn begin_upload(
+        &self,
+        uuid: &Uid,
+        upload_id: Uid,
+        name: &str,
+        mime: Arc<str>,
+        size: u64,
+    ) -> Result<()> {
+        let Some(account) = self.get_account(uuid).await else {
+            return Ok(());
+        };
+
+        let result = match self.require(uuid, Permission::SendMessages).await {
+            Ok(()) => {
+                self.blobs
+                    .begin(uuid, account, upload_id.clone(), name, mime, size)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        self.report_upload_failure(uuid, upload_id, result).await
+    }
+
+    pub async fn upload_chunk(
+        &self,
+        uuid: &Uid,
+        upload_id: Uid,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<()> {
+        let result = match self.blobs.write_chunk(uuid, &upload_id, offset, data).await {
+            // Chunks still in flight after an upload was aborted; the failure
+            // has already been reported, and is again when it is finished.
+            Err(Error::UnknownUpload { .. }) => Ok(()),
+            result => result,
+        };
+        self.report_upload_failure(uuid, upload_id, result).await
+    }
+
+    pub async fn finish_upload(&self, uuid: &Uid, upload_id: Uid, sha256: &[u8; 32]) -> Result<()> {
+        match self.blobs.finish(uuid, &upload_id, sha256).await {
+            Ok(attachment) => {
+                let message = ServerMessage::UploadComplete {
+                    upload_id,
+                    attachment,
+                };
+                self.send_to(uuid, message).await
+            }
+            Err(e) => self.report_upload_failure(uuid, upload_id, Err(e)).await,
+        }
+    }
+
+    /// Streams the blob to `uuid` in chunks of at most [`CHUNK_SIZE`] bytes.
+    pub async fn download(&self, uuid: &Uid, blob_id: Uid) -> Result<()> {
+        let data = match self.blobs.read(&blob_id).await {
+            Ok(data) => data,
+            Err(e) if e.is_rejection() => {
+                log_storage_error(&e);
+                let message = ServerMessage::DownloadFailed {
+                    blob_id,
+                    reason: e.reason(),
+                };
+                return self.send_to(uuid, message).await;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let total = data.len() as u64;
+        let mut offset = 0;
+        // An empty file is still sent as a single, empty chunk.
+        for chunk in data
+            .chunks(CHUNK_SIZE)
+            .chain(data.is_empty().then_some(&[][..]))
+        {
+            let message = ServerMessage::DownloadChunk {
+                blob_id: blob_id.clone(),
+                offset,
+                total,
+                data: chunk.to_vec(),
+            };
+            self.send_to(uuid, message).await?;
+            offset += chunk.len() as u64;
+        }
+
+        Ok(())
+    }
+
+    pub async fn resolve_attachments(&self, blob_ids: &[Uid]) -> Result<Vec<Attachment>> {
+        if blob_ids.len() > MAX_ATTACHMENTS {
+            return Err(Error::TooManyAttachments {
+                count: blob_ids.len(),
+            });
+        }
+
+        let mut attachments = Vec::with_capacity(blob_ids.len());
+        for blob_id in blob_ids {
+            attachments.push(self.blobs.get(blob_id).await?);
+        }
+
+        Ok(attachments)
     }
 
     pub async fn get_history(&self) -> Vec<Arc<ChatMessage>> {
         self.history.lock().await.clone()
     }
 
+    pub async fn get_participants(&self) -> Vec<ParticipantInfo> {
+        self.participants
+            .read()
+            .await
+            .iter()
+            .map(|(uuid, participant)| ParticipantInfo {
+                uuid: uuid.clone(),
+                username: participant.username.clone(),
+                presence: participant.presence.clone(),
+                role: participant.role,
+            })
+            .collect()
+    }
+
     pub async fn get_usernames(&self) -> Vec<(Uid, Arc<str>)> {
         self.participants
             .read()
@@ -73,36 +949,448 @@ impl ChatRoom {
             .collect()
     }
 
-    async fn add_participant(&self, uuid: Uid, participant: Participant) {
-        self.participants.write().await.insert(uuid, participant);
+    pub async fn get_read_receipts(&self) -> Vec<(Uid, u64)> {
+        let accounts: Vec<_> = self
+            .participants
+            .read()
+            .await
+            .iter()
+            .map(|(uuid, participant)| (uuid.clone(), participant.account.clone()))
+            .collect();
+        let read_markers = self.read_markers.lock().await;
+
+        accounts
+            .into_iter()
+            .filter_map(|(uuid, account)| Some((uuid, *read_markers.get(&account)?)))
+            .collect()
     }
 
-    async fn remove_participant(&self, uuid: &Uid) {
-        self.participants.write().await.remove(uuid);
+    async fn get_read_marker(&self, account: &Arc<str>) -> u64 {
+        self.read_markers
+            .lock()
+            .await
+            .get(account)
+            .copied()
+            .unwrap_or_default()
     }
 
-    async fn add_history(&self, message: Arc<ChatMessage>) {
-        self.history.lock().await.push(message);
+    async fn get_account(&self, uuid: &Uid) -> Option<Arc<str>> {
+        self.participants
+            .read()
+            .await
+            .get(uuid)
+            .map(|participant| participant.account.clone())
     }
 
-    async fn broadcast(&self, message: ServerMessage, sender: &Uid) -> Result<()> {
-        let bytes = encode_message(&message)
+    async fn get_username(&self, uuid: &Uid) -> Option<Arc<str>> {
+        self.participants
+            .read()
+            .await
+            .get(uuid)
+            .map(|participant| participant.username.clone())
+    }
+
+    /// Returns the participant replaced by a verified rejoin, or the session
+    /// that ended without leaving, under the same name.
+    async fn add_participant(
+        &self,
+        uuid: Uid,
+        participant: Participant,
+    ) -> Result<Option<(Uid, Participant)>> {
+        let mut participants = self.participants.write().await;
+
+        let holder = participants
+            .iter()
+            .find(|(_, other)| other.holds_name(&participant.username))
+            .map(|(uuid, other)| (uuid.clone(), other));
+        let replaced = match holder {
+            None => None,
+            Some((holder, other))
+                if other.tx.is_closed()
+                    || (participant.verified
+                        && other.verified
+                        && same_name(&other.account, &participant.account)) =>
+            {
+                participants.remove_entry(&holder)
+            }
+            Some(_) => {
+                return Err(Error::NameTaken {
+                    username: participant.username,
+                });
+            }
+        };
+
+        participants.insert(uuid, participant);
+        METRICS.participants.set(participants.len() as i64);
+        Ok(replaced)
+    }
+
+    async fn remove_participant(&self, uuid: &Uid) -> Option<Participant> {
+        let mut participants = self.participants.write().await;
+        let participant = participants.remove(uuid);
+        METRICS.participants.set(participants.len() as i64);
+        participant
+    }
+
+    async fn get_participant(&self, uuid: &Uid) -> Result<Participant> {
+        self.participants
+            .read()
+            .await
+            .get(uuid)
+            .cloned()
+            .ok_or_else(|| Error::UnknownParticipant { uuid: uuid.clone() })
+    }
+
+    async fn require(&self, uuid: &Uid, permission: Permission) -> Result<()> {
+        let participant = self.get_participant(uuid).await?;
+        self.ensure_permission(uuid, &participant, permission).await
+    }
+
+    async fn ensure_permission(
+        &self,
+        uuid: &Uid,
+        participant: &Participant,
+        permission: Permission,
+    ) -> Result<()> {
+        if self
+            .store
+            .has_permission(participant.role, permission)
             .await
-            .map_err(|_| Error::EncodeError { message })?;
+        {
+            Ok(())
+        } else {
+            Err(Error::MissingPermission {
+                uuid: uuid.clone(),
+                permission,
+            })
+        }
+    }
+
+    /// Checks that `moderator` may act on `target`, which must have a lower
+    /// role, returning the moderator's username and the target.
+    async fn check_moderation(
+        &self,
+        moderator: &Uid,
+        target: &Uid,
+    ) -> Result<(Arc<str>, Participant)> {
+        let moderator_participant = self.get_participant(moderator).await?;
+        self.ensure_permission(moderator, &moderator_participant, Permission::Moderate)
+            .await?;
+
+        let target_participant = self.get_participant(target).await?;
+        if target_participant.role >= moderator_participant.role {
+            return Err(Error::CannotModerate {
+                uuid: target.clone(),
+            });
+        }
+
+        Ok((moderator_participant.username, target_participant))
+    }
+
+    /// Removes `uuid` from the room, telling them why before the connection
+    /// closes.
+    async fn disconnect(&self, uuid: &Uid, reason: Arc<str>) -> Result<()> {
+        let Some(participant) = self.remove_participant(uuid).await else {
+            return Ok(());
+        };
+        self.dismiss(uuid, participant, reason).await
+    }
+
+    /// Tells a participant already taken out of the room why, and lets the
+    /// others know they left.
+    async fn dismiss(&self, uuid: &Uid, participant: Participant, reason: Arc<str>) -> Result<()> {
+        // Dropping the last sender ends the participant's write task once the
+        // notice has been flushed.
+        send(&participant, ServerMessage::Disconnected { reason }).await?;
+        drop(participant);
+
+        self.blobs.abort_uploads(uuid).await;
+
+        let message = ServerMessage::UserLeft { uuid: uuid.clone() };
+        self.broadcast_all(message).await?;
+
+        self.set_typing(uuid, false).await
+    }
+
+    /// Tells a muted participant that their message was dropped.
+    async fn notify_if_muted(&self, uuid: &Uid) -> Result<bool> {
+        let Ok(participant) = self.get_participant(uuid).await else {
+            return Ok(false);
+        };
+        // Accounts verified by their password are only matched by name, so a
+        // mute does not catch everyone sharing their address.
+        let ip = participant.ip.filter(|_| !participant.verified);
+        let muted = self.store.muted_until(&participant.account, ip).await;
+        let Some(until) = muted else {
+            return Ok(false);
+        };
+
+        let text = format!("You are muted {}", moderation::describe_remaining(until));
+        self.send_to(uuid, ServerMessage::System { text: text.into() })
+            .await?;
+        Ok(true)
+    }
+
+    /// Tells `uuid` why their request was turned down.
+    pub async fn notify_rejected(&self, uuid: &Uid, error: &Error) -> Result<()> {
+        let text = format!("Your request was rejected: {}", error.reason());
+        self.send_to(uuid, ServerMessage::System { text: text.into() })
+            .await
+    }
+
+    /// Tells `uuid` when `text` is over the length limit, in which case the
+    /// message is dropped.
+    pub async fn notify_if_too_long(&self, uuid: &Uid, text: &str) -> Result<bool> {
+        if text.len() <= MAX_MESSAGE_LEN {
+            return Ok(false);
+        }
+
+        let text = format!(
+            "Your message is too long; the limit is {} bytes",
+            MAX_MESSAGE_LEN
+        );
+        self.send_to(uuid, ServerMessage::System { text: text.into() })
+            .await?;
+        Ok(true)
+    }
+
+    /// Sends a system message to everyone in the room.
+    pub async fn announce(&self, text: String) -> Result<()> {
+        info!(%text, "announcement");
+        self.broadcast_all(ServerMessage::System { text: text.into() })
+            .await
+    }
+
+    /// Appends `message` to the history and assigns its sequence number,
+    /// provided the message it replies to is part of this room.
+    async fn add_history(&self, mut message: ChatMessage) -> Result<Arc<ChatMessage>> {
+        let mut history = self.history.lock().await;
+
+        if let Some(parent) = &message.reply_to
+            && !history
+                .iter()
+                .any(|other| other.id == *parent && !other.deleted)
+        {
+            return Err(Error::UnknownMessage { id: parent.clone() });
+        }
+
+        // Messages are never removed from the history, so positions are stable.
+        message.seq = history.len() as u64 + 1;
+        let message = Arc::new(message);
+        history.push(message.clone());
+
+        Ok(message)
+    }
+
+    /// Replaces the history entry `id` with an updated copy, provided it has
+    /// not been deleted. The entry is left untouched if `update` fails.
+    async fn update_message<F, T>(&self, id: &Uid, update: F) -> Result<T>
+    where
+        F: FnOnce(&mut ChatMessage) -> Result<T>,
+    {
+        let mut history = self.history.lock().await;
+        let message = history
+            .iter_mut()
+            .find(|message| message.id == *id && !message.deleted)
+            .ok_or_else(|| Error::UnknownMessage { id: id.clone() })?;
+
+        let mut updated = ChatMessage::clone(message);
+        let result = update(&mut updated)?;
+        *message = Arc::new(updated);
+
+        Ok(result)
+    }
+
+    async fn broadcast_reactions(
+        &self,
+        message_id: &Uid,
+        reactions: Option<Vec<Reaction>>,
+    ) -> Result<()> {
+        let Some(reactions) = reactions else {
+            return Ok(());
+        };
+
+        let message = ServerMessage::ReactionsUpdated {
+            message_id: message_id.clone(),
+            reactions,
+        };
+        self.broadcast_all(message).await
+    }
+
+    /// Tells the uploader why an upload was rejected and drops its partial data.
+    async fn report_upload_failure(
+        &self,
+        uuid: &Uid,
+        upload_id: Uid,
+        result: Result<()>,
+    ) -> Result<()> {
+        match result {
+            Err(e) if e.is_rejection() => {
+                log_storage_error(&e);
+                self.blobs.abort(uuid, &upload_id).await;
+
+                let message = ServerMessage::UploadFailed {
+                    upload_id,
+                    reason: e.reason(),
+                };
+                self.send_to(uuid, message).await
+            }
+            result => result,
+        }
+    }
+
+    async fn send_to(&self, uuid: &Uid, message: ServerMessage) -> Result<()> {
+        let participant = self.participants.read().await.get(uuid).cloned();
+        match participant {
+            Some(participant) => send(&participant, message).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Returns whether the message was pinned.
+    async fn remove_pin(&self, id: &Uid) -> bool {
+        let mut pins = self.pins.lock().await;
+        let count = pins.len();
+        pins.retain(|pin| pin != id);
+        pins.len() != count
+    }
+
+    async fn broadcast_room_info(&self) -> Result<()> {
+        let room = self.room_info().await;
+        self.broadcast_all(ServerMessage::RoomInfoChanged { room })
+            .await
+    }
+
+    async fn broadcast_typing(&self) -> Result<()> {
+        let typing = self.typing.lock().await.keys().cloned().collect();
+        self.broadcast_all(ServerMessage::TypingState { typing })
+            .await
+    }
+
+    async fn broadcast(&self, message: ServerMessage, sender: &Uid) -> Result<()> {
+        self.broadcast_except(message, Some(sender)).await
+    }
+
+    async fn broadcast_all(&self, message: ServerMessage) -> Result<()> {
+        self.broadcast_except(message, None).await
+    }
+
+    async fn broadcast_except(&self, message: ServerMessage, excluded: Option<&Uid>) -> Result<()> {
+        let _timer = METRICS.broadcast_seconds.start_timer();
 
         let participants = {
             let participants = self.participants.read().await;
             participants
                 .iter()
-                .filter(|(username, _)| *username != sender)
+                .filter(|(uuid, _)| Some(*uuid) != excluded)
                 .map(|(_, participant)| participant.clone())
                 .collect::<Vec<_>>()
         };
 
+        // The message is encoded once for each encoding in use.
+        let mut frames: Vec<(Encoding, Bytes)> = Vec::new();
         for participant in participants {
-            let _ = participant.tx.send(bytes.clone());
+            let encoding = participant.encoding;
+            let frame = match frames.iter().find(|(other, _)| *other == encoding) {
+                Some((_, frame)) => frame.clone(),
+                None => {
+                    let frame = encode(&message, encoding)?;
+                    frames.push((encoding, frame.clone()));
+                    frame
+                }
+            };
+            queue(&participant, frame);
         }
 
         Ok(())
     }
 }
+
+async fn send(participant: &Participant, message: ServerMessage) -> Result<()> {
+    let frame = encode(&message, participant.encoding)?;
+    queue(participant, frame);
+
+    Ok(())
+}
+
+fn encode(message: &ServerMessage, encoding: Encoding) -> Result<Bytes> {
+    encode_frame(message, encoding).map_err(|_| Error::EncodeError {
+        message: Box::new(message.clone()),
+    })
+}
+
+fn queue(participant: &Participant, bytes: Bytes) {
+    if participant.tx.send(bytes).is_ok() {
+        METRICS.queued_messages.inc();
+    }
+}
+
+fn truncate(text: &str, max_len: usize) -> Arc<str> {
+    text.trim().chars().take(max_len).collect::<String>().into()
+}
+
+fn truncate_reason(reason: Arc<str>) -> Arc<str> {
+    truncate(&reason, MAX_REASON_LEN)
+}
+
+fn article(word: &str) -> &'static str {
+    if word.starts_with(['a', 'e', 'i', 'o', 'u']) {
+        "an"
+    } else {
+        "a"
+    }
+}
+
+fn with_reason(reason: &str) -> String {
+    if reason.is_empty() {
+        String::new()
+    } else {
+        format!(": {}", reason)
+    }
+}
+
+/// Storage errors only reach the client as a generic reason, so the details
+/// are logged here.
+fn log_storage_error(error: &Error) {
+    if let Error::Storage(e) = error {
+        warn!(error = %e, "file storage failed");
+    }
+}
+
+/// Whether `participant` wrote `message`, in this session or, when they
+/// joined with their password, in an earlier one.
+fn is_author(message: &ChatMessage, uuid: &Uid, participant: &Participant) -> bool {
+    message.from == *uuid
+        || participant.verified
+            && message
+                .author
+                .as_deref()
+                .is_some_and(|author| same_name(author, &participant.account))
+}
+
+fn ensure_author(message: &ChatMessage, uuid: &Uid, participant: &Participant) -> Result<()> {
+    if !is_author(message, uuid, participant) {
+        return Err(Error::NotAuthor {
+            uuid: uuid.clone(),
+            id: message.id.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+fn validate_emoji(emoji: &Arc<str>) -> Result<()> {
+    let valid = !emoji.is_empty()
+        && emoji.len() <= MAX_EMOJI_LEN
+        && !emoji
+            .chars()
+            .any(|c| c.is_alphanumeric() || c.is_whitespace());
+
+    if !valid {
+        return Err(Error::InvalidReaction {
+            emoji: emoji.clone(),
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,441 @@
+//! Slash commands typed into the chat input, such as `/nick` or `/kick`.
+//! Every command declares its arguments and the permission it needs, and
+//! its replies go only to the participant who ran it.
+
+use std::sync::Arc;
+
+use common::{
+    protocol::{
+        ArgumentInfo, ArgumentKind, ChatMessage, CommandInfo, Permission, PresenceStatus, Role,
+    },
+    uuid::Uid,
+};
+
+use crate::{
+    error::{Error, Result},
+    server::ChatRoom,
+};
+
+pub struct Command {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub arguments: &'static [Argument],
+    pub permission: Option<Permission>,
+    handler: Handler,
+}
+
+pub struct Argument {
+    pub name: &'static str,
+    pub kind: ArgumentKind,
+    pub required: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Handler {
+    Help,
+    Nick,
+    Me,
+    Who,
+    Topic,
+    Kick,
+    Mute,
+    Ban,
+}
+
+const fn required(name: &'static str, kind: ArgumentKind) -> Argument {
+    Argument {
+        name,
+        kind,
+        required: true,
+    }
+}
+
+const fn optional(name: &'static str, kind: ArgumentKind) -> Argument {
+    Argument {
+        name,
+        kind,
+        required: false,
+    }
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        description: "Lists the commands or shows how to use one",
+        arguments: &[optional("command", ArgumentKind::Word)],
+        permission: None,
+        handler: Handler::Help,
+    },
+    Command {
+        name: "nick",
+        description: "Changes your username",
+        arguments: &[required("name", ArgumentKind::Word)],
+        permission: None,
+        handler: Handler::Nick,
+    },
+    Command {
+        name: "me",
+        description: "Describes an action, as in \"/me waves\"",
+        arguments: &[required("action", ArgumentKind::Text)],
+        permission: Some(Permission::SendMessages),
+        handler: Handler::Me,
+    },
+    Command {
+        name: "who",
+        description: "Lists who is in the room",
+        arguments: &[],
+        permission: None,
+        handler: Handler::Who,
+    },
+    Command {
+        name: "topic",
+        // Anyone may look at the topic; changing it takes `ManageRoom`.
+        description: "Shows the room's topic, or changes it",
+        arguments: &[optional("topic", ArgumentKind::Text)],
+        permission: None,
+        handler: Handler::Topic,
+    },
+    Command {
+        name: "kick",
+        description: "Removes a user from the room",
+        arguments: &[
+            required("user", ArgumentKind::User),
+            optional("reason", ArgumentKind::Text),
+        ],
+        permission: Some(Permission::Moderate),
+        handler: Handler::Kick,
+    },
+    Command {
+        name: "mute",
+        description: "Stops a user from sending messages, permanently without a duration",
+        arguments: &[
+            required("user", ArgumentKind::User),
+            optional("duration", ArgumentKind::Duration),
+            optional("reason", ArgumentKind::Text),
+        ],
+        permission: Some(Permission::Moderate),
+        handler: Handler::Mute,
+    },
+    Command {
+        name: "ban",
+        description: "Bans a user, permanently without a duration",
+        arguments: &[
+            required("user", ArgumentKind::User),
+            optional("duration", ArgumentKind::Duration),
+            optional("reason", ArgumentKind::Text),
+        ],
+        permission: Some(Permission::Moderate),
+        handler: Handler::Ban,
+    },
+];
+
+/// Metadata of every command, sent to clients for autocompletion.
+pub fn command_info() -> Vec<CommandInfo> {
+    COMMANDS.iter().map(info).collect()
+}
+
+/// Runs `input`, the chat text after the leading slash, on behalf of `caller`.
+/// Mistakes are explained to the caller instead of being returned.
+pub async fn execute(chat_room: &ChatRoom, caller: &Uid, input: &str) -> Result<()> {
+    let (name, rest) = split_word(input);
+    let Some(command) = find(name) else {
+        let text = format!(
+            "Unknown command /{}. Type /help for a list of commands.",
+            name
+        );
+        return chat_room.reply(caller, text).await;
+    };
+
+    if let Some(permission) = command.permission
+        && !chat_room.has_permission(caller, permission).await
+    {
+        let text = format!("You are not allowed to use /{}.", command.name);
+        return chat_room.reply(caller, text).await;
+    }
+
+    let usernames = chat_room.get_usernames().await;
+    let arguments = match parse_arguments(command, rest, &usernames) {
+        Ok(arguments) => arguments,
+        Err(problem) => {
+            let text = format!("{}. Usage: {}", problem, info(command).usage());
+            return chat_room.reply(caller, text).await;
+        }
+    };
+
+    match run(chat_room, caller, command.handler, arguments).await {
+        Err(e) if e.is_rejection() => {
+            let text = format!("/{} failed: {}.", command.name, describe(&e));
+            chat_room.reply(caller, text).await
+        }
+        result => result,
+    }
+}
+
+async fn run(
+    chat_room: &ChatRoom,
+    caller: &Uid,
+    handler: Handler,
+    arguments: Arguments,
+) -> Result<()> {
+    match handler {
+        Handler::Help => help(chat_room, caller, arguments.text(0)).await,
+        Handler::Nick => {
+            let name = arguments.text(0).unwrap_or_default();
+            chat_room.rename(caller, name).await
+        }
+        Handler::Me => {
+            let text = arguments.text(0).unwrap_or_default();
+            let message = ChatMessage {
+                emote: true,
+                ..ChatMessage::new(caller.clone(), text)
+            };
+            chat_room.relay_message(message, caller).await
+        }
+        Handler::Who => who(chat_room, caller).await,
+        Handler::Topic => match arguments.text(0) {
+            Some(topic) => chat_room.set_topic(caller, topic).await,
+            None => {
+                let topic = chat_room.topic().await;
+                let text = if topic.is_empty() {
+                    "No topic is set.".to_string()
+                } else {
+                    format!("The topic is: {}", topic)
+                };
+                chat_room.reply(caller, text).await
+            }
+        },
+        Handler::Kick => {
+            let Some(target) = arguments.user(0) else {
+                return Ok(());
+            };
+            let reason = arguments.text(1).unwrap_or_default();
+            chat_room.kick(caller, &target, reason).await
+        }
+        Handler::Mute => {
+            let Some(target) = arguments.user(0) else {
+                return Ok(());
+            };
+            let duration = arguments.duration(1);
+            let reason = arguments.text(2).unwrap_or_default();
+            chat_room.mute(caller, &target, duration, reason).await
+        }
+        Handler::Ban => {
+            let Some(target) = arguments.user(0) else {
+                return Ok(());
+            };
+            let duration = arguments.duration(1);
+            let reason = arguments.text(2).unwrap_or_default();
+            chat_room.ban(caller, &target, duration, reason).await
+        }
+    }
+}
+
+async fn help(chat_room: &ChatRoom, caller: &Uid, name: Option<Arc<str>>) -> Result<()> {
+    if let Some(name) = name {
+        let text = match find(name.trim_start_matches('/')) {
+            Some(command) => format!("{} — {}", info(command).usage(), command.description),
+            None => format!("Unknown command /{}.", name),
+        };
+        return chat_room.reply(caller, text).await;
+    }
+
+    let mut lines = vec!["Commands:".to_string()];
+    for command in COMMANDS {
+        let allowed = match command.permission {
+            Some(permission) => chat_room.has_permission(caller, permission).await,
+            None => true,
+        };
+        if allowed {
+            lines.push(format!(
+                "{} — {}",
+                info(command).usage(),
+                command.description
+            ));
+        }
+    }
+    chat_room.reply(caller, lines.join("\n")).await
+}
+
+async fn who(chat_room: &ChatRoom, caller: &Uid) -> Result<()> {
+    let mut participants = chat_room.get_participants().await;
+    participants.sort_by_key(|participant| participant.username.to_lowercase());
+
+    let names: Vec<_> = participants
+        .iter()
+        .map(|participant| {
+            let mut details = Vec::new();
+            if participant.role != Role::Member {
+                details.push(participant.role.name());
+            }
+            match participant.presence.status {
+                PresenceStatus::Online => {}
+                PresenceStatus::Away => details.push("away"),
+                PresenceStatus::DoNotDisturb => details.push("do not disturb"),
+            }
+
+            if details.is_empty() {
+                participant.username.to_string()
+            } else {
+                format!("{} ({})", participant.username, details.join(", "))
+            }
+        })
+        .collect();
+
+    let text = match names.len() {
+        1 => format!("Only you are here: {}", names[0]),
+        count => format!("{} people are here: {}", count, names.join(", ")),
+    };
+    chat_room.reply(caller, text).await
+}
+
+fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS
+        .iter()
+        .find(|command| command.name.eq_ignore_ascii_case(name))
+}
+
+fn info(command: &Command) -> CommandInfo {
+    CommandInfo {
+        name: command.name.into(),
+        description: command.description.into(),
+        arguments: command
+            .arguments
+            .iter()
+            .map(|argument| ArgumentInfo {
+                name: argument.name.into(),
+                kind: argument.kind,
+                required: argument.required,
+            })
+            .collect(),
+        permission: command.permission,
+    }
+}
+
+enum Value {
+    Text(Arc<str>),
+    User(Uid),
+    Duration(u64),
+}
+
+/// Parsed arguments by position; `None` where an optional one was left out.
+struct Arguments(Vec<Option<Value>>);
+
+impl Arguments {
+    fn text(&self, index: usize) -> Option<Arc<str>> {
+        match self.0.get(index)? {
+            Some(Value::Text(text)) => Some(text.clone()),
+            _ => None,
+        }
+    }
+
+    fn user(&self, index: usize) -> Option<Uid> {
+        match self.0.get(index)? {
+            Some(Value::User(uuid)) => Some(uuid.clone()),
+            _ => None,
+        }
+    }
+
+    fn duration(&self, index: usize) -> Option<u64> {
+        match self.0.get(index)? {
+            Some(Value::Duration(seconds)) => Some(*seconds),
+            _ => None,
+        }
+    }
+}
+
+fn parse_arguments(
+    command: &Command,
+    mut rest: &str,
+    usernames: &[(Uid, Arc<str>)],
+) -> core::result::Result<Arguments, String> {
+    let mut values = Vec::new();
+
+    for argument in command.arguments {
+        let value = if argument.kind == ArgumentKind::Text {
+            let text = rest.trim();
+            rest = "";
+            (!text.is_empty()).then(|| Value::Text(text.into()))
+        } else {
+            let (word, remainder) = split_word(rest);
+            if word.is_empty() {
+                None
+            } else {
+                match parse_value(argument.kind, word, usernames) {
+                    Ok(value) => {
+                        rest = remainder;
+                        Some(value)
+                    }
+                    // An optional argument that does not fit is left out, so
+                    // `/ban bob spamming` has a reason but no duration.
+                    Err(_) if !argument.required => None,
+                    Err(problem) => return Err(problem),
+                }
+            }
+        };
+
+        if argument.required && value.is_none() {
+            return Err(format!("Missing {}", argument.name));
+        }
+        values.push(value);
+    }
+
+    if !rest.trim().is_empty() {
+        return Err("Too many arguments".to_string());
+    }
+
+    Ok(Arguments(values))
+}
+
+fn parse_value(
+    kind: ArgumentKind,
+    word: &str,
+    usernames: &[(Uid, Arc<str>)],
+) -> core::result::Result<Value, String> {
+    match kind {
+        ArgumentKind::Word | ArgumentKind::Text => Ok(Value::Text(word.into())),
+        ArgumentKind::User => {
+            let name = word.strip_prefix('@').unwrap_or(word);
+            usernames
+                .iter()
+                .find(|(_, username)| username.to_lowercase() == name.to_lowercase())
+                .map(|(uuid, _)| Value::User(uuid.clone()))
+                .ok_or_else(|| format!("Nobody named {} is here", name))
+        }
+        ArgumentKind::Duration => parse_duration(word)
+            .map(Value::Duration)
+            .ok_or_else(|| format!("{} is not a duration like 30s, 10m, 2h or 1d", word)),
+    }
+}
+
+/// Parses durations such as `30s`, `10m`, `2h` or `1d` into seconds.
+fn parse_duration(word: &str) -> Option<u64> {
+    let unit = word.chars().last()?;
+    let multiplier = match unit.to_ascii_lowercase() {
+        's' => 1,
+        'm' => 60,
+        'h' => 3_600,
+        'd' => 86_400,
+        _ => return None,
+    };
+    let amount: u64 = word[..word.len() - unit.len_utf8()].parse().ok()?;
+
+    amount
+        .checked_mul(multiplier)
+        .filter(|seconds| *seconds > 0)
+}
+
+/// Splits off the first whitespace-separated word.
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    text.split_once(char::is_whitespace).unwrap_or((text, ""))
+}
+
+fn describe(error: &Error) -> &'static str {
+    match error {
+        Error::MissingPermission { .. } => "you are not allowed to do that",
+        Error::CannotModerate { .. } => "their role is not below yours",
+        Error::UnknownParticipant { .. } => "they are no longer here",
+        Error::NameTaken { .. } => "that name is taken",
+        _ => "the request was rejected",
+    }
+}
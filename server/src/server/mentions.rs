@@ -0,0 +1,48 @@
+use std::sync::Arc;
+
+use common::uuid::Uid;
+
+/// Finds the participants mentioned with `@username` in `text`.
+///
+/// Usernames may contain spaces, so at every `@` the longest matching username
+/// wins. Matching is case-insensitive and a mention has to end at a word
+/// boundary.
+pub fn resolve_mentions(text: &str, participants: &[(Uid, Arc<str>)]) -> Vec<Uid> {
+    let mut mentions = Vec::new();
+
+    for (index, _) in text.match_indices('@') {
+        let preceded_by_word = text[..index]
+            .chars()
+            .next_back()
+            .is_some_and(char::is_alphanumeric);
+        if preceded_by_word {
+            continue;
+        }
+
+        let rest = &text[index + 1..];
+        let mentioned = participants
+            .iter()
+            .filter(|(_, username)| is_mention_of(rest, username))
+            .max_by_key(|(_, username)| username.len());
+
+        if let Some((uuid, _)) = mentioned
+            && !mentions.contains(uuid)
+        {
+            mentions.push(uuid.clone());
+        }
+    }
+
+    mentions
+}
+
+fn is_mention_of(rest: &str, username: &str) -> bool {
+    let Some(candidate) = rest.get(..username.len()) else {
+        return false;
+    };
+    let at_boundary = !rest[username.len()..]
+        .chars()
+        .next()
+        .is_some_and(char::is_alphanumeric);
+
+    !username.is_empty() && at_boundary && candidate.to_lowercase() == username.to_lowercase()
+}
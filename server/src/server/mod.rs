@@ -1,6 +1,15 @@
+pub mod blob_store;
 pub mod chat_room;
+pub mod commands;
+pub mod mentions;
+pub mod moderation;
 pub mod network;
 pub mod participant;
+pub mod room_store;
+pub mod search;
+pub mod transport;
+pub mod websocket;
 
 pub use chat_room::*;
 pub use network::*;
+pub use websocket::*;
@@ -0,0 +1,65 @@
+use std::{
+    net::IpAddr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// A ban on an account and the address it connected from. Someone who
+/// joined without a password can still come back under another name from
+/// another address.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Ban {
+    /// The name the participant joined with, kept when they rename.
+    pub username: Arc<str>,
+    pub ip: Option<IpAddr>,
+    /// Unix time in seconds at which the ban ends, `None` if it is permanent.
+    pub until: Option<u64>,
+    pub reason: Arc<str>,
+}
+
+/// A mute on an account and the address it connected from, like a [`Ban`].
+/// The address only applies to participants who joined without a password.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Mute {
+    pub username: Arc<str>,
+    /// Missing in mutes stored before addresses were recorded.
+    #[serde(default)]
+    pub ip: Option<IpAddr>,
+    pub until: Option<u64>,
+}
+
+/// Current Unix time in seconds.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Describes a duration in seconds for system messages, e.g. "for 2 hours".
+pub fn describe_duration(seconds: Option<u64>) -> String {
+    let Some(seconds) = seconds else {
+        return "permanently".to_string();
+    };
+
+    let (amount, unit) = match seconds {
+        seconds if seconds >= 86_400 && seconds % 86_400 == 0 => (seconds / 86_400, "day"),
+        seconds if seconds >= 3_600 && seconds % 3_600 == 0 => (seconds / 3_600, "hour"),
+        seconds if seconds >= 60 => (seconds.div_ceil(60), "minute"),
+        seconds => (seconds, "second"),
+    };
+    let plural = if amount == 1 { "" } else { "s" };
+
+    format!("for {} {}{}", amount, unit, plural)
+}
+
+/// Describes the time left until `until`, e.g. "for 5 minutes".
+pub fn describe_remaining(until: Option<u64>) -> String {
+    describe_duration(until.map(|until| until.saturating_sub(now())))
+}
+
+pub fn is_active(until: Option<u64>) -> bool {
+    until.is_none_or(|until| until > now())
+}
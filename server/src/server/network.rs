@@ -1,67 +1,164 @@
-use std::{
-    sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
-};
+use std::{net::IpAddr, sync::Arc};
 
 use bytes::Bytes;
 use common::{
-    protocol::{ChatMessage, ClientMessage, read_msg},
+    protocol::{ChatMessage, ClientMessage, Encoding, Presence, ServerMessage, encode_frame},
     uuid::Uid,
 };
 use tokio::{
-    io::AsyncWriteExt,
-    net::{
-        TcpStream,
-        tcp::{OwnedReadHalf, OwnedWriteHalf},
-    },
+    net::TcpStream,
     sync::mpsc::{self, UnboundedReceiver},
 };
 
+use tracing::{Span, field, info, warn};
+
 use crate::{
     error::{Error, Result},
-    server::{chat_room::ChatRoom, participant::Participant},
+    metrics::METRICS,
+    server::{
+        chat_room::ChatRoom,
+        commands, moderation,
+        participant::Participant,
+        room_store::Verification,
+        search::Query,
+        transport::{ReadMessage, TcpReader, WriteFrame},
+    },
 };
 
 pub async fn handle_connection(socket: TcpStream, chat_room: Arc<ChatRoom>) -> Result<()> {
-    let (mut reader, writer) = socket.into_split();
+    let ip = socket.peer_addr().ok().map(|addr| addr.ip());
+    let (reader, writer) = socket.into_split();
 
-    let (username, rx, uuid) = handle_room_join(&mut reader, &chat_room).await?;
+    serve_connection(TcpReader(reader), writer, ip, chat_room).await
+}
+
+/// Runs a client session over any transport, from the join request until
+/// the client leaves.
+pub async fn serve_connection(
+    mut reader: impl ReadMessage,
+    mut writer: impl WriteFrame,
+    ip: Option<IpAddr>,
+    chat_room: Arc<ChatRoom>,
+) -> Result<()> {
+    let (username, mut rx, uuid) =
+        handle_room_join(&mut reader, &mut writer, &chat_room, ip).await?;
 
     let result = tokio::select! {
         res = read_messages(reader, &chat_room, &uuid, username) => res,
-        res = write_messages(rx, writer) => res
+        res = write_messages(&mut rx, writer) => res
     };
 
     chat_room.leave(&uuid).await?;
+    METRICS.queued_messages.sub(rx.len() as i64);
     result
 }
 
 async fn handle_room_join(
-    reader: &mut OwnedReadHalf,
+    reader: &mut impl ReadMessage,
+    writer: &mut impl WriteFrame,
     chat_room: &ChatRoom,
+    ip: Option<IpAddr>,
 ) -> Result<(Arc<str>, UnboundedReceiver<Bytes>, Uid)> {
-    let username = match read_msg(reader).await {
-        Ok(ClientMessage::JoinRequest { username }) => username,
-        _ => return Err(Error::FailedToJoin),
+    let (username, encoding, bot, password) = match reader.read_message().await {
+        Ok(ClientMessage::JoinRequest {
+            username,
+            encoding,
+            bot,
+            password,
+        }) => (username, encoding, bot, password),
+        _ => {
+            METRICS
+                .join_failures
+                .with_label_values(&["invalid_request"])
+                .inc();
+            return Err(Error::FailedToJoin);
+        }
+    };
+
+    if let Some(ban) = chat_room.find_ban(&username, ip).await {
+        let reason = format!(
+            "You are banned {}{}",
+            moderation::describe_remaining(ban.until),
+            if ban.reason.is_empty() {
+                String::new()
+            } else {
+                format!(": {}", ban.reason)
+            }
+        );
+        reject(writer, encoding, reason, "banned").await;
+        info!(%username, "rejected banned user");
+        return Err(Error::Banned { username });
+    }
+
+    let verified = match chat_room.verify(&username, password.as_deref()).await? {
+        Verification::Verified => true,
+        Verification::Unverified => false,
+        Verification::Refused => {
+            let reason = format!("{} is registered; join with its password", username);
+            reject(writer, encoding, reason, "wrong_password").await;
+            info!(%username, "rejected wrong password");
+            return Err(Error::WrongPassword { username });
+        }
+    };
+    let Some(role) = chat_room.role_for_join(&username, verified).await else {
+        let reason = format!(
+            "{} has a role here but is not registered; ask the server admin",
+            username
+        );
+        reject(writer, encoding, reason, "reserved_name").await;
+        info!(%username, "rejected reserved name");
+        return Err(Error::ReservedName { username });
     };
 
     let (tx, rx) = mpsc::unbounded_channel::<Bytes>();
-    let participant = Participant::new(username.clone(), tx);
+    let participant = Participant {
+        role,
+        verified,
+        ip,
+        encoding,
+        bot,
+        ..Participant::new(username.clone(), tx)
+    };
     let uuid = Uid::new();
 
-    chat_room.join(&uuid, participant).await?;
+    let span = Span::current();
+    span.record("uuid", field::display(uuid.inner()));
+    span.record("username", &*username);
+
+    match chat_room.join(&uuid, participant).await {
+        Err(Error::NameTaken { username }) => {
+            let reason = format!("{} is already in the room", username);
+            reject(writer, encoding, reason, "name_taken").await;
+            info!("rejected name in use");
+            return Err(Error::NameTaken { username });
+        }
+        result => result?,
+    }
+    info!("joined");
 
     Ok((username, rx, uuid))
 }
 
+/// Tells the client why it may not join, before the connection is closed.
+async fn reject(writer: &mut impl WriteFrame, encoding: Encoding, reason: String, label: &str) {
+    let rejected = ServerMessage::JoinRejected {
+        reason: reason.into(),
+    };
+    if let Ok(frame) = encode_frame(&rejected, encoding) {
+        let _ = writer.write_frame(&frame).await;
+    }
+    METRICS.join_failures.with_label_values(&[label]).inc();
+}
+
 async fn read_messages(
-    mut reader: OwnedReadHalf,
+    mut reader: impl ReadMessage,
     chat_room: &ChatRoom,
     user_uuid: &Uid,
     username: Arc<str>,
 ) -> Result<()> {
     loop {
-        let message = read_msg::<_, ClientMessage>(&mut reader)
+        let message = reader
+            .read_message()
             .await
             .map_err(|_| Error::ConnectionClosed {
                 uuid: user_uuid.clone(),
@@ -69,18 +166,141 @@ async fn read_messages(
             })?;
 
         match message {
-            ClientMessage::Chat { text } => {
-                let message = ChatMessage {
-                    from: user_uuid.clone(),
-                    text,
-                    timestamp: SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs(),
+            ClientMessage::Chat {
+                text,
+                reply_to,
+                attachments,
+            } => {
+                let result = relay_chat(chat_room, user_uuid, text, reply_to, attachments).await;
+                report_rejected(chat_room, user_uuid, result).await?;
+            }
+            ClientMessage::Rename { username } => {
+                let username = username.trim();
+                if !username.is_empty() {
+                    match chat_room.rename(user_uuid, username.into()).await {
+                        Err(Error::NameTaken { username }) => {
+                            let text = format!("The name {} is taken", username);
+                            chat_room.reply(user_uuid, text).await?;
+                        }
+                        result => result?,
+                    }
+                }
+            }
+            ClientMessage::Typing { active } => {
+                chat_room.set_typing(user_uuid, active).await?;
+            }
+            ClientMessage::EditMessage { id, text } => {
+                let result = chat_room.edit_message(user_uuid, &id, text).await;
+                report_rejected(chat_room, user_uuid, result).await?;
+            }
+            ClientMessage::DeleteMessage { id } => {
+                let result = chat_room.delete_message(user_uuid, &id).await;
+                report_rejected(chat_room, user_uuid, result).await?;
+            }
+            ClientMessage::AddReaction { message_id, emoji } => {
+                let result = chat_room.add_reaction(user_uuid, &message_id, emoji).await;
+                report_rejected(chat_room, user_uuid, result).await?;
+            }
+            ClientMessage::RemoveReaction { message_id, emoji } => {
+                let result = chat_room
+                    .remove_reaction(user_uuid, &message_id, emoji)
+                    .await;
+                report_rejected(chat_room, user_uuid, result).await?;
+            }
+            ClientMessage::MarkRead { seq } => {
+                chat_room.mark_read(user_uuid, seq).await?;
+            }
+            ClientMessage::SetPresence { status, text } => {
+                let presence = Presence { status, text };
+                chat_room.set_presence(user_uuid, presence).await?;
+            }
+            ClientMessage::BeginUpload {
+                upload_id,
+                name,
+                mime,
+                size,
+            } => {
+                chat_room
+                    .begin_upload(user_uuid, upload_id, &name, mime, size)
+                    .await?;
+            }
+            ClientMessage::UploadChunk {
+                upload_id,
+                offset,
+                data,
+            } => {
+                chat_room
+                    .upload_chunk(user_uuid, upload_id, offset, &data)
+                    .await?;
+            }
+            ClientMessage::FinishUpload { upload_id, sha256 } => {
+                chat_room
+                    .finish_upload(user_uuid, upload_id, &sha256)
+                    .await?;
+            }
+            ClientMessage::Download { blob_id } => {
+                chat_room.download(user_uuid, blob_id).await?;
+            }
+            ClientMessage::Kick { target, reason } => {
+                let result = chat_room.kick(user_uuid, &target, reason).await;
+                report_rejected(chat_room, user_uuid, result).await?;
+            }
+            ClientMessage::Ban {
+                target,
+                duration,
+                reason,
+            } => {
+                let result = chat_room.ban(user_uuid, &target, duration, reason).await;
+                report_rejected(chat_room, user_uuid, result).await?;
+            }
+            ClientMessage::Mute {
+                target,
+                duration,
+                reason,
+            } => {
+                let result = chat_room.mute(user_uuid, &target, duration, reason).await;
+                report_rejected(chat_room, user_uuid, result).await?;
+            }
+            ClientMessage::SetRole { target, role } => {
+                let result = chat_room.set_role(user_uuid, &target, role).await;
+                report_rejected(chat_room, user_uuid, result).await?;
+            }
+            ClientMessage::SetTopic { topic } => {
+                let result = chat_room.set_topic(user_uuid, topic).await;
+                report_rejected(chat_room, user_uuid, result).await?;
+            }
+            ClientMessage::SetDescription { description } => {
+                let result = chat_room.set_description(user_uuid, description).await;
+                report_rejected(chat_room, user_uuid, result).await?;
+            }
+            ClientMessage::Search {
+                query,
+                room,
+                from,
+                before,
+                after,
+                limit,
+            } => {
+                let query = Query {
+                    text: &query,
+                    room: room.as_deref(),
+                    from: from.as_deref(),
+                    registered: false,
+                    before,
+                    after,
+                    limit: limit as usize,
                 };
-                chat_room.relay_message(message, user_uuid).await?;
+                chat_room.search(user_uuid, query).await?;
             }
-            ClientMessage::JoinRequest { username: _ } => {
+            ClientMessage::PinMessage { id } => {
+                let result = chat_room.pin_message(user_uuid, &id).await;
+                report_rejected(chat_room, user_uuid, result).await?;
+            }
+            ClientMessage::UnpinMessage { id } => {
+                let result = chat_room.unpin_message(user_uuid, &id).await;
+                report_rejected(chat_room, user_uuid, result).await?;
+            }
+            ClientMessage::JoinRequest { .. } => {
                 return Err(Error::AlreadyJoined {
                     uuid: user_uuid.clone(),
                     username,
@@ -90,14 +310,56 @@ async fn read_messages(
     }
 }
 
+async fn relay_chat(
+    chat_room: &ChatRoom,
+    user_uuid: &Uid,
+    text: Arc<str>,
+    reply_to: Option<Uid>,
+    attachments: Vec<Uid>,
+) -> Result<()> {
+    if chat_room.notify_if_too_long(user_uuid, &text).await? {
+        return Ok(());
+    }
+
+    // A leading "//" sends a message that starts with a slash.
+    let text = match text.strip_prefix('/') {
+        Some(input) if !input.starts_with('/') && attachments.is_empty() => {
+            return commands::execute(chat_room, user_uuid, input).await;
+        }
+        Some(rest) if rest.starts_with('/') => rest.into(),
+        _ => text,
+    };
+
+    let message = ChatMessage {
+        reply_to,
+        attachments: chat_room.resolve_attachments(&attachments).await?,
+        ..ChatMessage::new(user_uuid.clone(), text)
+    };
+    chat_room.relay_message(message, user_uuid).await
+}
+
+/// Rejected requests are reported to the client instead of closing the
+/// connection over a single invalid request.
+async fn report_rejected(chat_room: &ChatRoom, user_uuid: &Uid, result: Result<()>) -> Result<()> {
+    match result {
+        Err(e) if e.is_rejection() => {
+            warn!(error = %e, "rejected request");
+            chat_room.notify_rejected(user_uuid, &e).await
+        }
+        result => result,
+    }
+}
+
 async fn write_messages(
-    mut rx: UnboundedReceiver<Bytes>,
-    mut writer: OwnedWriteHalf,
+    rx: &mut UnboundedReceiver<Bytes>,
+    mut writer: impl WriteFrame,
 ) -> Result<()> {
     while let Some(message) = rx.recv().await {
-        if writer.write_all(&message).await.is_err() {
+        METRICS.queued_messages.dec();
+        if writer.write_frame(&message).await.is_err() {
             break;
         }
+        METRICS.bytes_sent.inc_by(message.len() as u64);
     }
 
     Ok(())
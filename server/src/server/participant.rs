@@ -1,19 +1,49 @@
-use std::sync::Arc;
+use std::{net::IpAddr, sync::Arc};
 
 use bytes::Bytes;
+use common::protocol::{Encoding, Presence, Role};
 use tokio::sync::mpsc;
 
+use crate::server::room_store::same_name;
+
 #[derive(Clone)]
 pub struct Participant {
     pub username: Arc<str>,
+    /// The name the participant joined with. Roles, bans, mutes, read markers
+    /// and upload quotas stay with it when the participant renames.
+    pub account: Arc<str>,
+    /// Joined with the password registered for `account`.
+    pub verified: bool,
+    pub presence: Presence,
+    pub role: Role,
+    /// Address the participant connected from, used for bans.
+    pub ip: Option<IpAddr>,
+    /// Encoding of the messages sent to the participant.
+    pub encoding: Encoding,
+    /// Joined as a bot account.
+    pub bot: bool,
     pub tx: mpsc::UnboundedSender<Bytes>,
 }
 
 impl Participant {
     pub fn new(username: impl Into<Arc<str>>, tx: mpsc::UnboundedSender<Bytes>) -> Self {
+        let username = username.into();
         Participant {
-            username: username.into(),
+            account: username.clone(),
+            verified: false,
+            username,
+            presence: Presence::default(),
+            role: Role::default(),
+            ip: None,
+            encoding: Encoding::default(),
+            bot: false,
             tx,
         }
     }
+
+    /// Whether `name` is taken by this participant, as their current name or
+    /// the one they joined with.
+    pub fn holds_name(&self, name: &str) -> bool {
+        same_name(&self.username, name) || same_name(&self.account, name)
+    }
 }
@@ -0,0 +1,333 @@
+use std::{collections::HashMap, net::IpAddr, path::PathBuf, sync::Arc};
+
+use common::{
+    protocol::{Permission, Role},
+    uuid::Uid,
+};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::{
+    fs,
+    io::{self, ErrorKind},
+    sync::Mutex,
+    task,
+};
+
+use crate::{
+    http::constant_time_eq,
+    server::moderation::{Ban, Mute, is_active},
+};
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct State {
+    topic: Arc<str>,
+    description: Arc<str>,
+    /// Roles assigned to usernames; everyone else gets `default_role`.
+    roles: HashMap<Arc<str>, Role>,
+    default_role: Role,
+    /// Permission sets replacing a role's defaults in this room.
+    permissions: HashMap<Role, Vec<Permission>>,
+    bans: Vec<Ban>,
+    mutes: Vec<Mute>,
+    /// Passwords of registered usernames.
+    passwords: HashMap<Arc<str>, Password>,
+}
+
+/// PBKDF2 rounds for new passwords unless configured otherwise, as
+/// recommended by OWASP for HMAC-SHA256. Stored passwords keep the count
+/// they were hashed with.
+pub const PASSWORD_ITERATIONS: u32 = 600_000;
+
+/// A password hashed with PBKDF2-HMAC-SHA256. The salt and hash are hex
+/// encoded.
+#[derive(Serialize, Deserialize, Clone)]
+struct Password {
+    salt: String,
+    iterations: u32,
+    hash: String,
+}
+
+impl Password {
+    async fn new(password: &str, iterations: u32) -> io::Result<Self> {
+        let salt = hex::encode(Uid::new().inner().as_bytes());
+        let hash = hash_password(&salt, password, iterations).await?;
+        Ok(Self {
+            salt,
+            iterations,
+            hash,
+        })
+    }
+
+    async fn matches(&self, password: &str) -> io::Result<bool> {
+        let hash = hash_password(&self.salt, password, self.iterations).await?;
+        Ok(constant_time_eq(hash.as_bytes(), self.hash.as_bytes()))
+    }
+}
+
+/// The outcome of checking a joining user's password.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    /// The password matched, or registered the name.
+    Verified,
+    /// The name has no password and none was registered: either none was
+    /// given, or the name holds a role, which joining cannot claim.
+    Unverified,
+    /// The name is registered and the password is missing or wrong.
+    Refused,
+}
+
+/// Settings of a room that outlive the server process: the topic and
+/// description, roles, permissions, bans and mutes, persisted as JSON.
+///
+/// Entries are keyed by the username a participant joined with, compared
+/// case-insensitively. A name becomes an account once someone joins with a
+/// password for it; from then on only that password lets anyone join under
+/// it.
+pub struct RoomStore {
+    path: PathBuf,
+    state: Mutex<State>,
+    password_iterations: u32,
+}
+
+impl RoomStore {
+    /// Loads the store from `path`, starting empty if the file does not exist.
+    pub async fn load(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let state = match fs::read(&path).await {
+            Ok(data) => serde_json::from_slice(&data).map_err(io::Error::other)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => State::default(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            path,
+            state: Mutex::new(state),
+            password_iterations: PASSWORD_ITERATIONS,
+        })
+    }
+
+    /// Sets the PBKDF2 rounds for passwords registered from now on.
+    pub fn with_password_iterations(mut self, iterations: u32) -> Self {
+        self.password_iterations = iterations;
+        self
+    }
+
+    pub async fn topic(&self) -> Arc<str> {
+        self.state.lock().await.topic.clone()
+    }
+
+    pub async fn description(&self) -> Arc<str> {
+        self.state.lock().await.description.clone()
+    }
+
+    pub async fn set_topic(&self, topic: Arc<str>) -> io::Result<()> {
+        let mut state = self.state.lock().await;
+        state.topic = topic;
+        self.save(&state).await
+    }
+
+    pub async fn set_description(&self, description: Arc<str>) -> io::Result<()> {
+        let mut state = self.state.lock().await;
+        state.description = description;
+        self.save(&state).await
+    }
+
+    /// Checks `password` against the one registered for `username`,
+    /// registering it if the name has none yet. Hashing is slow on purpose,
+    /// so it happens without holding the store.
+    pub async fn verify(&self, username: &str, password: Option<&str>) -> io::Result<Verification> {
+        let (registered, holds_role) = {
+            let state = self.state.lock().await;
+            let holds_role = role_of(&state, username) > state.default_role;
+            (registered_password(&state, username), holds_role)
+        };
+
+        match (registered, password) {
+            (Some(registered), Some(password)) => Ok(if registered.matches(password).await? {
+                Verification::Verified
+            } else {
+                Verification::Refused
+            }),
+            (Some(_), None) => Ok(Verification::Refused),
+            // Otherwise the first to join under a name given a role, before
+            // its owner registered it, would take the role.
+            (None, Some(_)) if holds_role => Ok(Verification::Unverified),
+            (None, Some(password)) => {
+                let password = Password::new(password, self.password_iterations).await?;
+                let mut state = self.state.lock().await;
+                // Someone else registered the name while the password was
+                // being hashed.
+                if registered_password(&state, username).is_some() {
+                    return Ok(Verification::Refused);
+                }
+                state.passwords.insert(username.into(), password);
+                self.save(&state).await?;
+                Ok(Verification::Verified)
+            }
+            (None, None) => Ok(Verification::Unverified),
+        }
+    }
+
+    pub async fn is_registered(&self, username: &str) -> bool {
+        registered_password(&*self.state.lock().await, username).is_some()
+    }
+
+    /// Whether `username` is kept for its owner: it has a password, or a
+    /// role above the default.
+    pub async fn is_reserved(&self, username: &str) -> bool {
+        let state = self.state.lock().await;
+        state.passwords.keys().any(|name| same_name(name, username))
+            || role_of(&state, username) > state.default_role
+    }
+
+    /// Returns the role of a joining user, or `None` if the name holds a role
+    /// above the default and the join is not `verified`. Joining never
+    /// raises a role; owners are named through the admin API.
+    pub async fn role_for_join(&self, username: &str, verified: bool) -> Option<Role> {
+        let state = self.state.lock().await;
+        let role = role_of(&state, username);
+        (verified || role <= state.default_role).then_some(role)
+    }
+
+    pub async fn default_role(&self) -> Role {
+        self.state.lock().await.default_role
+    }
+
+    pub async fn set_role(&self, username: &str, role: Role) -> io::Result<()> {
+        let mut state = self.state.lock().await;
+        state.roles.retain(|name, _| !same_name(name, username));
+        if role != state.default_role {
+            state.roles.insert(username.into(), role);
+        }
+        self.save(&state).await
+    }
+
+    pub async fn has_permission(&self, role: Role, permission: Permission) -> bool {
+        let state = self.state.lock().await;
+        permissions_of(&state, role).contains(&permission)
+    }
+
+    /// The permission set of every role, in order of privilege.
+    pub async fn permissions(&self) -> Vec<(Role, Vec<Permission>)> {
+        let state = self.state.lock().await;
+        Role::ALL
+            .into_iter()
+            .map(|role| (role, permissions_of(&state, role)))
+            .collect()
+    }
+
+    /// Returns the active ban matching either `username` or `ip`.
+    pub async fn find_ban(&self, username: &str, ip: Option<IpAddr>) -> Option<Ban> {
+        let mut state = self.state.lock().await;
+        state.bans.retain(|ban| is_active(ban.until));
+        state
+            .bans
+            .iter()
+            .find(|ban| same_name(&ban.username, username) || (ip.is_some() && ban.ip == ip))
+            .cloned()
+    }
+
+    pub async fn ban(&self, ban: Ban) -> io::Result<()> {
+        let mut state = self.state.lock().await;
+        state.bans.retain(|other| is_active(other.until));
+        state.bans.push(ban);
+        self.save(&state).await
+    }
+
+    pub async fn mute(&self, mute: Mute) -> io::Result<()> {
+        let mut state = self.state.lock().await;
+        state
+            .mutes
+            .retain(|other| is_active(other.until) && !same_name(&other.username, &mute.username));
+        state.mutes.push(mute);
+        self.save(&state).await
+    }
+
+    /// Returns when the mute on `account` or `ip` ends, if there is one.
+    pub async fn muted_until(&self, account: &str, ip: Option<IpAddr>) -> Option<Option<u64>> {
+        let mut state = self.state.lock().await;
+        state.mutes.retain(|mute| is_active(mute.until));
+        state
+            .mutes
+            .iter()
+            .find(|mute| same_name(&mute.username, account) || (ip.is_some() && mute.ip == ip))
+            .map(|mute| mute.until)
+    }
+
+    /// Writes to a temporary file first so a crash never leaves a truncated
+    /// store behind.
+    async fn save(&self, state: &State) -> io::Result<()> {
+        let data = serde_json::to_vec_pretty(state).map_err(io::Error::other)?;
+        let temporary = self.path.with_extension("tmp");
+        fs::write(&temporary, data).await?;
+        fs::rename(&temporary, &self.path).await
+    }
+}
+
+fn role_of(state: &State, username: &str) -> Role {
+    state
+        .roles
+        .iter()
+        .find(|(name, _)| same_name(name, username))
+        .map_or(state.default_role, |(_, role)| *role)
+}
+
+fn permissions_of(state: &State, role: Role) -> Vec<Permission> {
+    state
+        .permissions
+        .get(&role)
+        .cloned()
+        .unwrap_or_else(|| role.default_permissions())
+}
+
+fn registered_password(state: &State, username: &str) -> Option<Password> {
+    state
+        .passwords
+        .iter()
+        .find(|(name, _)| same_name(name, username))
+        .map(|(_, registered)| registered.clone())
+}
+
+/// Runs PBKDF2-HMAC-SHA256 on a blocking thread, returning the hex encoded
+/// hash.
+async fn hash_password(salt: &str, password: &str, iterations: u32) -> io::Result<String> {
+    let salt = salt.to_string();
+    let password = password.to_string();
+    let hash = task::spawn_blocking(move || {
+        pbkdf2_sha256(password.as_bytes(), salt.as_bytes(), iterations)
+    })
+    .await
+    .map_err(io::Error::other)?;
+    Ok(hex::encode(hash))
+}
+
+/// PBKDF2 (RFC 8018) with HMAC-SHA256, producing a single 32 byte block.
+fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mac = Hmac::<Sha256>::new_from_slice(password).expect("HMAC takes keys of any length");
+    let mut block: [u8; 32] = mac
+        .clone()
+        .chain_update(salt)
+        .chain_update(1u32.to_be_bytes())
+        .finalize()
+        .into_bytes()
+        .into();
+    let mut hash = block;
+    for _ in 1..iterations {
+        block = mac
+            .clone()
+            .chain_update(block)
+            .finalize()
+            .into_bytes()
+            .into();
+        hash.iter_mut()
+            .zip(block)
+            .for_each(|(hash, byte)| *hash ^= byte);
+    }
+    hash
+}
+
+pub fn same_name(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
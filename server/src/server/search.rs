@@ -0,0 +1,166 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use common::{
+    protocol::{ChatMessage, SearchResult},
+    uuid::Uid,
+};
+
+/// Filters of a search; every given one must match.
+pub struct Query<'a> {
+    pub text: &'a str,
+    /// Name of the room, which has to be the one searched.
+    pub room: Option<&'a str>,
+    /// Account of the author, compared case-insensitively. Messages sent
+    /// without a verified account match by the name they were sent under,
+    /// unless that name is registered.
+    pub from: Option<&'a str>,
+    /// Whether `from` is registered, set by the room.
+    pub registered: bool,
+    /// Only messages sent before this Unix time in seconds.
+    pub before: Option<u64>,
+    /// Only messages sent at or after this Unix time in seconds.
+    pub after: Option<u64>,
+    pub limit: usize,
+}
+
+struct Entry {
+    id: Uid,
+    author: Arc<str>,
+    account: Option<Arc<str>>,
+    timestamp: u64,
+    text: Arc<str>,
+    words: BTreeSet<String>,
+}
+
+/// An inverted index over the room's history, keyed by sequence number.
+///
+/// Authors are recorded by username and verified account when a message is
+/// indexed, since participants get a new ID every time they connect.
+#[derive(Default)]
+pub struct SearchIndex {
+    postings: BTreeMap<String, BTreeSet<u64>>,
+    entries: BTreeMap<u64, Entry>,
+}
+
+impl SearchIndex {
+    pub fn insert(&mut self, message: &ChatMessage, author: Arc<str>) {
+        let words = message_words(message);
+        for word in &words {
+            self.postings
+                .entry(word.clone())
+                .or_default()
+                .insert(message.seq);
+        }
+
+        let entry = Entry {
+            id: message.id.clone(),
+            author,
+            account: message.author.clone(),
+            timestamp: message.timestamp,
+            text: message.text.clone(),
+            words,
+        };
+        self.entries.insert(message.seq, entry);
+    }
+
+    /// Re-indexes an edited message.
+    pub fn update(&mut self, message: &ChatMessage) {
+        if let Some(author) = self.remove(message.seq) {
+            self.insert(message, author);
+        }
+    }
+
+    /// Drops a message from the index, returning its author.
+    pub fn remove(&mut self, seq: u64) -> Option<Arc<str>> {
+        let entry = self.entries.remove(&seq)?;
+        for word in &entry.words {
+            if let Some(postings) = self.postings.get_mut(word) {
+                postings.remove(&seq);
+                if postings.is_empty() {
+                    self.postings.remove(word);
+                }
+            }
+        }
+        Some(entry.author)
+    }
+
+    /// Returns the newest matching messages first. Every word of the query
+    /// has to appear in the message, either in full or as the start of a word.
+    pub fn search(&self, query: &Query) -> Vec<SearchResult> {
+        let mut candidates: Option<BTreeSet<u64>> = None;
+
+        for word in &words(query.text) {
+            let matches: BTreeSet<u64> = self
+                .postings
+                .range(word.clone()..)
+                .take_while(|(indexed, _)| indexed.starts_with(word.as_str()))
+                .flat_map(|(_, postings)| postings.iter().copied())
+                .collect();
+
+            candidates = Some(match candidates {
+                Some(candidates) => candidates.intersection(&matches).copied().collect(),
+                None => matches,
+            });
+        }
+
+        let from = query.from.map(str::to_lowercase);
+        let matches_author = |entry: &Entry| match (&from, &entry.account) {
+            (None, _) => true,
+            (Some(from), Some(account)) => account.to_lowercase() == *from,
+            // Anyone may use a name that is not registered, but only its
+            // owner a registered one.
+            (Some(from), None) => !query.registered && entry.author.to_lowercase() == *from,
+        };
+        let matches_filters = |entry: &Entry| {
+            matches_author(entry)
+                && query.before.is_none_or(|before| entry.timestamp < before)
+                && query.after.is_none_or(|after| entry.timestamp >= after)
+        };
+        let to_result = |(seq, entry): (&u64, &Entry)| SearchResult {
+            id: entry.id.clone(),
+            seq: *seq,
+            author: entry.author.clone(),
+            timestamp: entry.timestamp,
+            text: entry.text.clone(),
+        };
+
+        match candidates {
+            Some(candidates) => candidates
+                .iter()
+                .rev()
+                .filter_map(|seq| self.entries.get_key_value(seq))
+                .filter(|(_, entry)| matches_filters(entry))
+                .take(query.limit)
+                .map(to_result)
+                .collect(),
+            // Without words, the filters alone decide.
+            None => self
+                .entries
+                .iter()
+                .rev()
+                .filter(|(_, entry)| matches_filters(entry))
+                .take(query.limit)
+                .map(to_result)
+                .collect(),
+        }
+    }
+}
+
+/// Words of the text and attachment names of `message`.
+fn message_words(message: &ChatMessage) -> BTreeSet<String> {
+    let mut indexed = words(&message.text);
+    for attachment in &message.attachments {
+        indexed.extend(words(&attachment.name));
+    }
+    indexed
+}
+
+fn words(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
@@ -0,0 +1,57 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use common::protocol::{ClientMessage, read_msg};
+use tokio::{
+    io::{self, AsyncRead, AsyncWriteExt, ReadBuf},
+    net::tcp::{OwnedReadHalf, OwnedWriteHalf},
+};
+
+use crate::metrics::METRICS;
+
+/// The receiving half of a client connection.
+pub trait ReadMessage: Send {
+    fn read_message(&mut self) -> impl Future<Output = io::Result<ClientMessage>> + Send;
+}
+
+/// The sending half of a client connection. Frames are length-prefixed, as
+/// produced by `encode_frame`.
+pub trait WriteFrame: Send {
+    fn write_frame(&mut self, frame: &Bytes) -> impl Future<Output = io::Result<()>> + Send;
+}
+
+/// Reads length-prefixed frames from a TCP connection, counting the bytes
+/// read.
+pub struct TcpReader(pub OwnedReadHalf);
+
+impl ReadMessage for TcpReader {
+    async fn read_message(&mut self) -> io::Result<ClientMessage> {
+        read_msg(&mut Metered(&mut self.0)).await
+    }
+}
+
+impl WriteFrame for OwnedWriteHalf {
+    async fn write_frame(&mut self, frame: &Bytes) -> io::Result<()> {
+        self.write_all(frame).await
+    }
+}
+
+struct Metered<R>(R);
+
+impl<R: AsyncRead + Unpin> AsyncRead for Metered<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.0).poll_read(cx, buf);
+        METRICS
+            .bytes_received
+            .inc_by((buf.filled().len() - filled) as u64);
+        poll
+    }
+}
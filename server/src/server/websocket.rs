@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use common::protocol::{ClientMessage, Encoding, decode_message};
+use futures_util::{
+    SinkExt, StreamExt,
+    stream::{SplitSink, SplitStream},
+};
+use tokio::{
+    io::{self, ErrorKind},
+    net::TcpStream,
+};
+use tokio_tungstenite::{
+    WebSocketStream,
+    tungstenite::{Message, Utf8Bytes},
+};
+
+use crate::{
+    error::{Error, Result},
+    metrics::METRICS,
+    server::{
+        ChatRoom,
+        network::serve_connection,
+        transport::{ReadMessage, WriteFrame},
+    },
+};
+
+/// Length of the prefix `encode_frame` puts in front of every frame. A
+/// WebSocket frame carries its own length, so the prefix is left out.
+const LENGTH_PREFIX: usize = 4;
+
+/// Accepts a WebSocket handshake on `socket` and runs the same session as a
+/// TCP client. Each frame carries one message: bincode in binary frames and
+/// JSON in text frames.
+pub async fn handle_websocket(socket: TcpStream, chat_room: Arc<ChatRoom>) -> Result<()> {
+    let ip = socket.peer_addr().ok().map(|addr| addr.ip());
+    let stream = tokio_tungstenite::accept_async(socket)
+        .await
+        .map_err(|_| Error::FailedToJoin)?;
+    let (sink, stream) = stream.split();
+
+    serve_connection(
+        WebSocketReader(stream),
+        WebSocketWriter(sink),
+        ip,
+        chat_room,
+    )
+    .await
+}
+
+struct WebSocketReader(SplitStream<WebSocketStream<TcpStream>>);
+
+impl ReadMessage for WebSocketReader {
+    async fn read_message(&mut self) -> io::Result<ClientMessage> {
+        loop {
+            let message = self
+                .0
+                .next()
+                .await
+                .ok_or(ErrorKind::UnexpectedEof)?
+                .map_err(io::Error::other)?;
+
+            match message {
+                Message::Binary(data) => {
+                    METRICS.bytes_received.inc_by(data.len() as u64);
+                    return decode_message(&data);
+                }
+                Message::Text(text) => {
+                    METRICS.bytes_received.inc_by(text.len() as u64);
+                    return decode_message(text.as_bytes());
+                }
+                Message::Close(_) => return Err(ErrorKind::UnexpectedEof.into()),
+                // Pings are answered by tungstenite itself.
+                Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => {}
+            }
+        }
+    }
+}
+
+struct WebSocketWriter(SplitSink<WebSocketStream<TcpStream>, Message>);
+
+impl WriteFrame for WebSocketWriter {
+    async fn write_frame(&mut self, frame: &Bytes) -> io::Result<()> {
+        let payload = frame.slice(LENGTH_PREFIX..);
+        let message = match Encoding::detect(&payload) {
+            Encoding::Bincode => Message::Binary(payload),
+            Encoding::Json => Message::Text(
+                Utf8Bytes::try_from(payload)
+                    .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?,
+            ),
+        };
+        self.0.send(message).await.map_err(io::Error::other)
+    }
+}
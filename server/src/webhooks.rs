@@ -0,0 +1,268 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use common::{protocol::ChatMessage, uuid::Uid};
+use hmac::{Hmac, Mac};
+use reqwest::{Client, header};
+use serde::Serialize;
+use sha2::Sha256;
+use tokio::{
+    sync::mpsc::{self, Receiver, error::TrySendError},
+    time,
+};
+use tracing::{error, warn};
+
+use crate::config::{WebhookConfig, WebhookEvent};
+
+/// Deliveries waiting per webhook. Events are dropped while the queue is full.
+const QUEUE_CAPACITY: usize = 1000;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `sha256=` followed by the hex-encoded HMAC-SHA256 of the body.
+pub const SIGNATURE_HEADER: &str = "x-chat-signature";
+
+pub const EVENT_HEADER: &str = "x-chat-event";
+
+/// Unique per event and unchanged across retries, so receivers can drop
+/// duplicates.
+pub const DELIVERY_HEADER: &str = "x-chat-delivery";
+
+/// Posts room events to the configured webhooks as JSON. Every webhook has
+/// its own queue and delivery task, so a slow or failing endpoint holds up
+/// neither the room nor the other webhooks.
+#[derive(Default)]
+pub struct Webhooks {
+    hooks: Vec<Hook>,
+}
+
+struct Hook {
+    events: Vec<WebhookEvent>,
+    /// Lowercased.
+    keywords: Vec<String>,
+    tx: mpsc::Sender<Delivery>,
+}
+
+struct Delivery {
+    id: Uid,
+    event: WebhookEvent,
+    body: Bytes,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+enum Payload<'a> {
+    Chat {
+        room: &'a str,
+        message: MessagePayload<'a>,
+    },
+    UserJoined {
+        room: &'a str,
+        uuid: &'a Uid,
+        username: &'a str,
+    },
+    Mention {
+        room: &'a str,
+        keyword: &'a str,
+        message: MessagePayload<'a>,
+    },
+}
+
+#[derive(Serialize)]
+struct MessagePayload<'a> {
+    id: &'a Uid,
+    seq: u64,
+    author: &'a str,
+    text: &'a str,
+    timestamp: u64,
+}
+
+impl<'a> MessagePayload<'a> {
+    fn new(message: &'a ChatMessage, author: &'a str) -> Self {
+        Self {
+            id: &message.id,
+            seq: message.seq,
+            author,
+            text: &message.text,
+            timestamp: message.timestamp,
+        }
+    }
+}
+
+impl Webhooks {
+    /// Starts a delivery task for each webhook. Must be called from within
+    /// the Tokio runtime.
+    pub fn new(configs: Vec<WebhookConfig>) -> Self {
+        if configs.is_empty() {
+            return Self::default();
+        }
+
+        let client = match Client::builder().timeout(REQUEST_TIMEOUT).build() {
+            Ok(client) => client,
+            Err(e) => {
+                error!(error = %e, "failed to create the webhook client");
+                return Self::default();
+            }
+        };
+
+        let hooks = configs
+            .into_iter()
+            .map(|config| {
+                let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+                let hook = Hook {
+                    events: config.events.clone(),
+                    keywords: config
+                        .keywords
+                        .iter()
+                        .map(|keyword| keyword.to_lowercase())
+                        .collect(),
+                    tx,
+                };
+                tokio::spawn(deliver(client.clone(), config, rx));
+                hook
+            })
+            .collect();
+
+        Self { hooks }
+    }
+
+    pub fn chat_message(&self, room: &str, message: &ChatMessage, author: &str) {
+        for hook in &self.hooks {
+            if hook.events.contains(&WebhookEvent::Chat) {
+                let payload = Payload::Chat {
+                    room,
+                    message: MessagePayload::new(message, author),
+                };
+                hook.enqueue(WebhookEvent::Chat, &payload);
+            }
+
+            if hook.events.contains(&WebhookEvent::Mention)
+                && let Some(keyword) = hook.find_keyword(&message.text)
+            {
+                let payload = Payload::Mention {
+                    room,
+                    keyword,
+                    message: MessagePayload::new(message, author),
+                };
+                hook.enqueue(WebhookEvent::Mention, &payload);
+            }
+        }
+    }
+
+    pub fn user_joined(&self, room: &str, uuid: &Uid, username: &str) {
+        for hook in &self.hooks {
+            if hook.events.contains(&WebhookEvent::UserJoined) {
+                let payload = Payload::UserJoined {
+                    room,
+                    uuid,
+                    username,
+                };
+                hook.enqueue(WebhookEvent::UserJoined, &payload);
+            }
+        }
+    }
+}
+
+impl Hook {
+    fn find_keyword(&self, text: &str) -> Option<&str> {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .find_map(|word| {
+                let word = word.to_lowercase();
+                self.keywords
+                    .iter()
+                    .find(|keyword| **keyword == word)
+                    .map(String::as_str)
+            })
+    }
+
+    fn enqueue(&self, event: WebhookEvent, payload: &Payload) {
+        let body = match serde_json::to_vec(payload) {
+            Ok(body) => body.into(),
+            Err(e) => {
+                error!(error = %e, event = event.name(), "failed to encode a webhook payload");
+                return;
+            }
+        };
+        let delivery = Delivery {
+            id: Uid::new(),
+            event,
+            body,
+        };
+
+        match self.tx.try_send(delivery) {
+            Ok(()) => {}
+            Err(TrySendError::Full(delivery)) => {
+                warn!(
+                    event = delivery.event.name(),
+                    "webhook queue is full, dropping event"
+                );
+            }
+            // The delivery task only stops with the runtime.
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+}
+
+/// Posts queued deliveries in order, retrying each with exponential backoff
+/// before giving up on it.
+async fn deliver(client: Client, config: WebhookConfig, mut rx: Receiver<Delivery>) {
+    let max_attempts = config.max_attempts.max(1);
+
+    while let Some(delivery) = rx.recv().await {
+        let mut delay = Duration::from_millis(config.retry_delay_ms);
+
+        for attempt in 1..=max_attempts {
+            match post(&client, &config, &delivery).await {
+                Ok(()) => break,
+                Err(e) if attempt < max_attempts => {
+                    warn!(
+                        url = %config.url,
+                        event = delivery.event.name(),
+                        attempt,
+                        error = %e,
+                        "webhook delivery failed, retrying"
+                    );
+                    time::sleep(delay).await;
+                    delay = delay.saturating_mul(2);
+                }
+                Err(e) => {
+                    error!(
+                        url = %config.url,
+                        event = delivery.event.name(),
+                        attempt,
+                        error = %e,
+                        "webhook delivery failed, giving up"
+                    );
+                }
+            }
+        }
+    }
+}
+
+async fn post(client: &Client, config: &WebhookConfig, delivery: &Delivery) -> reqwest::Result<()> {
+    let mut request = client
+        .post(&config.url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, delivery.event.name())
+        .header(DELIVERY_HEADER, delivery.id.inner().to_string());
+    if let Some(secret) = &config.secret {
+        request = request.header(SIGNATURE_HEADER, sign(secret.as_bytes(), &delivery.body));
+    }
+
+    request
+        .body(delivery.body.clone())
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+/// The value of the signature header for `body`.
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(body);
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
@@ -0,0 +1,122 @@
+//! Stores files in a temporary directory and opens the store again, as a
+//! restarted server would.
+
+use std::{path::PathBuf, sync::Arc};
+
+use common::uuid::Uid;
+use server::{error::Error, server::blob_store::BlobStore};
+use sha2::{Digest, Sha256};
+
+const DATA: &[u8] = b"hello, blobs";
+
+fn temporary_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("blob-store-test-{}", Uid::new().inner()))
+}
+
+/// Uploads `DATA` as `owner`, returning the new blob's ID.
+async fn upload(blobs: &BlobStore, owner: &str) -> Uid {
+    let uploader = Uid::new();
+    let upload_id = Uid::new();
+    blobs
+        .begin(
+            &uploader,
+            Arc::from(owner),
+            upload_id.clone(),
+            "notes.txt",
+            "text/plain".into(),
+            DATA.len() as u64,
+        )
+        .await
+        .unwrap();
+    blobs
+        .write_chunk(&uploader, &upload_id, 0, DATA)
+        .await
+        .unwrap();
+    let sha256: [u8; 32] = Sha256::digest(DATA).into();
+    let attachment = blobs.finish(&uploader, &upload_id, &sha256).await.unwrap();
+    attachment.blob_id
+}
+
+#[tokio::test]
+async fn blobs_survive_a_restart() {
+    let dir = temporary_dir();
+    let blobs = BlobStore::load(&dir).await.unwrap();
+    let blob_id = upload(&blobs, "alice").await;
+    drop(blobs);
+
+    let blobs = BlobStore::load(&dir).await.unwrap();
+    let attachment = blobs.get(&blob_id).await.unwrap();
+    assert_eq!(&*attachment.name, "notes.txt");
+    assert_eq!(blobs.read(&blob_id).await.unwrap(), DATA);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn missing_files_and_unfinished_uploads_are_dropped() {
+    let dir = temporary_dir();
+    let blobs = BlobStore::load(&dir).await.unwrap();
+    let blob_id = upload(&blobs, "alice").await;
+    blobs
+        .begin(
+            &Uid::new(),
+            "bob".into(),
+            Uid::new(),
+            "draft.txt",
+            "text/plain".into(),
+            1,
+        )
+        .await
+        .unwrap();
+    drop(blobs);
+
+    std::fs::remove_file(dir.join(blob_id.inner().to_string())).unwrap();
+    let blobs = BlobStore::load(&dir).await.unwrap();
+    assert!(matches!(
+        blobs.get(&blob_id).await,
+        Err(Error::UnknownBlob { .. })
+    ));
+    let partial = std::fs::read_dir(&dir).unwrap().any(|entry| {
+        entry
+            .unwrap()
+            .path()
+            .extension()
+            .is_some_and(|e| e == "part")
+    });
+    assert!(!partial);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Starts a one-byte upload as `owner` and leaves it unfinished.
+async fn begin_draft(blobs: &BlobStore, owner: &str) -> server::error::Result<()> {
+    blobs
+        .begin(
+            &Uid::new(),
+            Arc::from(owner),
+            Uid::new(),
+            "draft.txt",
+            "text/plain".into(),
+            1,
+        )
+        .await
+}
+
+#[tokio::test]
+async fn pending_uploads_are_limited() {
+    let dir = temporary_dir();
+    let blobs = BlobStore::load(&dir).await.unwrap();
+
+    let mut started = 0;
+    let error = loop {
+        match begin_draft(&blobs, "alice").await {
+            Ok(()) => started += 1,
+            Err(e) => break e,
+        }
+        assert!(started < 1000, "uploads are not limited");
+    };
+    assert!(matches!(error, Error::TooManyUploads { .. }));
+    begin_draft(&blobs, "bob").await.unwrap();
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,250 @@
+//! Delivers webhooks to a local stand-in HTTP server and checks what
+//! arrives.
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+};
+use common::{protocol::ChatMessage, uuid::Uid};
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use server::{
+    config::{WebhookConfig, WebhookEvent},
+    webhooks::{DELIVERY_HEADER, EVENT_HEADER, SIGNATURE_HEADER, Webhooks},
+};
+use sha2::Sha256;
+use tokio::{
+    net::TcpListener,
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    time,
+};
+
+const SECRET: &str = "webhook secret";
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Request {
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).expect("body is JSON")
+    }
+}
+
+#[derive(Clone)]
+struct StandIn {
+    requests: UnboundedSender<Request>,
+    /// Requests answered with an error before the stand-in starts accepting.
+    failures: Arc<AtomicUsize>,
+}
+
+/// Starts a stand-in receiver that fails the first `failures` requests.
+async fn stand_in(failures: usize) -> (SocketAddr, UnboundedReceiver<Request>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let state = StandIn {
+        requests: tx,
+        failures: Arc::new(AtomicUsize::new(failures)),
+    };
+    let app = Router::new()
+        .route("/hook", post(receive))
+        .with_state(state);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    (address, rx)
+}
+
+async fn receive(State(state): State<StandIn>, headers: HeaderMap, body: Bytes) -> StatusCode {
+    let _ = state.requests.send(Request { headers, body });
+
+    let failing = state
+        .failures
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |failures| {
+            failures.checked_sub(1)
+        })
+        .is_ok();
+    if failing {
+        StatusCode::INTERNAL_SERVER_ERROR
+    } else {
+        StatusCode::NO_CONTENT
+    }
+}
+
+fn config(address: SocketAddr, events: Vec<WebhookEvent>) -> WebhookConfig {
+    WebhookConfig {
+        url: format!("http://{}/hook", address),
+        secret: Some(SECRET.to_string()),
+        events,
+        keywords: vec!["Deploy".to_string()],
+        max_attempts: 3,
+        retry_delay_ms: 10,
+    }
+}
+
+async fn next(requests: &mut UnboundedReceiver<Request>) -> Request {
+    time::timeout(TIMEOUT, requests.recv())
+        .await
+        .expect("webhook arrives in time")
+        .expect("stand-in is running")
+}
+
+fn assert_signed(request: &Request) {
+    let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+    mac.update(&request.body);
+    let expected = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+
+    assert_eq!(request.header(SIGNATURE_HEADER), Some(expected.as_str()));
+}
+
+#[tokio::test]
+async fn delivers_signed_chat_and_mention_events() {
+    let (address, mut requests) = stand_in(0).await;
+    let webhooks = Webhooks::new(vec![config(
+        address,
+        vec![WebhookEvent::Chat, WebhookEvent::Mention],
+    )]);
+
+    let message = ChatMessage {
+        seq: 7,
+        ..ChatMessage::new(Uid::new(), "Ready to DEPLOY, anyone?".into())
+    };
+    webhooks.chat_message("general", &message, "alice");
+
+    let chat = next(&mut requests).await;
+    assert_signed(&chat);
+    assert_eq!(chat.header(EVENT_HEADER), Some("chat"));
+    assert_eq!(chat.header("content-type"), Some("application/json"));
+    let body = chat.json();
+    assert_eq!(body["event"], "chat");
+    assert_eq!(body["room"], "general");
+    assert_eq!(body["message"]["author"], "alice");
+    assert_eq!(body["message"]["seq"], 7);
+    assert_eq!(body["message"]["text"], "Ready to DEPLOY, anyone?");
+    assert_eq!(body["message"]["id"], message.id.inner().to_string());
+
+    let mention = next(&mut requests).await;
+    assert_signed(&mention);
+    assert_eq!(mention.header(EVENT_HEADER), Some("mention"));
+    let body = mention.json();
+    assert_eq!(body["event"], "mention");
+    assert_eq!(body["keyword"], "deploy");
+    assert_eq!(body["message"]["author"], "alice");
+}
+
+#[tokio::test]
+async fn only_subscribed_events_are_delivered() {
+    let (address, mut requests) = stand_in(0).await;
+    let webhooks = Webhooks::new(vec![config(address, vec![WebhookEvent::UserJoined])]);
+
+    let message = ChatMessage::new(Uid::new(), "time to deploy".into());
+    webhooks.chat_message("general", &message, "alice");
+    let uuid = Uid::new();
+    webhooks.user_joined("general", &uuid, "bob");
+
+    // Deliveries keep their order, so a chat event would have arrived first.
+    let joined = next(&mut requests).await;
+    assert_eq!(joined.header(EVENT_HEADER), Some("user-joined"));
+    let body = joined.json();
+    assert_eq!(body["event"], "user-joined");
+    assert_eq!(body["username"], "bob");
+    assert_eq!(body["uuid"], uuid.inner().to_string());
+}
+
+#[tokio::test]
+async fn keywords_match_whole_words() {
+    let (address, mut requests) = stand_in(0).await;
+    let webhooks = Webhooks::new(vec![config(address, vec![WebhookEvent::Mention])]);
+
+    webhooks.chat_message(
+        "general",
+        &ChatMessage::new(Uid::new(), "redeployment is done".into()),
+        "alice",
+    );
+    webhooks.chat_message(
+        "general",
+        &ChatMessage::new(Uid::new(), "deploy!".into()),
+        "alice",
+    );
+
+    let mention = next(&mut requests).await;
+    assert_eq!(mention.json()["message"]["text"], "deploy!");
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried() {
+    let (address, mut requests) = stand_in(2).await;
+    let webhooks = Webhooks::new(vec![config(address, vec![WebhookEvent::UserJoined])]);
+
+    webhooks.user_joined("general", &Uid::new(), "bob");
+
+    let attempts = [
+        next(&mut requests).await,
+        next(&mut requests).await,
+        next(&mut requests).await,
+    ];
+    let delivery = attempts[0].header(DELIVERY_HEADER).unwrap();
+    for attempt in &attempts {
+        assert_eq!(attempt.header(DELIVERY_HEADER), Some(delivery));
+        assert_eq!(attempt.body, attempts[0].body);
+        assert_signed(attempt);
+    }
+
+    time::sleep(Duration::from_millis(200)).await;
+    assert!(requests.try_recv().is_err(), "delivered after succeeding");
+}
+
+#[tokio::test]
+async fn deliveries_are_dropped_after_the_last_attempt() {
+    let (address, mut requests) = stand_in(usize::MAX).await;
+    let webhooks = Webhooks::new(vec![WebhookConfig {
+        max_attempts: 2,
+        ..config(address, vec![WebhookEvent::UserJoined])
+    }]);
+
+    webhooks.user_joined("general", &Uid::new(), "bob");
+    webhooks.user_joined("general", &Uid::new(), "carol");
+
+    let usernames = [
+        next(&mut requests).await,
+        next(&mut requests).await,
+        next(&mut requests).await,
+        next(&mut requests).await,
+    ]
+    .map(|request| request.json()["username"].as_str().unwrap().to_string());
+    assert_eq!(usernames, ["bob", "bob", "carol", "carol"]);
+}
+
+#[tokio::test]
+async fn requests_are_unsigned_without_a_secret() {
+    let (address, mut requests) = stand_in(0).await;
+    let webhooks = Webhooks::new(vec![WebhookConfig {
+        secret: None,
+        ..config(address, vec![WebhookEvent::UserJoined])
+    }]);
+
+    webhooks.user_joined("general", &Uid::new(), "bob");
+
+    let request = next(&mut requests).await;
+    assert_eq!(request.header(SIGNATURE_HEADER), None);
+}
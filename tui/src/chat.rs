@@ -0,0 +1,303 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chat_client::{
+    ChatClient, Event,
+    protocol::{ChatMessage, ParticipantInfo, PresenceStatus, Role, ServerMessage},
+    uuid::Uid,
+};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::input::Input;
+
+/// Lines moved by Page Up and Page Down.
+const PAGE: usize = 10;
+
+/// How often typing is re-announced while the user keeps typing.
+const TYPING_REFRESH: Duration = Duration::from_secs(2);
+
+pub struct User {
+    pub uuid: Uid,
+    pub username: Arc<str>,
+    pub role: Role,
+    pub status: PresenceStatus,
+}
+
+pub struct Message {
+    pub id: Uid,
+    pub timestamp: u64,
+    pub author: Arc<str>,
+    pub text: Arc<str>,
+    pub bot: bool,
+    pub emote: bool,
+    pub edited: bool,
+    pub deleted: bool,
+    pub mentions_me: bool,
+    pub is_author: bool,
+}
+
+pub enum Entry {
+    Message(Message),
+    System(String),
+}
+
+pub enum ChatAction {
+    Quit,
+}
+
+/// The joined room as shown in the terminal.
+pub struct Chat {
+    client: ChatClient,
+    /// The server, shown in the title.
+    pub address: String,
+    pub topic: Arc<str>,
+    pub entries: Vec<Entry>,
+    /// Sorted by username.
+    pub users: Vec<User>,
+    pub typing: Vec<Uid>,
+    pub input: Input,
+    /// When typing was last announced, while the user is typing.
+    typing_sent_at: Option<Instant>,
+    /// Lines scrolled up from the newest message.
+    pub scroll: usize,
+    /// Set when the connection ended for good.
+    pub disconnected: bool,
+}
+
+impl Chat {
+    pub fn new(client: ChatClient, address: String) -> Self {
+        Self {
+            client,
+            address,
+            topic: "".into(),
+            entries: Vec::new(),
+            users: Vec::new(),
+            typing: Vec::new(),
+            input: Input::default(),
+            typing_sent_at: None,
+            scroll: 0,
+            disconnected: false,
+        }
+    }
+
+    pub fn own_uuid(&self) -> Uid {
+        self.client.uuid()
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<ChatAction> {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Some(ChatAction::Quit),
+            KeyCode::Char('c') if control => return Some(ChatAction::Quit),
+            KeyCode::PageUp => self.scroll += PAGE,
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(PAGE),
+            KeyCode::Enter => self.send(),
+            _ => {
+                if self.input.handle_key(key) {
+                    self.set_typing(!self.input.text().is_empty());
+                }
+            }
+        }
+
+        None
+    }
+
+    fn set_typing(&mut self, active: bool) {
+        let should_send = match (active, self.typing_sent_at) {
+            (true, Some(sent_at)) => sent_at.elapsed() >= TYPING_REFRESH,
+            (true, None) => true,
+            (false, sent_at) => sent_at.is_some(),
+        };
+
+        if should_send && !self.disconnected {
+            self.typing_sent_at = active.then(Instant::now);
+            let _ = self.client.set_typing(active);
+        }
+    }
+
+    fn send(&mut self) {
+        if self.input.text().trim().is_empty() {
+            return;
+        }
+        if self.disconnected {
+            self.push_system("Not connected".to_string());
+            return;
+        }
+
+        let text = self.input.submit();
+        self.set_typing(false);
+        if self.client.send_message(text.trim()).is_err() {
+            self.push_system("Not connected".to_string());
+        }
+        self.scroll = 0;
+    }
+
+    pub fn apply(&mut self, event: Event) {
+        match event {
+            Event::Server(message) => self.apply_server_message(message),
+            Event::Reconnecting { attempt } => self.push_system(format!(
+                "Lost the connection to the server, reconnecting (attempt {})",
+                attempt
+            )),
+            Event::Disconnected { reason } => {
+                self.disconnected = true;
+                self.push_system(
+                    reason.map_or("Lost the connection to the server".to_string(), |reason| {
+                        reason.to_string()
+                    }),
+                );
+            }
+        }
+    }
+
+    fn apply_server_message(&mut self, message: ServerMessage) {
+        match message {
+            ServerMessage::JoinAccepted {
+                history,
+                participants,
+                room,
+                ..
+            } => {
+                // After a reconnect the room is described from scratch.
+                self.users = participants.iter().map(to_user).collect();
+                self.sort_users();
+                self.topic = room.topic;
+                self.typing.clear();
+                self.entries.clear();
+                for message in history {
+                    self.push_message(&message);
+                }
+            }
+            ServerMessage::Chat(message) => self.push_message(&message),
+            ServerMessage::UserJoined {
+                uuid,
+                username,
+                role,
+            } => {
+                self.push_system(format!("{} joined the chat", username));
+                self.users.push(User {
+                    uuid,
+                    username,
+                    role,
+                    status: PresenceStatus::default(),
+                });
+                self.sort_users();
+            }
+            ServerMessage::UserLeft { uuid } => {
+                if let Some(index) = self.users.iter().position(|user| user.uuid == uuid) {
+                    let user = self.users.remove(index);
+                    self.push_system(format!("{} left the chat", user.username));
+                }
+            }
+            ServerMessage::UserRenamed { uuid, username } => {
+                if let Some(user) = self.users.iter_mut().find(|user| user.uuid == uuid) {
+                    let text = format!("{} is now known as {}", user.username, username);
+                    user.username = username;
+                    self.push_system(text);
+                    self.sort_users();
+                }
+            }
+            ServerMessage::RoleChanged { uuid, role } => {
+                if let Some(user) = self.users.iter_mut().find(|user| user.uuid == uuid) {
+                    user.role = role;
+                }
+            }
+            ServerMessage::PresenceChanged { uuid, presence } => {
+                if let Some(user) = self.users.iter_mut().find(|user| user.uuid == uuid) {
+                    user.status = presence.status;
+                }
+            }
+            ServerMessage::TypingState { typing } => self.typing = typing,
+            ServerMessage::MessageEdited { id, text, .. } => {
+                if let Some(message) = self.find_message(&id) {
+                    message.text = text;
+                    message.edited = true;
+                }
+            }
+            ServerMessage::MessageDeleted { id } => {
+                if let Some(message) = self.find_message(&id) {
+                    message.deleted = true;
+                }
+            }
+            ServerMessage::RoomInfoChanged { room } => self.topic = room.topic,
+            ServerMessage::System { text } | ServerMessage::CommandReply { text } => {
+                self.push_system(text.to_string());
+            }
+            ServerMessage::UploadFailed { reason, .. } => {
+                self.push_system(format!("Upload failed: {}", reason));
+            }
+            // Not shown in the terminal.
+            ServerMessage::ReactionsUpdated { .. }
+            | ServerMessage::ReadReceipts { .. }
+            | ServerMessage::SearchResults { .. }
+            | ServerMessage::UploadComplete { .. }
+            | ServerMessage::DownloadChunk { .. }
+            | ServerMessage::DownloadFailed { .. } => {}
+            // Handled by the client library.
+            ServerMessage::JoinRejected { .. } | ServerMessage::Disconnected { .. } => {}
+        }
+    }
+
+    fn push_message(&mut self, message: &ChatMessage) {
+        let own_uuid = self.own_uuid();
+        let author = match &message.bot {
+            Some(bot) => bot.clone(),
+            None => self
+                .users
+                .iter()
+                .find(|user| user.uuid == message.from)
+                .map_or("Unknown".into(), |user| user.username.clone()),
+        };
+        let is_author = self.client.is_own(message);
+
+        self.entries.push(Entry::Message(Message {
+            id: message.id.clone(),
+            timestamp: message.timestamp,
+            author,
+            text: message.text.clone(),
+            bot: message.bot.is_some(),
+            emote: message.emote,
+            edited: message.edited,
+            deleted: message.deleted,
+            mentions_me: !is_author && message.mentions.contains(&own_uuid),
+            is_author,
+        }));
+    }
+
+    fn push_system(&mut self, text: String) {
+        self.entries.push(Entry::System(text));
+    }
+
+    fn find_message(&mut self, id: &Uid) -> Option<&mut Message> {
+        self.entries.iter_mut().rev().find_map(|entry| match entry {
+            Entry::Message(message) if message.id == *id => Some(message),
+            _ => None,
+        })
+    }
+
+    fn sort_users(&mut self) {
+        self.users.sort_by_key(|user| user.username.to_lowercase());
+    }
+
+    /// Usernames of the others who are typing.
+    pub fn typing_names(&self) -> Vec<Arc<str>> {
+        let own_uuid = self.own_uuid();
+        self.typing
+            .iter()
+            .filter(|uuid| **uuid != own_uuid)
+            .filter_map(|uuid| self.users.iter().find(|user| user.uuid == *uuid))
+            .map(|user| user.username.clone())
+            .collect()
+    }
+}
+
+fn to_user(participant: &ParticipantInfo) -> User {
+    User {
+        uuid: participant.uuid.clone(),
+        username: participant.username.clone(),
+        role: participant.role,
+        status: participant.presence.status,
+    }
+}
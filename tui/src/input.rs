@@ -0,0 +1,121 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Most entries kept in the input history.
+const MAX_HISTORY: usize = 100;
+
+/// A single-line text field. With history enabled, Up and Down recall
+/// earlier submissions.
+#[derive(Default)]
+pub struct Input {
+    text: String,
+    /// Cursor position, in characters.
+    cursor: usize,
+    history: Vec<String>,
+    /// Index into `history` while browsing it.
+    browsing: Option<usize>,
+    /// The text being typed before browsing started.
+    draft: String,
+}
+
+impl Input {
+    pub fn with_text(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            cursor: text.chars().count(),
+            ..Self::default()
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Applies an editing key. Returns whether the key was used.
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                let at = self.byte_index();
+                self.text.insert(at, c);
+                self.cursor += 1;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                let at = self.byte_index();
+                self.text.remove(at);
+            }
+            KeyCode::Delete if self.cursor < self.text.chars().count() => {
+                let at = self.byte_index();
+                self.text.remove(at);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.text.chars().count()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.text.chars().count(),
+            KeyCode::Up => self.recall_older(),
+            KeyCode::Down => self.recall_newer(),
+            _ => return false,
+        }
+
+        true
+    }
+
+    /// Takes the text, recording it in the history.
+    pub fn submit(&mut self) -> String {
+        let text = std::mem::take(&mut self.text);
+        self.cursor = 0;
+        self.browsing = None;
+        self.draft.clear();
+
+        if !text.trim().is_empty() && self.history.last() != Some(&text) {
+            if self.history.len() == MAX_HISTORY {
+                self.history.remove(0);
+            }
+            self.history.push(text.clone());
+        }
+
+        text
+    }
+
+    fn recall_older(&mut self) {
+        let index = match self.browsing {
+            Some(0) => return,
+            Some(index) => index - 1,
+            None if self.history.is_empty() => return,
+            None => {
+                self.draft = self.text.clone();
+                self.history.len() - 1
+            }
+        };
+        self.browsing = Some(index);
+        self.set_text(self.history[index].clone());
+    }
+
+    fn recall_newer(&mut self) {
+        let Some(index) = self.browsing else { return };
+
+        if index + 1 < self.history.len() {
+            self.browsing = Some(index + 1);
+            self.set_text(self.history[index + 1].clone());
+        } else {
+            self.browsing = None;
+            let draft = std::mem::take(&mut self.draft);
+            self.set_text(draft);
+        }
+    }
+
+    fn set_text(&mut self, text: String) {
+        self.cursor = text.chars().count();
+        self.text = text;
+    }
+
+    fn byte_index(&self) -> usize {
+        self.text
+            .char_indices()
+            .nth(self.cursor)
+            .map_or(self.text.len(), |(index, _)| index)
+    }
+}
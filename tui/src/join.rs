@@ -0,0 +1,84 @@
+use crossterm::event::{KeyCode, KeyEvent};
+
+use crate::input::Input;
+
+const DEFAULT_ADDRESS: &str = "localhost:8080";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Address,
+    Username,
+    Password,
+}
+
+pub enum FormAction {
+    Submit,
+    Quit,
+}
+
+/// The form asking where to connect and under which name.
+pub struct JoinForm {
+    pub address: Input,
+    pub username: Input,
+    /// Optional; empty to join without one.
+    pub password: Input,
+    pub focus: Field,
+    pub error: Option<String>,
+    pub connecting: bool,
+}
+
+impl JoinForm {
+    pub fn new(address: Option<String>, username: Option<String>) -> Self {
+        let focus = if address.is_some() {
+            Field::Username
+        } else {
+            Field::Address
+        };
+
+        Self {
+            address: Input::with_text(address.as_deref().unwrap_or(DEFAULT_ADDRESS)),
+            username: Input::with_text(username.as_deref().unwrap_or_default()),
+            password: Input::default(),
+            focus,
+            error: None,
+            connecting: false,
+        }
+    }
+
+    /// Whether both fields are filled in.
+    pub fn can_submit(&self) -> bool {
+        !self.address.text().trim().is_empty() && !self.username.text().trim().is_empty()
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<FormAction> {
+        match key.code {
+            KeyCode::Esc => return Some(FormAction::Quit),
+            KeyCode::Enter if self.focus == Field::Address => self.focus = Field::Username,
+            KeyCode::Enter if self.can_submit() => return Some(FormAction::Submit),
+            KeyCode::Tab | KeyCode::Down => {
+                self.focus = match self.focus {
+                    Field::Address => Field::Username,
+                    Field::Username => Field::Password,
+                    Field::Password => Field::Address,
+                };
+            }
+            KeyCode::BackTab | KeyCode::Up => {
+                self.focus = match self.focus {
+                    Field::Address => Field::Password,
+                    Field::Username => Field::Address,
+                    Field::Password => Field::Username,
+                };
+            }
+            _ => {
+                let input = match self.focus {
+                    Field::Address => &mut self.address,
+                    Field::Username => &mut self.username,
+                    Field::Password => &mut self.password,
+                };
+                input.handle_key(key);
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,125 @@
+use std::io;
+
+use chat_client::{ChatClient, Events, JoinOptions};
+use crossterm::event::{Event as TerminalEvent, EventStream, KeyEvent, KeyEventKind};
+use futures_util::StreamExt;
+use ratatui::DefaultTerminal;
+
+use crate::{
+    chat::{Chat, ChatAction},
+    join::{FormAction, JoinForm},
+};
+
+mod chat;
+mod input;
+mod join;
+mod ui;
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let address = args.next();
+    let username = args.next();
+
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, address, username).await;
+    ratatui::restore();
+    result
+}
+
+async fn run(
+    terminal: &mut DefaultTerminal,
+    address: Option<String>,
+    username: Option<String>,
+) -> io::Result<()> {
+    let mut keys = EventStream::new();
+
+    let Some((client, events, address)) = join(terminal, &mut keys, address, username).await?
+    else {
+        return Ok(());
+    };
+
+    chat(terminal, &mut keys, Chat::new(client, address), events).await
+}
+
+/// Shows the join form until the room is joined, or returns `None` if the
+/// user quits.
+async fn join(
+    terminal: &mut DefaultTerminal,
+    keys: &mut EventStream,
+    address: Option<String>,
+    username: Option<String>,
+) -> io::Result<Option<(ChatClient, Events, String)>> {
+    let mut form = JoinForm::new(address, username);
+
+    loop {
+        terminal.draw(|frame| ui::draw_join(frame, &form))?;
+
+        let Some(key) = next_key(keys).await? else {
+            continue;
+        };
+        match form.handle_key(key) {
+            Some(FormAction::Quit) => return Ok(None),
+            Some(FormAction::Submit) => {}
+            None => continue,
+        }
+
+        form.connecting = true;
+        form.error = None;
+        terminal.draw(|frame| ui::draw_join(frame, &form))?;
+
+        let address = form.address.text().trim().to_string();
+        let options = JoinOptions {
+            password: (!form.password.text().is_empty()).then(|| form.password.text().into()),
+            ..JoinOptions::new(form.username.text().trim())
+        };
+        form.connecting = false;
+
+        match ChatClient::connect_with(address.as_str(), options).await {
+            Ok((client, events)) => return Ok(Some((client, events, address))),
+            Err(chat_client::Error::Io(e)) => {
+                form.error = Some(format!("Could not connect: {}", e));
+            }
+            Err(chat_client::Error::JoinRejected { reason }) => {
+                form.error = Some(reason.to_string());
+            }
+            Err(_) => form.error = Some("Unexpected reply from the server".to_string()),
+        }
+    }
+}
+
+async fn chat(
+    terminal: &mut DefaultTerminal,
+    keys: &mut EventStream,
+    mut chat: Chat,
+    mut events: Events,
+) -> io::Result<()> {
+    loop {
+        terminal.draw(|frame| ui::draw_chat(frame, &mut chat))?;
+
+        tokio::select! {
+            key = next_key(keys) => {
+                if let Some(key) = key?
+                    && let Some(ChatAction::Quit) = chat.handle_key(key)
+                {
+                    return Ok(());
+                }
+            }
+            event = events.next(), if !chat.disconnected => match event {
+                Some(event) => chat.apply(event),
+                None => chat.disconnected = true,
+            },
+        }
+    }
+}
+
+/// Waits for the next terminal event. Returns the key if it was a key press;
+/// other events, such as resizes, only call for a redraw.
+async fn next_key(keys: &mut EventStream) -> io::Result<Option<KeyEvent>> {
+    match keys.next().await {
+        Some(Ok(TerminalEvent::Key(key))) if key.kind == KeyEventKind::Press => Ok(Some(key)),
+        Some(Ok(_)) => Ok(None),
+        Some(Err(e)) => Err(e),
+        None => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+}
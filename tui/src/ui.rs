@@ -0,0 +1,237 @@
+use chat_client::protocol::{PresenceStatus, Role};
+use ratatui::{
+    Frame,
+    layout::{Constraint, Flex, Layout, Position, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, Paragraph, Wrap},
+};
+
+use crate::{
+    chat::{Chat, Entry, Message, User},
+    input::Input,
+    join::{Field, JoinForm},
+};
+
+/// Width of the online users pane, borders included.
+const USERS_WIDTH: u16 = 24;
+
+pub fn draw_join(frame: &mut Frame, form: &JoinForm) {
+    let [area] = Layout::horizontal([Constraint::Length(50)])
+        .flex(Flex::Center)
+        .areas(frame.area());
+    let [area] = Layout::vertical([Constraint::Length(12)])
+        .flex(Flex::Center)
+        .areas(area);
+
+    let block = Block::bordered().title(" Join a chat room ");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let [address, username, password, status] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Length(3),
+        Constraint::Length(3),
+        Constraint::Length(1),
+    ])
+    .areas(inner);
+
+    let fields = [
+        (&form.address, " Server address ", address, Field::Address),
+        (&form.username, " Username ", username, Field::Username),
+        (
+            &form.password,
+            " Password (optional) ",
+            password,
+            Field::Password,
+        ),
+    ];
+    for (input, title, area, field) in fields {
+        let focused = form.focus == field;
+        draw_input(frame, input, title, area, focused, field == Field::Password);
+    }
+
+    let status_line = if form.connecting {
+        Line::from("Connecting...").dim()
+    } else if let Some(error) = &form.error {
+        Line::from(error.as_str()).red()
+    } else {
+        Line::from("Enter to join, Esc to quit").dim()
+    };
+    frame.render_widget(Paragraph::new(status_line), status);
+}
+
+pub fn draw_chat(frame: &mut Frame, chat: &mut Chat) {
+    let [main, status, input] = Layout::vertical([
+        Constraint::Min(3),
+        Constraint::Length(1),
+        Constraint::Length(3),
+    ])
+    .areas(frame.area());
+    let [messages, users] =
+        Layout::horizontal([Constraint::Min(20), Constraint::Length(USERS_WIDTH)]).areas(main);
+
+    draw_messages(frame, chat, messages);
+    draw_users(frame, &chat.users, users);
+
+    let status_line = if chat.disconnected {
+        Line::from("Disconnected. Esc to quit").red()
+    } else {
+        match chat.typing_names().as_slice() {
+            [] if chat.scroll > 0 => Line::from("Scrolled up, Page Down to return").dim(),
+            [] => Line::default(),
+            [name] => Line::from(format!("{} is typing...", name)).dim(),
+            [first, second] => Line::from(format!("{} and {} are typing...", first, second)).dim(),
+            _ => Line::from("Several people are typing...").dim(),
+        }
+    };
+    frame.render_widget(Paragraph::new(status_line), status);
+
+    draw_input(frame, &chat.input, " Message ", input, true, false);
+}
+
+fn draw_messages(frame: &mut Frame, chat: &mut Chat, area: Rect) {
+    let title = if chat.topic.is_empty() {
+        format!(" {} ", chat.address)
+    } else {
+        format!(" {} - {} ", chat.address, chat.topic)
+    };
+    let block = Block::bordered().title(title);
+    let inner = block.inner(area);
+
+    let lines: Vec<Line> = chat.entries.iter().map(entry_line).collect();
+    let paragraph = Paragraph::new(lines).wrap(Wrap { trim: false });
+
+    // Scrolling is counted from the bottom, so new messages stay in view.
+    let max_scroll = paragraph
+        .line_count(inner.width)
+        .saturating_sub(inner.height as usize);
+    chat.scroll = chat.scroll.min(max_scroll);
+    let offset = (max_scroll - chat.scroll).min(u16::MAX as usize) as u16;
+
+    frame.render_widget(paragraph.block(block).scroll((offset, 0)), area);
+}
+
+fn entry_line(entry: &Entry) -> Line<'_> {
+    match entry {
+        Entry::Message(message) => message_line(message),
+        Entry::System(text) => Line::from(format!("-- {}", text)).dim().italic(),
+    }
+}
+
+fn message_line(message: &Message) -> Line<'_> {
+    let Message {
+        timestamp,
+        author,
+        text,
+        bot,
+        emote,
+        edited,
+        deleted,
+        mentions_me,
+        is_author,
+        ..
+    } = message;
+
+    let mut author_style = Style::new().add_modifier(Modifier::BOLD);
+    if *is_author {
+        author_style = author_style.fg(Color::Cyan);
+    }
+
+    let mut spans = vec![Span::from(format!("{} ", format_time(*timestamp))).dim()];
+    if *bot {
+        spans.push(Span::from("[BOT] ").magenta());
+    }
+
+    if *deleted {
+        spans.push(Span::styled(author.as_ref(), author_style));
+        spans.push(Span::from(": message deleted").dim().italic());
+        return Line::from(spans);
+    }
+
+    if *emote {
+        spans.push(Span::from("* ").italic());
+        spans.push(Span::styled(author.as_ref(), author_style.italic()));
+        spans.push(Span::from(format!(" {}", text)).italic());
+    } else {
+        spans.push(Span::styled(author.as_ref(), author_style));
+        spans.push(Span::from(": "));
+        spans.push(Span::from(text.as_ref()));
+    }
+    if *edited {
+        spans.push(Span::from(" (edited)").dim());
+    }
+
+    let line = Line::from(spans);
+    if *mentions_me { line.yellow() } else { line }
+}
+
+fn draw_users(frame: &mut Frame, users: &[User], area: Rect) {
+    let items: Vec<ListItem> = users
+        .iter()
+        .map(|user| {
+            let status = match user.status {
+                PresenceStatus::Online => Span::from("● ").green(),
+                PresenceStatus::Away => Span::from("● ").yellow(),
+                PresenceStatus::DoNotDisturb => Span::from("● ").red(),
+            };
+            let role = match user.role {
+                Role::Owner => "~",
+                Role::Moderator => "@",
+                Role::Member => "",
+                Role::Guest => "?",
+                Role::ReadOnly => "-",
+            };
+            ListItem::new(Line::from(vec![
+                status,
+                Span::from(role).bold(),
+                Span::from(user.username.as_ref()),
+            ]))
+        })
+        .collect();
+
+    let title = format!(" Online ({}) ", users.len());
+    frame.render_widget(List::new(items).block(Block::bordered().title(title)), area);
+}
+
+/// Draws a bordered text field, placing the cursor in it when focused. Long
+/// text scrolls sideways to keep the cursor visible, and `masked` text is
+/// shown as dots.
+fn draw_input(
+    frame: &mut Frame,
+    input: &Input,
+    title: &str,
+    area: Rect,
+    focused: bool,
+    masked: bool,
+) {
+    let block = Block::bordered().title(title);
+    let block = if focused {
+        block.border_style(Style::new().fg(Color::Cyan))
+    } else {
+        block
+    };
+
+    let width = area.width.saturating_sub(2) as usize;
+    let start = input.cursor().saturating_sub(width.saturating_sub(1));
+    let offset = start.min(u16::MAX as usize) as u16;
+    let text = if masked {
+        "•".repeat(input.text().chars().count())
+    } else {
+        input.text().to_string()
+    };
+    frame.render_widget(Paragraph::new(text).block(block).scroll((0, offset)), area);
+
+    if focused {
+        frame.set_cursor_position(Position::new(
+            area.x + 1 + (input.cursor() - start) as u16,
+            area.y + 1,
+        ));
+    }
+}
+
+/// `HH:MM` in UTC.
+fn format_time(timestamp: u64) -> String {
+    let seconds = timestamp % 86_400;
+    format!("{:02}:{:02}", seconds / 3600, seconds % 3600 / 60)
+}